@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A command line split into positional arguments, options that take a value and
+/// switches.  Options can be given as `--name value` or `--name=value`.
+pub struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    switches: Vec<String>,
+}
+
+impl Args {
+    /// Fails on anything starting with `--` that isn't in `options` or `switches`
+    pub fn parse(args: impl IntoIterator<Item = String>, options: &[&str], switches: &[&str]) -> Result<Self, String> {
+        let mut parsed = Args { positional: vec![], options: HashMap::new(), switches: vec![] };
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                parsed.positional.push(arg);
+                continue;
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            if switches.contains(&name.as_str()) && inline.is_none() {
+                parsed.switches.push(name);
+            } else if options.contains(&name.as_str()) {
+                let value = match inline {
+                    Some(value) => value,
+                    None => args.next().ok_or_else(|| format!("{} needs a value", name))?,
+                };
+                parsed.options.insert(name, value);
+            } else {
+                return Err(format!("unknown option {}", name));
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The `i`th positional argument, which has to be there
+    pub fn positional(&self, i: usize, what: &str) -> Result<&str, String> {
+        self.positional.get(i).map(|x| x.as_str()).ok_or_else(|| format!("missing {}", what))
+    }
+
+    pub fn expect_positional(&self, count: usize) -> Result<(), String> {
+        match self.positional.get(count) {
+            Some(extra) => Err(format!("unexpected argument {}", extra)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|x| x.as_str())
+    }
+
+    /// An option's value parsed as a `T`, numbers can be given in hex with `0x`
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        let Some(text) = self.option(name) else { return Ok(None) };
+        let parsed = match text.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok().and_then(|x| x.to_string().parse().ok()),
+            None => text.parse().ok(),
+        };
+        parsed.map(Some).ok_or_else(|| format!("bad value for {}: {}", name, text))
+    }
+
+    pub fn switch(&self, name: &str) -> bool {
+        self.switches.iter().any(|x| x == name)
+    }
+}
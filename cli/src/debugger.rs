@@ -0,0 +1,331 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use simulator::Simulator;
+use simulator::assembler::disassemble;
+use simulator::processor::debug::{Access, BreakStage, Breakpoint, Comparison, Condition, Watchpoint};
+use simulator::processor::registers::Register;
+use simulator::run::{CancelHandle, RunLimits, StopReason};
+
+pub const HELP: &str = "step [N]                 run N clock cycles, 1 by default (s)
+stepi [N]                run until N more instructions retire, 1 by default (si)
+continue                 run until the program stops or hits a breakpoint (c)
+break ADDR [fetch] [if REG OP VALUE]
+                         stop before the instruction at ADDR retires, or once it is
+                         fetched, optionally only when REG OP VALUE holds (b)
+watch ADDR [LEN]         stop once the memory stage writes ADDR..ADDR+LEN
+rwatch ADDR [LEN]        the same for reads
+awatch ADDR [LEN]        the same for reads and writes
+delete ID                remove a breakpoint or watchpoint (d)
+info breakpoints         list breakpoints and watchpoints (i b)
+print registers          show every register (p regs), or print REG for one
+x/Nw ADDR                show N words of memory from ADDR, x/Ni disassembles them
+disassemble [ADDR] [N]   disassemble N words from ADDR, around the next instruction
+                         to retire by default (disas)
+show pipeline            every stage with its status and instruction
+show cache NAME SET      every line of one set of a cache, L1D 0 for instance
+help                     this list
+quit                     leave (q)
+
+Numbers can be given in hex with 0x, an empty line repeats the last command.";
+
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<", Comparison::Lt),
+    ("<=", Comparison::Le),
+    (">", Comparison::Gt),
+    (">=", Comparison::Ge),
+];
+
+// Words `disassemble` shows when it isn't told how many
+const DISASSEMBLE_WORDS: usize = 8;
+
+/// A debugging session over one simulator, taking a command at a time
+pub struct Session {
+    sim: Simulator,
+    // Everything the console has been sent, and how much of it has been shown
+    output: Arc<Mutex<Vec<u8>>>,
+    shown: usize,
+    // Replaced before every run so an interrupt only ever stops the current one
+    cancel: Arc<Mutex<CancelHandle>>,
+    last: Option<String>,
+}
+
+impl Session {
+    pub fn new(sim: Simulator, output: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self { sim, output, shown: 0, cancel: Arc::new(Mutex::new(CancelHandle::new())), last: None }
+    }
+
+    /// Cancelling it stops whichever run is going
+    pub fn interrupt(&self) -> Arc<Mutex<CancelHandle>> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// Runs one command line and returns what it printed.  An empty line runs the
+    /// last command again.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = match (line.trim(), &self.last) {
+            ("", Some(last)) => last.clone(),
+            ("", None) => return Ok(String::new()),
+            (line, _) => String::from(line),
+        };
+        self.last = Some(line.clone());
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["step" | "s", ref rest @ ..] => self.step(rest, false),
+            ["stepi" | "si", ref rest @ ..] => self.step(rest, true),
+            ["continue" | "c"] => self.resume(RunLimits::default()),
+            ["break" | "b", ref rest @ ..] => self.set_breakpoint(rest),
+            ["watch", ref rest @ ..] => self.set_watchpoint(rest, Access::Write),
+            ["rwatch", ref rest @ ..] => self.set_watchpoint(rest, Access::Read),
+            ["awatch", ref rest @ ..] => self.set_watchpoint(rest, Access::Any),
+            ["delete" | "d", id] => match self.sim.remove_breakpoint(number(id)?) {
+                true => Ok(String::new()),
+                false => Err(format!("no breakpoint or watchpoint {}", id)),
+            },
+            ["info" | "i", "breakpoints" | "break" | "b"] => Ok(self.breakpoints()),
+            ["info" | "i", "registers" | "reg" | "r"] | ["print" | "p", "registers" | "regs"] => Ok(self.registers()),
+            ["print" | "p", reg] => {
+                let value = self.sim.processor.view_registers()[register(reg)? as usize];
+                Ok(format!("{} = {:#010x} {}\n", reg.to_ascii_uppercase(), value, value))
+            },
+            [examine, addr] if examine.starts_with("x/") || examine == "x" => self.examine(examine, number(addr)?),
+            ["disassemble" | "disas", ref rest @ ..] => self.disassemble(rest),
+            ["show", "pipeline"] => Ok(self.pipeline()),
+            ["show", "cache", name, set] => self.cache_set(name, number(set)?),
+            ["help" | "h"] => Ok(format!("{}\n", HELP)),
+            _ => Err(format!("unknown command \"{}\", try help", line)),
+        }
+    }
+
+    fn step(&mut self, args: &[&str], instructions: bool) -> Result<String, String> {
+        let n = match args {
+            [] => 1,
+            [n] => number(n)? as u64,
+            _ => return Err(String::from("expected a count")),
+        };
+        let limits = match instructions {
+            true => RunLimits::default().with_max_instructions(n),
+            false => RunLimits::default().with_max_cycles(n),
+        };
+        self.resume(limits)
+    }
+
+    fn resume(&mut self, limits: RunLimits) -> Result<String, String> {
+        if let Some(reason) = self.sim.stop_reason() {
+            return Err(format!("the program has stopped, {}", reason));
+        }
+        let cancel = CancelHandle::new();
+        *self.cancel.lock().unwrap() = cancel.clone();
+        let result = self.sim.run(&limits.with_cancel(cancel));
+
+        let mut text = self.new_output();
+        match result.reason {
+            StopReason::CycleLimit | StopReason::InstructionLimit => {},
+            reason => writeln!(text, "{}", reason).unwrap(),
+        }
+        text.push_str(&self.location());
+        Ok(text)
+    }
+
+    // Whatever the console has been sent since it was last shown
+    fn new_output(&mut self) -> String {
+        let output = self.output.lock().unwrap();
+        let text = String::from_utf8_lossy(&output[self.shown..]).into_owned();
+        self.shown = output.len();
+        match text.is_empty() || text.ends_with('\n') {
+            true => text,
+            false => text + "\n",
+        }
+    }
+
+    // The instruction that retires next, where gdb would show the current line
+    fn next_pc(&self) -> i32 {
+        match self.sim.processor.view_retiring() {
+            Some(instr) => instr.meta.pc,
+            None => self.sim.processor.view_registers()[Register::PC as usize],
+        }
+    }
+
+    fn location(&self) -> String {
+        let cycle = self.sim.processor.view_cycles();
+        match self.sim.processor.view_retiring() {
+            Some(instr) => format!("cycle {}, retiring {:#010x}  {}\n", cycle, instr.meta.pc, disassemble(instr.instr_raw as u32)),
+            None => format!("cycle {}, nothing retiring\n", cycle),
+        }
+    }
+
+    fn set_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let [addr, ref rest @ ..] = args[..] else { return Err(String::from("expected an address")) };
+        let mut breakpoint = Breakpoint::new(number(addr)? as i32);
+        let rest = match rest {
+            ["fetch", rest @ ..] => {
+                breakpoint = breakpoint.at(BreakStage::Fetch);
+                rest
+            },
+            rest => rest,
+        };
+        match rest {
+            [] => {},
+            ["if", reg, op, value] => {
+                let op = COMPARISONS.iter().find(|x| x.0 == *op).ok_or_else(|| format!("unknown comparison {}", op))?.1;
+                breakpoint = breakpoint.when(Condition { reg: register(reg)?, op, value: signed(value)? });
+            },
+            _ => return Err(String::from("expected [fetch] [if REG OP VALUE] after the address")),
+        }
+        let id = self.sim.add_breakpoint(breakpoint);
+        Ok(format!("breakpoint {} at {:#x}\n", id, number(addr)?))
+    }
+
+    fn set_watchpoint(&mut self, args: &[&str], access: Access) -> Result<String, String> {
+        let (start, len) = match args {
+            [addr] => (number(addr)?, self.sim.config.memory.word_size),
+            [addr, len] => (number(addr)?, number(len)?),
+            _ => return Err(String::from("expected an address and optionally a length")),
+        };
+        let id = self.sim.add_watchpoint(Watchpoint::new(start, start + len, access));
+        Ok(format!("watchpoint {} on {:#x}..{:#x}\n", id, start, start + len))
+    }
+
+    fn breakpoints(&self) -> String {
+        let mut text = String::new();
+        for breakpoint in self.sim.breakpoints() {
+            write!(text, "{:<4} break  {:#010x} at {:?}", breakpoint.id, breakpoint.addr, breakpoint.stage).unwrap();
+            if let Some(condition) = breakpoint.condition {
+                let op = COMPARISONS.iter().find(|x| x.1 == condition.op).unwrap().0;
+                write!(text, " if {:?} {} {}", condition.reg, op, condition.value).unwrap();
+            }
+            text.push('\n');
+        }
+        for watchpoint in self.sim.watchpoints() {
+            writeln!(text, "{:<4} watch  {:#010x}..{:#010x} {:?}", watchpoint.id, watchpoint.start, watchpoint.end, watchpoint.access).unwrap();
+        }
+        match text.is_empty() {
+            true => String::from("no breakpoints or watchpoints\n"),
+            false => text,
+        }
+    }
+
+    fn registers(&self) -> String {
+        let mut text = String::new();
+        for (i, value) in self.sim.processor.view_registers().iter().enumerate() {
+            let name = format!("{:?}", Register::from_i32(i as i32));
+            write!(text, "{:<3} {:#010x} {:>11}", name, value, value).unwrap();
+            text.push_str(if i % 4 == 3 { "\n" } else { "   " });
+        }
+        text
+    }
+
+    fn examine(&self, format: &str, addr: usize) -> Result<String, String> {
+        let spec = format.strip_prefix("x/").unwrap_or("w");
+        let digits = spec.trim_end_matches(|x: char| x.is_ascii_alphabetic());
+        let count = match digits {
+            "" => 1,
+            digits => number(digits)?,
+        };
+        let word_size = self.sim.config.memory.word_size;
+        match &spec[digits.len()..] {
+            "" | "w" | "x" => {
+                let mut text = String::new();
+                for row in (0..count).collect::<Vec<_>>().chunks(4) {
+                    write!(text, "{:#010x}:", addr + row[0] * word_size).unwrap();
+                    for i in row {
+                        write!(text, " {:#010x}", self.sim.peek(addr + i * word_size)).unwrap();
+                    }
+                    text.push('\n');
+                }
+                Ok(text)
+            },
+            "i" => Ok(self.listing(addr, count, None)),
+            unit => Err(format!("unknown format {}, use w for words or i for instructions", unit)),
+        }
+    }
+
+    fn disassemble(&self, args: &[&str]) -> Result<String, String> {
+        let word_size = self.sim.config.memory.word_size;
+        let next = self.next_pc() as usize;
+        let (addr, count) = match args {
+            [] => (next.saturating_sub(DISASSEMBLE_WORDS / 2 * word_size), DISASSEMBLE_WORDS),
+            [addr] => (number(addr)?, DISASSEMBLE_WORDS),
+            [addr, count] => (number(addr)?, number(count)?),
+            _ => return Err(String::from("expected an address and optionally a count")),
+        };
+        Ok(self.listing(addr, count, Some(next)))
+    }
+
+    // One line per word, the one at `marked` pointed out
+    fn listing(&self, addr: usize, count: usize, marked: Option<usize>) -> String {
+        let word_size = self.sim.config.memory.word_size;
+        let mut text = String::new();
+        for addr in (0..count).map(|i| addr + i * word_size) {
+            let word = self.sim.peek(addr);
+            let marker = if marked == Some(addr) { "=>" } else { "  " };
+            writeln!(text, "{} {:#010x}  {:#010x}  {}", marker, addr, word, disassemble(word)).unwrap();
+        }
+        text
+    }
+
+    fn pipeline(&self) -> String {
+        let stages = self.sim.processor.tracer().lock().unwrap().stages().to_vec();
+        let instrs = self.sim.processor.view_pipeline_instrs();
+        let status = self.sim.processor.view_pipeline_status();
+
+        let mut text = String::new();
+        for ((stage, instr), status) in stages.iter().zip(instrs).zip(status) {
+            write!(text, "{:<3} {:<8}", stage, format!("{:?}", status)).unwrap();
+            match instr {
+                Some(instr) if instr.meta.initialized => {
+                    write!(text, " {:#010x}  {}", instr.meta.pc, disassemble(instr.instr_raw as u32)).unwrap();
+                    if instr.meta.squashed {
+                        text.push_str("  (squashed)");
+                    }
+                },
+                _ => text.push_str(" -"),
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn cache_set(&self, name: &str, set: usize) -> Result<String, String> {
+        let lines = self.sim.view_cache_set(name, set).ok_or_else(|| format!("no cache {} with a set {}", name, set))?;
+        let mut text = String::new();
+        for (way, (tag, contents)) in lines.iter().enumerate() {
+            let state = match (tag.valid, tag.dirty) {
+                (false, _) => "invalid",
+                (true, false) => "clean",
+                (true, true) => "dirty",
+            };
+            write!(text, "way {:<2} {:<7} tag {:#x} block {:#010x} |", way, state, tag.tag, tag.block).unwrap();
+            for word in contents {
+                write!(text, " {:08x}", word).unwrap();
+            }
+            text.push('\n');
+        }
+        Ok(text)
+    }
+}
+
+/// A number in decimal, or hex with `0x`
+fn number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("bad number {}", text))
+}
+
+fn signed(text: &str) -> Result<i32, String> {
+    match text.strip_prefix('-') {
+        Some(text) => Ok(-(number(text)? as i32)),
+        None => Ok(number(text)? as i32),
+    }
+}
+
+fn register(name: &str) -> Result<Register, String> {
+    (0..16).map(Register::from_i32)
+        .find(|reg| format!("{:?}", reg).eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("no register {}", name))
+}
@@ -0,0 +1,338 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use serde_json::json;
+use simulator::assembler::try_assemble;
+use simulator::config::MachineConfig;
+use simulator::explore::{self, Exploration, Grid};
+use simulator::gdb::GdbStub;
+use simulator::memory::Console;
+use simulator::memory::trace::{self, read_trace, TraceFormat};
+use simulator::processor::registers::Register;
+use simulator::processor::trace::CommitFormat;
+use simulator::run::{RunLimits, StopReason};
+use simulator::{Simulator, SimulatorBuilder};
+
+use args::Args;
+use debugger::Session;
+use tui::Visualiser;
+
+mod args;
+mod debugger;
+mod tui;
+
+const USAGE: &str = "usage:
+  ironleg run <program.s> [--config FILE] [--max-cycles N] [--max-instructions N]
+              [--console ADDR] [--commit-log FILE] [--commit-format text|json]
+              [--memory-trace FILE] [--trace-format native|din] [--json]
+  ironleg debug <program.s> [--config FILE] [--console ADDR]
+  ironleg tui <program.s> [--config FILE] [--console ADDR]
+  ironleg gdb <program.s> [--config FILE] [--console ADDR] [--port N]
+  ironleg explore <program.s> <grid.toml> [--config FILE] [--threads N] [--max-cycles N] [--json]
+  ironleg replay <trace> [--config FILE] [--din] [--json]
+
+Configurations are read as TOML or JSON from their extension.  There's only a
+console with --console, at ADDR, printing whatever the program writes there.  run
+exits with 0 when the program halts, 1 when it faults and 3 when it hits a limit;
+bad arguments or input exit with 2.";
+
+// A run that never halts still has to finish
+const MAX_CYCLES: u64 = 100_000_000;
+// gdb's usual port for `target remote`
+const GDB_PORT: u16 = 1234;
+
+const EXIT_HALTED: u8 = 0;
+const EXIT_FAULT: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_LIMIT: u8 = 3;
+
+fn main() -> ExitCode {
+    let mut argv = env::args().skip(1);
+    let result = match argv.next().as_deref() {
+        Some("run") => run(argv),
+        Some("debug") => debug(argv),
+        Some("tui") => visualise(argv),
+        Some("gdb") => gdb(argv),
+        Some("explore") => explore(argv),
+        Some("replay") => replay(argv),
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            Ok(EXIT_HALTED)
+        },
+        Some(command) => Err(format!("unknown command {}\n\n{}", command, USAGE)),
+        None => Err(String::from(USAGE)),
+    };
+
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(message) => {
+            eprintln!("ironleg: {}", message);
+            ExitCode::from(EXIT_USAGE)
+        },
+    }
+}
+
+fn load_config(args: &Args) -> Result<MachineConfig, String> {
+    match args.option("--config") {
+        Some(path) => MachineConfig::load(path).map_err(|e| e.to_string()),
+        None => Ok(MachineConfig::default()),
+    }
+}
+
+fn load_program(path: &str) -> Result<Vec<u32>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    try_assemble(&source).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Everything the program has written to its console
+pub type ConsoleOutput = Arc<Mutex<Vec<u8>>>;
+
+// The program flashed at 0 on the configured machine, with a console attached if
+// --console says where.  Without one the output stays empty.
+fn load_machine(args: &Args, path: &str) -> Result<(Simulator, ConsoleOutput), String> {
+    let program = load_program(path)?;
+    let mut builder = SimulatorBuilder::from_config(load_config(args)?).with_program(0, &program);
+    let mut output = ConsoleOutput::default();
+    if let Some(addr) = args.value("--console")? {
+        let console = Console::new();
+        output = console.output();
+        builder = builder.with_device(addr, Box::new(console));
+    }
+    let sim = builder.build().map_err(|e| e.to_string())?;
+    Ok((sim, output))
+}
+
+fn create(path: &str) -> Result<BufWriter<File>, String> {
+    File::create(path).map(BufWriter::new).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn run(argv: impl Iterator<Item = String>) -> Result<u8, String> {
+    let args = Args::parse(argv, &[
+        "--config", "--max-cycles", "--max-instructions", "--console",
+        "--commit-log", "--commit-format", "--memory-trace", "--trace-format",
+    ], &["--json"])?;
+    let path = args.positional(0, "program")?;
+    args.expect_positional(1)?;
+
+    let (mut sim, output) = load_machine(&args, path)?;
+
+    let commit_format = match args.option("--commit-format") {
+        None | Some("text") => CommitFormat::Text,
+        Some("json") => CommitFormat::JsonLines,
+        Some(other) => return Err(format!("unknown commit log format {}", other)),
+    };
+    let trace_format = match args.option("--trace-format") {
+        None | Some("native") => TraceFormat::Native,
+        Some("din") => TraceFormat::Din,
+        Some(other) => return Err(format!("unknown memory trace format {}", other)),
+    };
+    if let Some(path) = args.option("--commit-log") {
+        sim.log_commits(create(path)?, commit_format);
+    }
+    if let Some(path) = args.option("--memory-trace") {
+        sim.trace_memory(create(path)?, trace_format);
+    }
+
+    let mut limits = RunLimits::default().with_max_cycles(args.value("--max-cycles")?.unwrap_or(MAX_CYCLES));
+    if let Some(instructions) = args.value("--max-instructions")? {
+        limits = limits.with_max_instructions(instructions);
+    }
+    let result = sim.run(&limits);
+
+    if args.option("--commit-log").is_some() {
+        sim.finish_commit_log().map_err(|e| format!("writing the commit log: {}", e))?;
+    }
+    if args.option("--memory-trace").is_some() {
+        sim.finish_memory_trace().map_err(|e| format!("writing the memory trace: {}", e))?;
+    }
+
+    let output = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+    let cpi = match result.instructions {
+        0 => 0.0,
+        instructions => result.cycles as f64 / instructions as f64,
+    };
+    if args.switch("--json") {
+        let report = json!({
+            "stop": result.reason.to_string(),
+            "cycles": result.cycles,
+            "instructions": result.instructions,
+            "cpi": cpi,
+            "registers": registers(&sim).into_iter().map(|(name, value)| (name, json!(value))).collect::<serde_json::Map<_, _>>(),
+            "memory": sim.view_levels().into_iter().map(|(name, stats)| json!({ "level": name, "stats": stats })).collect::<Vec<_>>(),
+            "predictor": sim.processor.view_predictor_stats(),
+            "output": output,
+        });
+        println!("{}", serde_json::to_string_pretty(&report).expect("the report always serializes"));
+    } else {
+        print_report(&sim, &result.reason, result.cycles, result.instructions, cpi, &output);
+    }
+
+    Ok(match result.reason {
+        StopReason::Halted => EXIT_HALTED,
+        StopReason::Fault(_) => EXIT_FAULT,
+        _ => EXIT_LIMIT,
+    })
+}
+
+fn registers(sim: &Simulator) -> Vec<(String, i32)> {
+    sim.processor.view_registers().iter().enumerate()
+        .map(|(i, value)| (format!("{:?}", Register::from_i32(i as i32)), *value))
+        .collect()
+}
+
+fn print_report(sim: &Simulator, reason: &StopReason, cycles: u64, instructions: u64, cpi: f64, output: &str) {
+    println!("{} after {} cycles, {} instructions (CPI {:.3})", reason, cycles, instructions, cpi);
+
+    println!("\nRegisters");
+    for row in registers(sim).chunks(4) {
+        let row: Vec<String> = row.iter().map(|(name, value)| format!("{:<3} {:#010x} {:>11}", name, value, value)).collect();
+        println!("  {}", row.join("   "));
+    }
+
+    println!("\nMemory");
+    for (name, stats) in sim.view_levels() {
+        println!("  {:<6} {:>8} accesses {:>8} hits {:>8} misses  miss rate {:.4}",
+            name, stats.accesses(), stats.hits(), stats.misses(), stats.miss_rate());
+    }
+
+    let predictor = sim.processor.view_predictor_stats();
+    println!("\nBranches");
+    println!("  {} predictions, {} mispredicted", predictor.predictions, predictor.mispredictions);
+
+    if !output.is_empty() {
+        println!("\nOutput");
+        println!("{}", output);
+    }
+}
+
+fn debug(argv: impl Iterator<Item = String>) -> Result<u8, String> {
+    let args = Args::parse(argv, &["--config", "--console"], &[])?;
+    let path = args.positional(0, "program")?;
+    args.expect_positional(1)?;
+
+    let (sim, output) = load_machine(&args, path)?;
+    let mut session = Session::new(sim, output);
+    // Ctrl-C at the prompt is the line editor's, while running it stops the run
+    let interrupt = session.interrupt();
+    ctrlc::set_handler(move || interrupt.lock().unwrap().cancel()).map_err(|e| e.to_string())?;
+
+    let mut editor = DefaultEditor::new().map_err(|e| e.to_string())?;
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".ironleg_history"));
+    if let Some(history) = &history {
+        // There's no history the first time round
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("(ironleg) ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.to_string()),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        if matches!(line.trim(), "quit" | "q") {
+            break;
+        }
+        match session.execute(&line) {
+            Ok(text) => print!("{}", text),
+            Err(message) => println!("{}", message),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(|e| format!("saving the history: {}", e))?;
+    }
+    Ok(EXIT_HALTED)
+}
+
+fn visualise(argv: impl Iterator<Item = String>) -> Result<u8, String> {
+    let args = Args::parse(argv, &["--config", "--console"], &[])?;
+    let path = args.positional(0, "program")?;
+    args.expect_positional(1)?;
+
+    let (sim, output) = load_machine(&args, path)?;
+    let mut terminal = ratatui::init();
+    let result = Visualiser::new(sim, output).run(&mut terminal);
+    ratatui::restore();
+    result.map_err(|e| e.to_string())?;
+    Ok(EXIT_HALTED)
+}
+
+fn gdb(argv: impl Iterator<Item = String>) -> Result<u8, String> {
+    let args = Args::parse(argv, &["--config", "--console", "--port"], &[])?;
+    let path = args.positional(0, "program")?;
+    args.expect_positional(1)?;
+    let port = args.value("--port")?.unwrap_or(GDB_PORT);
+
+    let (sim, output) = load_machine(&args, path)?;
+    // Only this machine can reach the simulator, there's nothing guarding it
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("listening on port {}: {}", port, e))?;
+    eprintln!("waiting for gdb on 127.0.0.1:{}", port);
+    let (stream, peer) = listener.accept().map_err(|e| e.to_string())?;
+    eprintln!("gdb connected from {}", peer);
+
+    let mut stub = GdbStub::new(sim);
+    stub.serve(stream).map_err(|e| e.to_string())?;
+    print!("{}", String::from_utf8_lossy(&output.lock().unwrap()));
+    Ok(EXIT_HALTED)
+}
+
+fn explore(argv: impl Iterator<Item = String>) -> Result<u8, String> {
+    let args = Args::parse(argv, &["--config", "--threads", "--max-cycles"], &["--json"])?;
+    let program = load_program(args.positional(0, "program")?)?;
+    let grid_path = args.positional(1, "grid")?;
+    args.expect_positional(2)?;
+
+    let text = fs::read_to_string(grid_path).map_err(|e| format!("{}: {}", grid_path, e))?;
+    let grid = Grid::from_toml(&text).map_err(|e| format!("{}: {}", grid_path, e))?;
+    let mut exploration = Exploration::new(load_config(&args)?, grid);
+    if let Some(threads) = args.value("--threads")? {
+        exploration = exploration.with_threads(threads);
+    }
+    if let Some(max_cycles) = args.value("--max-cycles")? {
+        exploration = exploration.with_max_cycles(max_cycles);
+    }
+
+    let outcomes = exploration.run(&program);
+    match args.switch("--json") {
+        true => println!("{}", explore::to_json(&outcomes)),
+        false => print!("{}", explore::to_csv(&outcomes)),
+    }
+    Ok(EXIT_HALTED)
+}
+
+fn replay(argv: impl Iterator<Item = String>) -> Result<u8, String> {
+    let args = Args::parse(argv, &["--config"], &["--din", "--json"])?;
+    let path = args.positional(0, "trace")?;
+    args.expect_positional(1)?;
+
+    let config = load_config(&args)?;
+    let format = match args.switch("--din") {
+        true => TraceFormat::Din,
+        false => TraceFormat::Native,
+    };
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let records = read_trace(BufReader::new(file), format, config.memory.word_size).map_err(|e| format!("{}: {}", path, e))?;
+    let result = trace::replay(&records, &config.memory);
+
+    if args.switch("--json") {
+        println!("{}", serde_json::to_string_pretty(&result).expect("replay results always serialize"));
+    } else {
+        println!("{} accesses, {} cycles waiting on memory", result.accesses, result.cycles);
+        for (name, stats) in &result.levels {
+            println!("  {:<6} {:>8} accesses {:>8} hits {:>8} misses  miss rate {:.4}",
+                name, stats.accesses(), stats.hits(), stats.misses(), stats.miss_rate());
+        }
+    }
+    Ok(EXIT_HALTED)
+}
@@ -0,0 +1,258 @@
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use simulator::Simulator;
+use simulator::assembler::disassemble;
+use simulator::processor::pipeline::StageResult;
+use simulator::processor::registers::Register;
+use simulator::run::{RunLimits, StopReason};
+
+use crate::ConsoleOutput;
+
+const KEYS: &str = "s step  n next instruction  r run/pause  b back  +/- speed  tab level  ↑↓ PgUp PgDn scroll  q quit";
+
+// How long to wait for a key before drawing again
+const FRAME: Duration = Duration::from_millis(30);
+// Cycles run between frames while running, changed with + and -
+const SPEED: u64 = 64;
+const MAX_SPEED: u64 = 1 << 20;
+
+/// A full screen view of one simulator, updated as it steps or runs
+pub struct Visualiser {
+    sim: Simulator,
+    output: ConsoleOutput,
+    running: bool,
+    speed: u64,
+    // Why the last step or run stopped early, if it did
+    status: String,
+    // Which memory level is shown, counting from the top, and the first line
+    level: usize,
+    line: usize,
+}
+
+impl Visualiser {
+    pub fn new(sim: Simulator, output: ConsoleOutput) -> Self {
+        Self { sim, output, running: false, speed: SPEED, status: String::new(), level: 0, line: 0 }
+    }
+
+    pub fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if self.running {
+                self.advance(RunLimits::default().with_max_cycles(self.speed));
+            }
+            if !event::poll(FRAME)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('s') | KeyCode::Char(' ') => self.advance(RunLimits::default().with_max_cycles(1)),
+                KeyCode::Char('n') => self.advance(RunLimits::default().with_max_instructions(1)),
+                KeyCode::Char('r') => self.running = !self.running && self.sim.stop_reason().is_none(),
+                KeyCode::Char('b') => {
+                    self.running = false;
+                    if self.sim.step_back(1) == 0 {
+                        self.status = String::from("no history to step back through");
+                    } else {
+                        self.status.clear();
+                    }
+                },
+                KeyCode::Char('+') => self.speed = (self.speed * 2).min(MAX_SPEED),
+                KeyCode::Char('-') => self.speed = (self.speed / 2).max(1),
+                KeyCode::Tab => self.level = (self.level + 1) % self.levels().len(),
+                KeyCode::Up => self.line = self.line.saturating_sub(1),
+                KeyCode::Down => self.line += 1,
+                KeyCode::PageUp => self.line = self.line.saturating_sub(16),
+                KeyCode::PageDown => self.line += 16,
+                _ => {},
+            }
+        }
+    }
+
+    fn advance(&mut self, limits: RunLimits) {
+        let result = self.sim.run(&limits);
+        self.status = match result.reason {
+            StopReason::CycleLimit | StopReason::InstructionLimit => String::new(),
+            reason => {
+                self.running = false;
+                reason.to_string()
+            },
+        };
+    }
+
+    // Names of the data side's levels, top first
+    fn levels(&self) -> Vec<String> {
+        self.sim.memory.lock().unwrap().view_names().into_iter().rev().collect()
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)])
+            .areas(frame.area());
+        let [left, middle, memory] = Layout::horizontal([Constraint::Percentage(36), Constraint::Percentage(24), Constraint::Percentage(40)])
+            .areas(body);
+        let [pipeline, registers, stats, console] = Layout::vertical([
+            Constraint::Length(self.sim.processor.view_pipeline_status().len() as u16 + 2),
+            Constraint::Length(10),
+            Constraint::Min(0),
+            Constraint::Length(6),
+        ]).areas(left);
+
+        frame.render_widget(self.header(), header);
+        frame.render_widget(Paragraph::new(KEYS).dark_gray(), footer);
+        frame.render_widget(self.pipeline(), pipeline);
+        frame.render_widget(self.registers(), registers);
+        frame.render_widget(self.stats(), stats);
+        frame.render_widget(self.console(), console);
+        frame.render_widget(self.disassembly(middle), middle);
+        frame.render_widget(self.memory(memory), memory);
+    }
+
+    fn header(&self) -> Paragraph<'_> {
+        let state = match (self.sim.stop_reason(), self.running) {
+            (Some(_), _) => "stopped",
+            (None, true) => "running",
+            (None, false) => "paused",
+        };
+        let mut spans = vec![
+            Span::from(" IronLEG ").bold().reversed(),
+            Span::from(format!("  cycle {}  retired {}  {}", self.sim.processor.view_cycles(), self.sim.processor.view_retired(), state)),
+        ];
+        if self.running {
+            spans.push(Span::from(format!(" at {} cycles a frame", self.speed)));
+        }
+        if !self.status.is_empty() {
+            spans.push(Span::from(format!("  {}", self.status)).yellow());
+        }
+        Paragraph::new(Line::from(spans))
+    }
+
+    fn pipeline(&self) -> Paragraph<'_> {
+        let stages = self.sim.processor.tracer().lock().unwrap().stages().to_vec();
+        let instrs = self.sim.processor.view_pipeline_instrs();
+        let status = self.sim.processor.view_pipeline_status();
+
+        let lines: Vec<Line> = stages.iter().zip(instrs).zip(status).map(|((stage, instr), status)| {
+            let colour = match status {
+                StageResult::DONE | StageResult::COMPLETE => Color::Green,
+                StageResult::WAIT => Color::Yellow,
+                StageResult::SQUASH => Color::Magenta,
+                StageResult::HALT => Color::Blue,
+                StageResult::FAULT => Color::Red,
+            };
+            let mut spans = vec![Span::from(format!("{:<3}", stage)).bold(), Span::styled(format!("{:<9}", format!("{:?}", status)), colour)];
+            match instr {
+                Some(instr) if instr.meta.initialized => {
+                    let text = format!("{:#06x}  {}", instr.meta.pc, disassemble(instr.instr_raw as u32));
+                    spans.push(match instr.meta.squashed {
+                        true => Span::from(text).crossed_out().dark_gray(),
+                        false => Span::from(text),
+                    });
+                },
+                _ => spans.push(Span::from("-").dark_gray()),
+            }
+            Line::from(spans)
+        }).collect();
+        Paragraph::new(lines).block(Block::bordered().title(" Pipeline "))
+    }
+
+    fn registers(&self) -> Paragraph<'_> {
+        let values = self.sim.processor.view_registers();
+        let in_use = self.sim.processor.view_register_status();
+
+        let lines: Vec<Line> = (0..16).collect::<Vec<usize>>().chunks(2).map(|pair| {
+            Line::from(pair.iter().map(|&i| {
+                let text = format!("{:<4}{:#010x}{:>12} ", format!("{:?}", Register::from_i32(i as i32)), values[i], values[i]);
+                match in_use[i] {
+                    true => Span::from(text).black().on_yellow(),
+                    false => Span::from(text),
+                }
+            }).collect::<Vec<_>>())
+        }).collect();
+        Paragraph::new(lines).block(Block::bordered().title(" Registers "))
+    }
+
+    fn stats(&self) -> Paragraph<'_> {
+        let cycles = self.sim.processor.view_cycles();
+        let retired = self.sim.processor.view_retired();
+        let cpi = match retired {
+            0 => 0.0,
+            retired => cycles as f64 / retired as f64,
+        };
+        let predictor = self.sim.processor.view_predictor_stats();
+
+        let mut lines = vec![
+            Line::from(format!("CPI {:.3}", cpi)),
+            Line::from(format!("branches {} predicted, {} wrong", predictor.predictions, predictor.mispredictions)),
+        ];
+        for (name, stats) in self.sim.view_levels() {
+            lines.push(Line::from(format!("{:<5} {:>7} hits {:>7} misses  {:>5.1}%", name, stats.hits(), stats.misses(), stats.miss_rate() * 100.0)));
+        }
+        Paragraph::new(lines).block(Block::bordered().title(" Stats "))
+    }
+
+    fn console(&self) -> Paragraph<'_> {
+        let text = String::from_utf8_lossy(&self.output.lock().unwrap()).into_owned();
+        Paragraph::new(text).wrap(Wrap { trim: false }).block(Block::bordered().title(" Console "))
+    }
+
+    // Words around the instruction that retires next
+    fn disassembly(&self, area: Rect) -> Paragraph<'_> {
+        let word_size = self.sim.config.memory.word_size;
+        let next = match self.sim.processor.view_retiring() {
+            Some(instr) => instr.meta.pc,
+            None => self.sim.processor.view_registers()[Register::PC as usize],
+        } as usize;
+        let rows = area.height.saturating_sub(2) as usize;
+        let first = next.saturating_sub(rows / 3 * word_size);
+
+        let lines: Vec<Line> = (0..rows).map(|i| first + i * word_size).map(|addr| {
+            let text = format!("{:#06x}  {}", addr, disassemble(self.sim.peek(addr)));
+            match addr == next {
+                true => Line::from(format!("▶ {}", text)).bold().cyan(),
+                false => Line::from(format!("  {}", text)),
+            }
+        }).collect();
+        Paragraph::new(lines).block(Block::bordered().title(" Disassembly "))
+    }
+
+    fn memory(&self, area: Rect) -> Paragraph<'_> {
+        let memory = self.sim.memory.lock().unwrap();
+        let names = memory.view_names();
+        let level = names.len() - 1 - self.level.min(names.len() - 1);
+        let block_bytes = self.sim.config.memory.block_size * self.sim.config.memory.word_size;
+        let rows = area.height.saturating_sub(2) as usize;
+
+        let lines: Vec<Line> = (self.line..self.line + rows).map(|line| {
+            let words: Vec<String> = memory.view_line(line)[level].iter().map(|x| format!("{:08x}", x)).collect();
+            match memory.view_tags(line)[level] {
+                None => Line::from(format!("{:#07x}  {}", line * block_bytes, words.join(" "))),
+                Some(tag) => {
+                    let state = match (tag.valid, tag.dirty) {
+                        (false, _) => "-",
+                        (true, false) => "V",
+                        (true, true) => "D",
+                    };
+                    let style = match tag.valid {
+                        true => Style::default(),
+                        false => Style::default().add_modifier(Modifier::DIM),
+                    };
+                    Line::styled(format!("{:>4} {} {:#07x}  {}", line, state, tag.block, words.join(" ")), style)
+                },
+            }
+        }).collect();
+        let title = format!(" Memory: {} from line {} ", names[level], self.line);
+        Paragraph::new(lines).block(Block::bordered().title(title))
+    }
+}
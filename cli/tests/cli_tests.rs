@@ -0,0 +1,99 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// Counts to 3 in memory, then prints H
+const COUNT: &str = "MOV R1, 0
+MOV R2, 256
+ADD R1, 1
+STR R1, R2
+CMP R1, 3
+BNE 8
+MOV R3, 72
+MOV R4, 0xF00
+STR R3, R4
+HLT";
+
+// A file of its own per test, tests run side by side
+fn source(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("ironleg-{}-{}.s", std::process::id(), name));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn ironleg(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ironleg"))
+        .args(args)
+        .env("HOME", env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn exit_codes_say_how_the_run_ended() {
+    let count = source("exit-count", COUNT);
+    let spin = source("exit-spin", "B 0");
+    let bad = source("exit-bad", "MOV R1, 1\nMOV R1, 99999");
+
+    let halted = ironleg(&["run", count.to_str().unwrap(), "--console", "0xF00"], "");
+    assert_eq!(halted.status.code(), Some(0));
+    assert!(stdout(&halted).starts_with("halted after "));
+    assert!(stdout(&halted).ends_with("Output\nH\n"));
+
+    let limited = ironleg(&["run", spin.to_str().unwrap(), "--max-cycles", "200"], "");
+    assert_eq!(limited.status.code(), Some(3));
+
+    let invalid = ironleg(&["run", bad.to_str().unwrap()], "");
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&invalid.stderr).contains("line 2: 99999 doesn't fit in 12 bits"));
+}
+
+#[test]
+fn json_reports_registers_by_name() {
+    let count = source("json", COUNT);
+    let output = ironleg(&["run", count.to_str().unwrap(), "--json", "--console", "0xF00"], "");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(report["stop"], "halted");
+    assert_eq!(report["registers"]["R1"], 3);
+    assert_eq!(report["output"], "H");
+
+    // Without a console the store just goes to memory
+    let output = ironleg(&["run", count.to_str().unwrap(), "--json"], "");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["output"], "");
+}
+
+#[test]
+fn debugger_stops_at_breakpoints_and_shows_state() {
+    let count = source("debug", COUNT);
+    let script = "break 0x10 if R1 == 2
+continue
+print R1
+x/2w 256
+watch 0xF00
+continue
+continue
+";
+    let output = ironleg(&["debug", count.to_str().unwrap(), "--console", "0xF00"], script);
+    let text = stdout(&output);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(text.contains("breakpoint 1 at 0x10\ncycle "), "{}", text);
+    assert!(text.contains("R1 = 0x00000002 2"));
+    assert!(text.contains("0x00000100: 0x00000002 0x00000000"));
+    // The console is written in the memory stage, before the store retires
+    assert!(text.contains("H\nwatchpoint 2 on 0xf00\ncycle "));
+    assert!(text.contains("\nhalted\ncycle "));
+}
@@ -0,0 +1,232 @@
+use simulator::Simulator;
+use simulator::memory::MemoryStats;
+use simulator::processor::instruction::Instruction;
+use simulator::processor::pipeline::StageResult;
+use simulator::run::{RunLimits, StopReason};
+
+use actix_web::{get, rt, web, HttpRequest, HttpResponse, Result};
+use actix_ws::{Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::session::SessionData;
+use crate::worker::{self, Budget, RunState};
+
+
+// Cycles run between updates during a run the socket started, unless the page asks
+// for another number
+const DEFAULT_EVERY: u64 = 1000;
+// As many lines as /refresh shows
+const MEMORY_LINES: usize = 6;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    /// Runs `cycles` cycles, 1 by default, pausing a run first
+    Step { cycles: Option<u64> },
+    /// Starts the session's run, the same one /run starts
+    Run,
+    Resume,
+    Pause,
+    /// Moves the memory lines being watched, and sends an update either way
+    View { line: usize },
+}
+
+/// Only what changed since the last update is sent, anything left out is as it was
+#[derive(Serialize, Debug, Default)]
+struct Update {
+    num_cycles: u128,
+    running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopped: Option<StopReason>,
+    // Registers by number
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    register_values: Vec<(usize, i32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    register_status: Option<[bool; 16]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pipeline_values: Option<Vec<Option<Instruction>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pipeline_status: Option<Vec<StageResult>>,
+    // Lines in view, by line number, with every level's contents
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    memory_lines: Vec<(usize, Vec<Vec<usize>>)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory_stats: Option<Vec<MemoryStats>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory_levels: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Outgoing {
+    Update(Update),
+    Error { message: String },
+}
+
+// What the page was last sent, which starts out as nothing so the first update has
+// everything
+#[derive(Default)]
+struct Seen {
+    registers: Option<[i32; 16]>,
+    register_status: Option<[bool; 16]>,
+    // Instructions can't be compared, their JSON can
+    pipeline: Option<serde_json::Value>,
+    pipeline_status: Option<Vec<StageResult>>,
+    memory: HashMap<usize, Vec<Vec<usize>>>,
+    stats: Option<Vec<MemoryStats>>,
+    levels: Option<Vec<String>>,
+}
+
+// `Some(new)` and remembers it if it differs from what was seen
+fn changed<T: PartialEq + Clone>(seen: &mut Option<T>, new: T) -> Option<T> {
+    if seen.as_ref() == Some(&new) {
+        return None;
+    }
+    *seen = Some(new.clone());
+    Some(new)
+}
+
+impl Seen {
+    fn update(&mut self, sim: &Simulator, line: usize) -> Update {
+        let registers = sim.processor.view_registers();
+        let register_values = (0..16)
+            .filter(|&i| self.registers.is_none_or(|seen| seen[i] != registers[i]))
+            .map(|i| (i, registers[i]))
+            .collect();
+        self.registers = Some(registers);
+
+        let pipeline: Vec<Option<Instruction>> = sim.processor.view_pipeline_instrs().into_iter().cloned().collect();
+        let pipeline_values = changed(&mut self.pipeline, serde_json::to_value(&pipeline).unwrap()).map(|_| pipeline);
+
+        let (levels, stats) = sim.view_levels().into_iter().unzip();
+        let mem = sim.memory.lock().unwrap();
+        // Lines out of view are forgotten, coming back into view sends them again
+        self.memory.retain(|i, _| (line..line + MEMORY_LINES).contains(i));
+        let mut memory_lines = vec![];
+        for i in line..line + MEMORY_LINES {
+            let contents = mem.view_line(i);
+            if self.memory.get(&i) != Some(&contents) {
+                self.memory.insert(i, contents.clone());
+                memory_lines.push((i, contents));
+            }
+        }
+
+        Update {
+            num_cycles: sim.processor.view_cycles(),
+            running: false,
+            stopped: None,
+            register_values,
+            register_status: changed(&mut self.register_status, sim.processor.view_register_status()),
+            pipeline_values,
+            pipeline_status: changed(&mut self.pipeline_status, sim.processor.view_pipeline_status()),
+            memory_lines,
+            memory_stats: changed(&mut self.stats, stats),
+            memory_levels: changed(&mut self.levels, levels),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LiveQuery {
+    every: Option<u64>,
+    line: Option<usize>,
+}
+
+/// A WebSocket that sends what changed after every slice of the session's run,
+/// however it was started, and after every command.  Commands are JSON,
+/// `{"command": "step"}`, `"run"`, `"resume"`, `"pause"` or `"view"` with a `line`.
+#[get("/live")]
+pub async fn live(req: HttpRequest, body: web::Payload, query: web::Query<LiveQuery>, data: SessionData) -> Result<HttpResponse> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let (sender, commands) = mpsc::unbounded_channel();
+
+    rt::spawn(receive(session.clone(), stream, sender));
+    rt::spawn(push(session, commands, data, query.every.unwrap_or(DEFAULT_EVERY).max(1), query.line.unwrap_or(0)));
+    Ok(response)
+}
+
+// Hands commands over to `push`, which stops once this drops the sender
+async fn receive(mut session: Session, mut stream: MessageStream, commands: UnboundedSender<Command>) {
+    while let Some(Ok(message)) = stream.recv().await {
+        let sent = match message {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(command) => commands.send(command).is_ok(),
+                Err(error) => send(&mut session, Outgoing::Error { message: error.to_string() }).await,
+            },
+            Message::Ping(bytes) => session.pong(&bytes).await.is_ok(),
+            Message::Close(_) => false,
+            _ => true,
+        };
+        if !sent {
+            break;
+        }
+    }
+}
+
+// Whether the page is still there
+async fn send(session: &mut Session, message: Outgoing) -> bool {
+    session.text(serde_json::to_string(&message).unwrap()).await.is_ok()
+}
+
+async fn push(mut session: Session, mut commands: UnboundedReceiver<Command>, data: SessionData, every: u64, mut line: usize) {
+    let mut seen = Seen::default();
+    let mut slices = data.worker.lock().unwrap().subscribe();
+    // So the first update, with everything, goes out straight away
+    slices.mark_changed();
+    // The run whose end was last sent, so each one's is sent once
+    let mut reported = data.worker.lock().unwrap().runs();
+
+    loop {
+        let mut step = None;
+        let mut error = None;
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Step { cycles }) => {
+                    worker::stop(&data);
+                    step = Some(RunLimits::default().with_max_cycles(cycles.unwrap_or(1)));
+                },
+                Some(Command::Run) => error = worker::start(&data, Some(Budget::new(None, None, None)), every).err(),
+                Some(Command::Resume) => error = worker::start(&data, None, every).err(),
+                Some(Command::Pause) => worker::stop(&data),
+                Some(Command::View { line: view }) => line = view,
+                None => break,
+            },
+            changed = slices.changed() => if changed.is_err() {
+                break;
+            },
+        }
+        if let Some(message) = error {
+            if !send(&mut session, Outgoing::Error { message: message.to_string() }).await {
+                break;
+            }
+            continue;
+        }
+
+        // Takes the lock only as long as a step, so a run's slices carry on around it
+        let data = data.clone();
+        let Ok((update, last, run)) = web::block(move || {
+            let mut simulator = data.sim.lock().unwrap();
+            let stepped = step.map(|limits| simulator.run(&limits).reason)
+                .filter(|reason| !matches!(reason, StopReason::CycleLimit | StopReason::InstructionLimit));
+            let worker = data.worker.lock().unwrap();
+            let running = worker.state(&simulator) == RunState::Running;
+            let stopped = match worker.progress().reason.clone() {
+                Some(reason) if !running && worker.runs() != reported => {
+                    reported = worker.runs();
+                    Some(reason)
+                },
+                _ => stepped,
+            };
+            (Update { running, stopped, ..seen.update(&simulator, line) }, seen, reported)
+        }).await else { break };
+        seen = last;
+        reported = run;
+
+        if !send(&mut session, Outgoing::Update(update)).await {
+            break;
+        }
+    }
+    let _ = session.close(None).await;
+}
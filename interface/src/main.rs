@@ -1,38 +1,96 @@
-use simulator;
 use simulator::assembler;
+use simulator::config::MachineConfig;
+use simulator::explore::{self, Exploration, Grid, Outcome};
+use simulator::memory::MemoryStats;
+use simulator::processor::debug::{Breakpoint, Watchpoint};
 use simulator::processor::instruction::Instruction;
 use simulator::processor::pipeline::StageResult;
+use simulator::snapshot::Snapshot;
 
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder, Result};
-use actix_files;
+use actix_web::{delete, get, post, put, web, App, HttpResponse, HttpServer, Responder, Result};
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 
+mod live;
+mod session;
+mod worker;
 
-struct SimulatorState {
+use session::{SessionData, Sessions};
+use worker::{RunProgress, RunState, Worker};
+
+pub struct SimulatorState {
     sim: Mutex<simulator::Simulator>,
+    // Kept outside the simulator lock, which a run holds for a slice at a time
+    worker: Mutex<Worker>,
+    // The last program flashed, which a new configuration can keep
+    program: Mutex<Vec<u32>>,
+}
+
+// The simulator and its run, for changing the machine from outside.  A run going on
+// in the background would carry on with whatever it was changed to, so it has to be
+// paused first.
+fn idle(data: &SimulatorState) -> Result<(MutexGuard<'_, simulator::Simulator>, MutexGuard<'_, Worker>), HttpResponse> {
+    let simulator = data.sim.lock().unwrap();
+    let worker = data.worker.lock().unwrap();
+    match worker.state(&simulator) {
+        RunState::Running => Err(HttpResponse::Conflict().body("pause the run before changing the machine")),
+        _ => Ok((simulator, worker)),
+    }
 }
 
 #[get("/step")]
-async fn step(data: web::Data<SimulatorState>) -> HttpResponse {
-    let mut simulator = data.sim.lock().unwrap();
-    simulator.processor.cycle().await;
+async fn step(data: SessionData) -> HttpResponse {
+    let (mut simulator, _worker) = match idle(&data) {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    simulator.step();
     
     HttpResponse::Ok().body("🦿")
 }
 
-#[get("/run")]
-async fn run(data: web::Data<SimulatorState>) -> HttpResponse {
-    let mut simulator = data.sim.lock().unwrap();
-    while simulator.processor.cycle().await {}
-    
-    HttpResponse::Ok().body("🦿")
+#[derive(Deserialize, Debug)]
+struct StepBackQuery {
+    cycles: Option<u64>,
+    instructions: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct SteppedBack {
+    cycles: u64,
+    instructions: u64,
+}
+
+/// Goes back one cycle by default, or by the given number of cycles or retired
+/// instructions
+#[get("/step/back")]
+async fn step_back(query: web::Query<StepBackQuery>, data: SessionData) -> HttpResponse {
+    let (mut simulator, mut worker) = match idle(&data) {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    let (cycles, retired) = (simulator.processor.view_cycles(), simulator.processor.view_retired());
+    match query.instructions {
+        Some(instructions) => simulator.step_back_instructions(instructions),
+        None => simulator.step_back(query.cycles.unwrap_or(1)),
+    };
+    worker.reset();
+
+    HttpResponse::Ok().json(SteppedBack {
+        cycles: (cycles - simulator.processor.view_cycles()) as u64,
+        instructions: retired - simulator.processor.view_retired(),
+    })
 }
 
 #[get("/reset")]
-async fn reset(data: web::Data<SimulatorState>) -> HttpResponse {
-    let mut simulator = data.sim.lock().unwrap();
+async fn reset(data: SessionData) -> HttpResponse {
+    let (mut simulator, mut worker) = match idle(&data) {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
     simulator.reset();
+    worker.reset();
 
     HttpResponse::Ok().body("🦿")
 }
@@ -43,20 +101,133 @@ struct Program {
 }
 
 #[post("/flash")]
-async fn flash(program: web::Json<Program>, data: web::Data<SimulatorState>) -> HttpResponse {
-    let mut simulator = data.sim.lock().unwrap();
-
-    let bytecode = assembler::assemble(&program.program);
+async fn flash(program: web::Json<Program>, data: SessionData) -> HttpResponse {
+    // Before locking, a bad program mustn't take the session down with it
+    let bytecode = match assembler::try_assemble(&program.program) {
+        Ok(bytecode) => bytecode,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+
+    let (mut simulator, mut worker) = match idle(&data) {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
     simulator.flash(0, &bytecode);
+    worker.reset();
+    *data.program.lock().unwrap() = bytecode;
 
     HttpResponse::Ok().body("🦿")
 }
 
+// Sweeps are run away from the simulator, but still shouldn't run forever
+const EXPLORE_MAX_CYCLES: u64 = 1_000_000;
+const EXPLORE_MAX_POINTS: usize = 256;
+
+#[derive(Deserialize, Debug)]
+struct ExploreRequest {
+    program: String,
+    grid: Grid,
+    /// `csv` or `json`, CSV by default
+    format: Option<String>,
+    max_cycles: Option<u64>,
+}
+
+/// Runs a program on every combination of a grid of parameters, starting from the
+/// current machine, and returns the cycles, CPI and miss rates of each
+#[post("/explore")]
+async fn run_exploration(request: web::Json<ExploreRequest>, data: SessionData) -> Result<HttpResponse> {
+    let request = request.into_inner();
+    let (content_type, write): (&str, fn(&[Outcome]) -> String) = match request.format.as_deref() {
+        None | Some("csv") => ("text/csv", explore::to_csv),
+        Some("json") => ("application/json", explore::to_json),
+        Some(format) => return Ok(HttpResponse::BadRequest().body(format!("unknown format {}", format))),
+    };
+    let program = match assembler::try_assemble(&request.program) {
+        Ok(program) => program,
+        Err(error) => return Ok(HttpResponse::BadRequest().body(error.to_string())),
+    };
+    let points = request.grid.point_count();
+    if points > EXPLORE_MAX_POINTS {
+        return Ok(HttpResponse::BadRequest().body(format!("the grid has {} points, at most {} can be run", points, EXPLORE_MAX_POINTS)));
+    }
+
+    let base = data.sim.lock().unwrap().config.clone();
+    let exploration = Exploration::new(base, request.grid)
+        .with_max_cycles(request.max_cycles.unwrap_or(EXPLORE_MAX_CYCLES).min(EXPLORE_MAX_CYCLES));
+
+    let outcomes = web::block(move || exploration.run(&program)).await?;
+    Ok(HttpResponse::Ok().content_type(content_type).body(write(&outcomes)))
+}
+
+/// The machine configuration, in the JSON `PUT /config` takes
+#[get("/config")]
+async fn get_config(data: SessionData) -> HttpResponse {
+    let simulator = data.sim.lock().unwrap();
+
+    HttpResponse::Ok().content_type("application/json").body(simulator.config.to_json())
+}
+
+#[derive(Deserialize, Debug)]
+struct ConfigQuery {
+    keep_program: Option<bool>,
+}
+
+/// Builds a new machine from the configuration in the body, flashing the last
+/// program into it again with `keep_program=true`.  Everything else about the old
+/// machine, its state, breakpoints and history, goes with it.
+#[put("/config")]
+async fn put_config(query: web::Query<ConfigQuery>, body: String, data: SessionData) -> HttpResponse {
+    let built = MachineConfig::from_json(&body).and_then(|config| simulator::Simulator::from_config(&config));
+    let mut machine = match built {
+        Ok(machine) => machine,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+    machine.record_pipeline(DIAGRAM_INSTRUCTIONS);
+
+    let (mut simulator, mut worker) = match idle(&data) {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if query.keep_program.unwrap_or(false) {
+        machine.flash(0, &data.program.lock().unwrap());
+    }
+    *simulator = machine;
+    worker.reset();
+
+    HttpResponse::Ok().body("🦿")
+}
+
+/// The whole machine as a snapshot file
+#[get("/snapshot")]
+async fn get_snapshot(data: SessionData) -> HttpResponse {
+    let simulator = data.sim.lock().unwrap();
+
+    HttpResponse::Ok().content_type("application/json").body(simulator.snapshot().to_json())
+}
+
+// Snapshots carry every cache line and all of the RAM in use
+const SNAPSHOT_LIMIT: usize = 64 * 1024 * 1024;
+
+#[post("/snapshot")]
+async fn load_snapshot(body: String, data: SessionData) -> HttpResponse {
+    let (mut simulator, mut worker) = match idle(&data) {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+
+    match Snapshot::from_json(&body).and_then(|snapshot| simulator.load_snapshot(&snapshot)) {
+        Ok(()) => {
+            worker.reset();
+            HttpResponse::Ok().body("🦿")
+        },
+        Err(error) => HttpResponse::BadRequest().body(error.to_string()),
+    }
+}
 
 #[get("/cycles")]
-async fn get_cycles(data: web::Data<SimulatorState>) -> Result<impl Responder> {
+async fn get_cycles(data: SessionData) -> Result<impl Responder> {
     let simulator = data.sim.lock().unwrap();
-    Ok(web::Json(simulator.processor.view_cycles().await))
+    Ok(web::Json(simulator.processor.view_cycles()))
 }
 
 #[derive(Serialize, Debug)]
@@ -65,56 +236,74 @@ struct UserInterfaceData {
     register_values: [i32; 16],
     register_status: [bool; 16],
     memory_contents: Vec<Vec<Vec<usize>>>,
+    memory_stats: Vec<MemoryStats>,
+    memory_levels: Vec<String>,
     pipeline_values: Vec<Option<Instruction>>,
     pipeline_status: Vec<StageResult>,
-
+    run_state: RunState,
+    run: RunProgress,
 }
 
 #[get("/refresh/{line_num}")]
-async fn refresh(path: web::Path<usize>, data: web::Data<SimulatorState>) -> Result<impl Responder> {
+async fn refresh(path: web::Path<usize>, data: SessionData) -> Result<impl Responder> {
     let simulator = data.sim.lock().unwrap();
+    // Every level of both sides, the lines below are only the data side's
+    let (memory_levels, memory_stats) = simulator.view_levels().into_iter().unzip();
     let mem = simulator.memory.lock().unwrap();
+    let worker = data.worker.lock().unwrap();
 
     let line_num = path.into_inner();
 
     let mut memory_contents: Vec<Vec<Vec<usize>>> = vec![];
     for i in line_num..line_num + 6 {
-        memory_contents.push(mem.view_line(i).into_iter().map(|x| x.clone()).collect());
+        memory_contents.push(mem.view_line(i));
     }
     
     Ok(web::Json(UserInterfaceData {
-        num_cycles: simulator.processor.view_cycles().await,
+        num_cycles: simulator.processor.view_cycles(),
         register_values: simulator.processor.view_registers(),
         register_status: simulator.processor.view_register_status(),
-        memory_contents: memory_contents,
-        pipeline_values: simulator.processor.view_pipeline_instrs().into_iter().map(|x| x.clone()).collect(),
+        memory_contents,
+        memory_stats,
+        memory_levels,
+        pipeline_values: simulator.processor.view_pipeline_instrs().into_iter().cloned().collect(),
         pipeline_status: simulator.processor.view_pipeline_status(),
+        run_state: worker.state(&simulator),
+        run: worker.progress().clone(),
     }))
 }
 
 #[get("/registers")]
-async fn get_regs(data: web::Data<SimulatorState>) -> impl Responder {
+async fn get_regs(data: SessionData) -> impl Responder {
     let simulator = data.sim.lock().unwrap();
     web::Json(simulator.processor.view_registers())
 }
 
 #[get("/registers/status")]
-async fn get_regs_status(data: web::Data<SimulatorState>) -> impl Responder {
+async fn get_regs_status(data: SessionData) -> impl Responder {
     let simulator = data.sim.lock().unwrap();
     web::Json(simulator.processor.view_register_status())
 }
 
 
 #[get("/memory/size")]
-async fn get_size(data: web::Data<SimulatorState>) -> Result<impl Responder> {
+async fn get_size(data: SessionData) -> Result<impl Responder> {
     let simulator = data.sim.lock().unwrap();
     let size = simulator.memory.lock().unwrap().view_size();
 
     Ok(web::Json(size))
 }
 
+#[get("/memory/stats")]
+async fn get_stats(data: SessionData) -> Result<impl Responder> {
+    let simulator = data.sim.lock().unwrap();
+    let stats: Vec<MemoryStats> = simulator.view_levels().into_iter().map(|(_, stats)| stats).collect();
+
+    Ok(web::Json(stats))
+}
+
 #[get("/memory/line/{line_num}")]
-async fn get_line(path: web::Path<usize>, data: web::Data<SimulatorState>) -> Result<impl Responder> {
+async fn get_line(path: web::Path<usize>, data: SessionData) -> Result<impl Responder> {
     let line_num = path.into_inner();
 
     let simulator = data.sim.lock().unwrap();
@@ -122,8 +311,7 @@ async fn get_line(path: web::Path<usize>, data: web::Data<SimulatorState>) -> Re
 
     let mut returnable: Vec<Vec<Vec<usize>>> = vec![];
     for i in line_num..line_num + 5 {
-        let lines = mem.view_line(i);
-        returnable.push(lines.into_iter().map(|x| x.clone()).collect());
+        returnable.push(mem.view_line(i));
     }
 
     Ok(web::Json(returnable))
@@ -131,43 +319,137 @@ async fn get_line(path: web::Path<usize>, data: web::Data<SimulatorState>) -> Re
 
 
 #[get("/processor/pipeline")]
-async fn get_pipeline(data: web::Data<SimulatorState>) -> Result<impl Responder> {
+async fn get_pipeline(data: SessionData) -> Result<impl Responder> {
     let simulator = data.sim.lock().unwrap();
     let status = simulator.processor.view_pipeline_instrs();
 
-    let status: Vec<Option<simulator::processor::instruction::Instruction>> = status.into_iter().map(|x| x.clone()).collect();
+    let status: Vec<Option<simulator::processor::instruction::Instruction>> = status.into_iter().cloned().collect();
     Ok(web::Json(status))
 }
 
 
+// Instructions kept for the pipeline diagram, and the cycles the text chart shows by
+// default
+const DIAGRAM_INSTRUCTIONS: usize = 10_000;
+const CHART_CYCLES: u64 = 64;
+
+#[derive(Deserialize, Debug)]
+struct DiagramQuery {
+    format: Option<String>,
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+/// The pipeline diagram as a Kanata log for Konata, or with `format=text` as a chart
+/// of the last few cycles
+#[get("/processor/pipeline/diagram")]
+async fn get_pipeline_diagram(query: web::Query<DiagramQuery>, data: SessionData) -> HttpResponse {
+    let simulator = data.sim.lock().unwrap();
+    let Some(diagram) = simulator.pipeline_trace() else {
+        return HttpResponse::NotFound().body("the pipeline isn't being recorded");
+    };
+
+    match query.format.as_deref() {
+        None | Some("kanata") => HttpResponse::Ok().content_type("text/plain").body(diagram.to_kanata()),
+        Some("text") => {
+            // The query parser can't read u128, no run gets anywhere near u64 cycles
+            let to = query.to.map_or(simulator.processor.view_cycles().saturating_sub(1), u128::from);
+            let from = query.from.map_or(to.saturating_sub(CHART_CYCLES as u128 - 1), u128::from);
+            HttpResponse::Ok().content_type("text/plain").body(diagram.to_chart(from, to.max(from)))
+        },
+        Some(format) => HttpResponse::BadRequest().body(format!("unknown diagram format {}", format)),
+    }
+}
+
 #[get("/processor/pipeline/status")]
-async fn get_pipeline_status(data: web::Data<SimulatorState>) -> Result<impl Responder> {
+async fn get_pipeline_status(data: SessionData) -> Result<impl Responder> {
     let simulator = data.sim.lock().unwrap();
     Ok(web::Json(simulator.processor.view_pipeline_status()))
 }
 
 
+#[derive(Serialize, Debug)]
+struct DebugPoints {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+#[get("/breakpoints")]
+async fn get_breakpoints(data: SessionData) -> Result<impl Responder> {
+    let simulator = data.sim.lock().unwrap();
+    Ok(web::Json(DebugPoints {
+        breakpoints: simulator.breakpoints(),
+        watchpoints: simulator.watchpoints(),
+    }))
+}
+
+#[post("/breakpoints")]
+async fn add_breakpoint(breakpoint: web::Json<Breakpoint>, data: SessionData) -> Result<impl Responder> {
+    let mut simulator = data.sim.lock().unwrap();
+    Ok(web::Json(simulator.add_breakpoint(breakpoint.into_inner())))
+}
+
+#[post("/watchpoints")]
+async fn add_watchpoint(watchpoint: web::Json<Watchpoint>, data: SessionData) -> Result<impl Responder> {
+    let mut simulator = data.sim.lock().unwrap();
+    Ok(web::Json(simulator.add_watchpoint(watchpoint.into_inner())))
+}
+
+/// Removes a breakpoint or a watchpoint, they share one set of ids
+#[delete("/breakpoints/{id}")]
+async fn remove_breakpoint(path: web::Path<usize>, data: SessionData) -> HttpResponse {
+    let mut simulator = data.sim.lock().unwrap();
+    match simulator.remove_breakpoint(path.into_inner()) {
+        true => HttpResponse::Ok().body("🦿"),
+        false => HttpResponse::NotFound().finish(),
+    }
+}
+
+
+// Each session is a whole machine, with up to 65536 lines of RAM
+const MAX_SESSIONS: usize = 32;
+const SESSION_IDLE: Duration = Duration::from_secs(30 * 60);
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let sim = web::Data::new(SimulatorState {
-        sim: Mutex::new(simulator::Simulator::new()),
-    });
+    let sessions = web::Data::new(Sessions::new(MAX_SESSIONS, SESSION_IDLE));
 
     HttpServer::new(move || {
         App::new()
-            .app_data(sim.clone())
-            .service(run)
+            .app_data(sessions.clone())
+            .app_data(web::PayloadConfig::new(SNAPSHOT_LIMIT))
+            .service(session::create_session)
+            .service(session::list_sessions)
+            .service(session::current_session)
+            .service(session::delete_session)
+            .service(worker::run)
+            .service(worker::pause)
+            .service(worker::resume)
+            .service(worker::cancel_run)
             .service(step)
+            .service(step_back)
             .service(reset)
             .service(flash)
+            .service(run_exploration)
+            .service(get_config)
+            .service(put_config)
+            .service(get_snapshot)
+            .service(load_snapshot)
             .service(refresh)
+            .service(live::live)
             .service(get_regs_status)
             .service(get_regs)
             .service(get_cycles)
             .service(get_size)
+            .service(get_stats)
             .service(get_line)
             .service(get_pipeline_status)
+            .service(get_pipeline_diagram)
             .service(get_pipeline)
+            .service(get_breakpoints)
+            .service(add_breakpoint)
+            .service(add_watchpoint)
+            .service(remove_breakpoint)
             .service(actix_files::Files::new("/", "./interface/static").show_files_listing())
     })
     .bind(("127.0.0.1", 8080))?
@@ -0,0 +1,186 @@
+use simulator::config::MachineConfig;
+use simulator::Simulator;
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::Payload;
+use actix_web::{delete, error, get, post, web, FromRequest, HttpRequest, HttpResponse, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{SimulatorState, DIAGRAM_INSTRUCTIONS};
+use crate::worker::Worker;
+
+
+// The cookie the page keeps its session in, scripts can send the header instead
+const COOKIE: &str = "ironleg_session";
+const HEADER: &str = "X-Session";
+
+struct Entry {
+    state: Arc<SimulatorState>,
+    created: Instant,
+    last_used: Instant,
+}
+
+/// Every student's simulator, by the token handed out when it was created.  A
+/// session nobody has used for `idle` is dropped, though a run or live socket
+/// already holding it carries on until it's done.
+pub struct Sessions {
+    entries: Mutex<HashMap<String, Entry>>,
+    max: usize,
+    idle: Duration,
+}
+
+impl Sessions {
+    pub fn new(max: usize, idle: Duration) -> Self {
+        Self { entries: Mutex::new(HashMap::new()), max, idle }
+    }
+
+    /// The new session's token, or `None` if there are already as many as allowed
+    fn create(&self, sim: Simulator) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries);
+        if entries.len() >= self.max {
+            return None;
+        }
+
+        let id = Uuid::new_v4().simple().to_string();
+        let state = Arc::new(SimulatorState {
+            sim: Mutex::new(sim),
+            worker: Mutex::new(Worker::default()),
+            program: Mutex::new(vec![]),
+        });
+        let now = Instant::now();
+        entries.insert(id.clone(), Entry { state, created: now, last_used: now });
+        Some(id)
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<SimulatorState>> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries);
+        let entry = entries.get_mut(id)?;
+        entry.last_used = Instant::now();
+        Some(entry.state.clone())
+    }
+
+    fn remove(&self, id: &str) -> bool {
+        self.entries.lock().unwrap().remove(id).is_some()
+    }
+
+    // Ids are left out, anyone holding one can use the session
+    fn list(&self) -> Vec<SessionInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries);
+        let mut list: Vec<SessionInfo> = entries.values().map(|entry| SessionInfo::new(None, entry)).collect();
+        list.sort_by_key(|info| info.age_secs);
+        list
+    }
+
+    fn expire(&self, entries: &mut HashMap<String, Entry>) {
+        entries.retain(|_, entry| entry.last_used.elapsed() < self.idle);
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SessionInfo {
+    // Only ever the caller's own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    age_secs: u64,
+    idle_secs: u64,
+}
+
+impl SessionInfo {
+    fn new(id: Option<&str>, entry: &Entry) -> Self {
+        Self {
+            id: id.map(String::from),
+            age_secs: entry.created.elapsed().as_secs(),
+            idle_secs: entry.last_used.elapsed().as_secs(),
+        }
+    }
+}
+
+fn token(req: &HttpRequest) -> Option<String> {
+    match req.headers().get(HEADER) {
+        Some(header) => header.to_str().ok().map(String::from),
+        None => req.cookie(COOKIE).map(|cookie| cookie.value().to_string()),
+    }
+}
+
+/// The simulator of the session a request belongs to, from its header or cookie
+#[derive(Clone)]
+pub struct SessionData(Arc<SimulatorState>);
+
+impl Deref for SessionData {
+    type Target = SimulatorState;
+
+    fn deref(&self) -> &SimulatorState {
+        &self.0
+    }
+}
+
+impl FromRequest for SessionData {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let sessions = req.app_data::<web::Data<Sessions>>().expect("the sessions are app data");
+        ready(token(req).and_then(|id| sessions.get(&id)).map(SessionData)
+            .ok_or_else(|| error::ErrorUnauthorized("no session, create one with POST /sessions")))
+    }
+}
+
+/// Starts a session with the machine configuration in the body, as JSON, or the
+/// default one if there's no body.  The token comes back in the body and a cookie.
+#[post("/sessions")]
+pub async fn create_session(body: String, sessions: web::Data<Sessions>) -> HttpResponse {
+    let config = match body.trim() {
+        "" => Ok(MachineConfig::default()),
+        text => MachineConfig::from_json(text),
+    };
+    let mut simulator = match config.and_then(|config| Simulator::from_config(&config)) {
+        Ok(simulator) => simulator,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+    simulator.record_pipeline(DIAGRAM_INSTRUCTIONS);
+
+    let Some(id) = sessions.create(simulator) else {
+        return HttpResponse::ServiceUnavailable().body(format!("there are already {} sessions", sessions.max));
+    };
+    HttpResponse::Ok()
+        .cookie(Cookie::build(COOKIE, id.clone()).path("/").http_only(true).finish())
+        .json(&id)
+}
+
+/// How many sessions there are and how long they've been idle, without their ids
+#[get("/sessions")]
+pub async fn list_sessions(sessions: web::Data<Sessions>) -> HttpResponse {
+    HttpResponse::Ok().json(sessions.list())
+}
+
+/// The session the request belongs to, 401 if it has none or it has expired
+#[get("/session")]
+pub async fn current_session(req: HttpRequest, sessions: web::Data<Sessions>) -> HttpResponse {
+    let entries = sessions.entries.lock().unwrap();
+    match token(&req).and_then(|id| entries.get_key_value(&id)) {
+        Some((id, entry)) if entry.last_used.elapsed() < sessions.idle => HttpResponse::Ok().json(SessionInfo::new(Some(id), entry)),
+        _ => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+/// Ends the caller's own session straight away, rather than waiting for it to expire
+#[delete("/sessions/{id}")]
+pub async fn delete_session(req: HttpRequest, path: web::Path<String>, sessions: web::Data<Sessions>) -> HttpResponse {
+    let id = path.into_inner();
+    if token(&req).as_ref() != Some(&id) {
+        return HttpResponse::Forbidden().body("only a session's own token can end it");
+    }
+    match sessions.remove(&id) {
+        true => HttpResponse::Ok().body("🦿"),
+        false => HttpResponse::NotFound().finish(),
+    }
+}
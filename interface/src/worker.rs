@@ -0,0 +1,241 @@
+use simulator::Simulator;
+use simulator::run::{CancelHandle, RunLimits, RunResult, StopReason};
+
+use actix_web::{get, rt, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::SimulatorState;
+use crate::session::SessionData;
+
+
+// Cycles run each time the worker takes the simulator lock, unless whoever started
+// the run asked for another number
+pub const SLICE: u64 = 10_000;
+// A run no longer holds the simulator, but a runaway one still shouldn't spin forever
+const DEFAULT_MAX_CYCLES: u64 = 10_000_000;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    Running,
+    /// Stopped by a pause, a breakpoint or a limit, and can be resumed
+    Paused,
+    Halted,
+    Faulted,
+}
+
+/// The whole run so far, across pauses
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RunProgress {
+    pub cycles: u64,
+    pub instructions: u64,
+    /// Why it last stopped, `None` while it's going
+    pub reason: Option<StopReason>,
+}
+
+/// What's left of a run's limits
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    cycles: Option<u64>,
+    instructions: Option<u64>,
+    time: Option<Duration>,
+}
+
+impl Budget {
+    pub fn new(cycles: Option<u64>, instructions: Option<u64>, time: Option<Duration>) -> Self {
+        Self { cycles: Some(cycles.unwrap_or(DEFAULT_MAX_CYCLES)), instructions, time }
+    }
+
+    fn spend(&mut self, result: &RunResult, elapsed: Duration) {
+        self.cycles = self.cycles.map(|x| x.saturating_sub(result.cycles));
+        self.instructions = self.instructions.map(|x| x.saturating_sub(result.instructions));
+        self.time = self.time.map(|x| x.saturating_sub(elapsed));
+    }
+}
+
+/// The session's run, whether it was started over HTTP or the live socket.  It's the
+/// only thing that runs the simulator for more than a step, and the only holder of
+/// the handle that stops it.
+pub struct Worker {
+    running: bool,
+    // Counts runs started, so a run that ends the way the last one did is still news
+    runs: u64,
+    slice: u64,
+    left: Budget,
+    progress: RunProgress,
+    cancel: CancelHandle,
+    // Ticks after every slice, for the live sockets to send what changed
+    slices: watch::Sender<()>,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self {
+            running: false,
+            runs: 0,
+            slice: SLICE,
+            left: Budget::default(),
+            progress: RunProgress::default(),
+            cancel: CancelHandle::new(),
+            slices: watch::channel(()).0,
+        }
+    }
+}
+
+impl Worker {
+    pub fn state(&self, sim: &Simulator) -> RunState {
+        match sim.stop_reason() {
+            Some(StopReason::Halted) => RunState::Halted,
+            Some(_) => RunState::Faulted,
+            None if self.running => RunState::Running,
+            None => RunState::Paused,
+        }
+    }
+
+    pub fn progress(&self) -> &RunProgress {
+        &self.progress
+    }
+
+    pub fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.slices.subscribe()
+    }
+
+    /// Forgets the run, for a new machine, keeping the sockets listening
+    pub fn reset(&mut self) {
+        self.running = false;
+        self.left = Budget::default();
+        self.progress = RunProgress::default();
+        self.slices.send_replace(());
+    }
+}
+
+/// Starts the run, going on with what's left of the last one unless given a new
+/// budget, and running `slice` cycles each time it takes the simulator
+pub fn start(data: &SessionData, budget: Option<Budget>, slice: u64) -> Result<(), &'static str> {
+    let mut worker = data.worker.lock().unwrap();
+    if worker.running {
+        return Err("the simulator is already running");
+    }
+    if let Some(budget) = budget {
+        worker.left = budget;
+        worker.progress = RunProgress::default();
+    }
+    worker.running = true;
+    worker.runs += 1;
+    worker.slice = slice.max(1);
+    worker.progress.reason = None;
+    worker.cancel = CancelHandle::new();
+
+    rt::spawn(work(data.clone(), worker.cancel.clone()));
+    Ok(())
+}
+
+/// Stops the run at the end of the cycle it's on, leaving it to be resumed
+pub fn stop(data: &SimulatorState) {
+    data.worker.lock().unwrap().cancel.cancel();
+}
+
+async fn work(data: SessionData, cancel: CancelHandle) {
+    loop {
+        let (slice_data, cancel) = (data.clone(), cancel.clone());
+        match web::block(move || slice(&slice_data, cancel)).await {
+            Ok(false) => {},
+            Ok(true) => break,
+            // The slice panicked, so the simulator lock is poisoned anyway
+            Err(_) => {
+                data.worker.lock().unwrap().running = false;
+                break;
+            },
+        }
+    }
+}
+
+// Runs a slice with the simulator locked, returning true once the run is over
+fn slice(data: &SimulatorState, cancel: CancelHandle) -> bool {
+    let (left, slice) = {
+        let worker = data.worker.lock().unwrap();
+        (worker.left, worker.slice)
+    };
+    let limits = RunLimits {
+        max_cycles: Some(left.cycles.map_or(slice, |cycles| cycles.min(slice))),
+        max_instructions: left.instructions,
+        timeout: left.time,
+        cancel: Some(cancel),
+    };
+    let start = Instant::now();
+    let result = data.sim.lock().unwrap().run(&limits);
+
+    let mut worker = data.worker.lock().unwrap();
+    worker.left.spend(&result, start.elapsed());
+    worker.progress.cycles += result.cycles;
+    worker.progress.instructions += result.instructions;
+    // Every slice ends at a cycle limit, the run only when its own is used up
+    let over = result.reason != StopReason::CycleLimit || worker.left.cycles == Some(0);
+    if over {
+        worker.running = false;
+        worker.progress.reason = Some(result.reason);
+    }
+    worker.slices.send_replace(());
+    over
+}
+
+#[derive(Serialize, Debug)]
+struct RunStatus {
+    state: RunState,
+    #[serde(flatten)]
+    progress: RunProgress,
+}
+
+fn status(data: &SimulatorState) -> HttpResponse {
+    let sim = data.sim.lock().unwrap();
+    let worker = data.worker.lock().unwrap();
+    HttpResponse::Ok().json(RunStatus { state: worker.state(&sim), progress: worker.progress.clone() })
+}
+
+fn started(data: &SessionData, result: Result<(), &str>) -> HttpResponse {
+    match result {
+        Ok(()) => status(data),
+        Err(message) => HttpResponse::Conflict().body(message.to_string()),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RunQuery {
+    max_cycles: Option<u64>,
+    max_instructions: Option<u64>,
+    timeout_ms: Option<u64>,
+}
+
+/// Starts a run in the background and returns straight away.  The simulator is only
+/// locked a slice at a time, so the rest of the interface keeps working, and live
+/// sockets are sent what changed after every slice.
+#[get("/run")]
+pub async fn run(query: web::Query<RunQuery>, data: SessionData) -> HttpResponse {
+    let budget = Budget::new(query.max_cycles, query.max_instructions, query.timeout_ms.map(Duration::from_millis));
+    started(&data, start(&data, Some(budget), SLICE))
+}
+
+/// Carries on a paused run with whatever was left of its limits
+#[get("/resume")]
+pub async fn resume(data: SessionData) -> HttpResponse {
+    started(&data, start(&data, None, SLICE))
+}
+
+#[get("/pause")]
+pub async fn pause(data: SessionData) -> HttpResponse {
+    stop(&data);
+    status(&data)
+}
+
+#[get("/run/cancel")]
+pub async fn cancel_run(data: SessionData) -> HttpResponse {
+    stop(&data);
+
+    HttpResponse::Ok().body("🦿")
+}
@@ -45,13 +45,15 @@ async function update_pipeline(pipe_contents, pipe_status) {
     };
 }
 
-async function update_memory(data) {
+async function update_memory(data, levels) {
     const tableSpace = document.getElementById("memory-table-space");
     tableSpace.innerHTML = '';
 
     for (let i = 0; i < data[0].length; i++) {
         const tbl = document.createElement('table');
         tbl.className = "table table-sm table-bordered table-hover";
+        tbl.createCaption().innerHTML = `<b>${levels[data[0].length - i - 1]}</b>`;
+        tbl.caption.style.captionSide = "top";
 
         const tr = tbl.createTHead().insertRow();
         tr.insertCell().innerHTML = "<b>Address</b>";
@@ -78,40 +80,159 @@ async function update_cycles() {
     document.getElementById('cycles-count').innerHTML = `Cycles: ${data}`;
 }
 
+// The live socket, and the page's copy of the machine that its updates are applied to
+let socket = null;
+let state = null;
+
+function memory_line() {
+    const line = document.getElementById('memory-address-box').value;
+    return line ? parseInt(line) : 0;
+}
+
+function describe(reason) {
+    if (typeof reason === 'string') {
+        return reason;
+    }
+    const [kind, detail] = Object.entries(reason)[0];
+    return kind === 'Fault' ? `Fault: ${detail}` : `${Object.keys(detail)[0]} ${Object.values(detail)[0].id}`;
+}
+
+async function render(data) {
+    document.getElementById('cycles-count').innerHTML = `Cycles: ${data.num_cycles}`;
+
+    await update_registers(data.register_values, data.register_status);
+    await update_pipeline(data.pipeline_values, data.pipeline_status);
+    await update_memory(data.memory_contents, data.memory_levels);
+}
+
+// Updates only carry what changed since the last one
+async function apply(update) {
+    state.num_cycles = update.num_cycles;
+    for (const [i, value] of update.register_values ?? []) {
+        state.register_values[i] = value;
+    }
+    for (const [line, contents] of update.memory_lines ?? []) {
+        state.memory[line] = contents;
+    }
+    for (const key of ['register_status', 'pipeline_values', 'pipeline_status', 'memory_stats', 'memory_levels']) {
+        if (key in update) {
+            state[key] = update[key];
+        }
+    }
+
+    if (update.stopped) {
+        document.getElementById('run-status').innerHTML = `${describe(update.stopped)} at cycle ${update.num_cycles}`;
+    } else if (update.running) {
+        document.getElementById('run-status').innerHTML = 'Running';
+    }
+    state.memory_contents = [...Array(6).keys()].map(i => state.memory[state.line + i]);
+    await render(state);
+}
+
+function connect() {
+    socket = new WebSocket(`ws://${location.host}/live?line=${memory_line()}`);
+    socket.onopen = () => {
+        state = {register_values: Array(16).fill(0), memory: {}, line: memory_line()};
+    };
+    socket.onmessage = async (event) => {
+        const message = JSON.parse(event.data);
+        if (message.type === 'update') {
+            await apply(message);
+        } else {
+            document.getElementById('run-status').innerHTML = message.message;
+        }
+    };
+    // Fall back to fetching everything after each action
+    socket.onclose = () => {
+        socket = null;
+    };
+}
+
+function command(message) {
+    if (socket === null || socket.readyState !== WebSocket.OPEN) {
+        return false;
+    }
+    socket.send(JSON.stringify(message));
+    return true;
+}
+
 async function flash() {
     let content = document.getElementById('leg-code').value;
-    await fetch('/flash', {
+    const response = await fetch('/flash', {
         method: 'POST',
         headers: {
             'Content-Type': 'application/json'
         },
         body: JSON.stringify({program: content})
     });
+    if (!response.ok) {
+        document.getElementById('run-status').innerHTML = await response.text();
+    }
     await refresh_ui();
 }
 
 async function refresh_ui() {
-    const line = document.getElementById('memory-address-box').value;
-    console.log(line ? line : '0');
-
-    const response = await fetch('/refresh/' + (line ? line : '0'));
-    const data = await response.json();
-
-    document.getElementById('cycles-count').innerHTML = `Cycles: ${data.num_cycles}`;
+    const line = memory_line();
+    if (command({command: 'view', line})) {
+        state.line = line;
+        return;
+    }
 
-    await update_registers(data.register_values, data.register_status);
-    await update_pipeline(data.pipeline_values, data.pipeline_status);
-    await update_memory(data.memory_contents);
+    const response = await fetch('/refresh/' + line);
+    await render(await response.json());
 }
 
 async function step() {
-    await fetch('/step');
+    if (!command({command: 'step'})) {
+        await fetch('/step');
+        await refresh_ui();
+    }
+}
+
+async function step_back() {
+    await fetch('/step/back');
     await refresh_ui();
 }
 
+function show_run(state, run) {
+    const status = document.getElementById('run-status');
+    if (state === 'running') {
+        status.innerHTML = `Running, ${run.cycles} cycles`;
+    } else if (run.reason) {
+        status.innerHTML = `${describe(run.reason)} after ${run.cycles} cycles`;
+    }
+}
+
+// The live socket is sent every slice of the run, without it the page shows where
+// the run had got to when it was asked
+async function start_run(name) {
+    if (command({command: name})) {
+        return;
+    }
+
+    const response = await fetch('/' + name);
+    if (response.ok) {
+        const data = await response.json();
+        show_run(data.state, data);
+        await refresh_ui();
+    } else {
+        document.getElementById('run-status').innerHTML = await response.text();
+    }
+}
+
 async function run() {
-    await fetch('/run');
-    await refresh_ui();
+    await start_run('run');
+}
+
+async function resume() {
+    await start_run('resume');
+}
+
+async function pause() {
+    if (!command({command: 'pause'})) {
+        await fetch('/pause');
+        await refresh_ui();
+    }
 }
 
 async function reset() {
@@ -119,18 +240,99 @@ async function reset() {
     await refresh_ui();
 }
 
+// The configuration the form was filled from, so fields it doesn't show survive a change
+let machine_config = null;
+const CACHES = ['instruction_cache', 'data_cache'];
+// Defaults for a cache that's switched on from nothing
+const NEW_CACHE = {size: 16384, associativity: 2, latency: 1};
+
+function field(name) {
+    return document.getElementById(`config-${name}`);
+}
+
+async function load_config() {
+    const response = await fetch('/config');
+    machine_config = await response.json();
+
+    for (const name of CACHES) {
+        const cache = machine_config.memory[name];
+        field(`${name}-enabled`).checked = cache !== null;
+        const shown = cache ?? {...NEW_CACHE, replacement: 'lru', write_policy: 'write-back'};
+        for (const key of ['size', 'associativity', 'latency', 'write_policy']) {
+            field(`${name}-${key}`).value = shown[key];
+        }
+        // Random replacement carries its seed
+        field(`${name}-replacement`).value = typeof shown.replacement === 'string' ? shown.replacement : Object.keys(shown.replacement)[0];
+    }
+    field('block_size').value = machine_config.memory.block_size;
+    field('ram_latency').value = machine_config.memory.ram_latency;
+    field('predictor').value = machine_config.pipeline.predictor;
+    field('btb_entries').value = machine_config.pipeline.btb_entries;
+    field('forwarding').checked = machine_config.pipeline.forwarding;
+}
+
+async function apply_config() {
+    const config = structuredClone(machine_config);
+    for (const name of CACHES) {
+        if (!field(`${name}-enabled`).checked) {
+            config.memory[name] = null;
+            continue;
+        }
+        const cache = config.memory[name] ?? {name: name === 'data_cache' ? 'L1D' : 'L1I'};
+        for (const key of ['size', 'associativity', 'latency']) {
+            cache[key] = parseInt(field(`${name}-${key}`).value);
+        }
+        cache.write_policy = field(`${name}-write_policy`).value;
+        const replacement = field(`${name}-replacement`).value;
+        if (replacement !== 'random') {
+            cache.replacement = replacement;
+        } else if (typeof cache.replacement === 'string') {
+            cache.replacement = {random: {seed: 1}};
+        }
+        config.memory[name] = cache;
+    }
+    config.memory.block_size = parseInt(field('block_size').value);
+    config.memory.ram_latency = parseInt(field('ram_latency').value);
+    config.pipeline.predictor = field('predictor').value;
+    config.pipeline.btb_entries = parseInt(field('btb_entries').value);
+    config.pipeline.forwarding = field('forwarding').checked;
+
+    const keep = field('keep-program').checked;
+    const response = await fetch(`/config?keep_program=${keep}`, {
+        method: 'PUT',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify(config)
+    });
+    document.getElementById('config-status').innerHTML = response.ok ? 'Applied' : await response.text();
+    await load_config();
+    await refresh_ui();
+}
+
+// A simulator of our own, unless the cookie still names one that hasn't expired
+async function join() {
+    const response = await fetch('/session');
+    if (!response.ok) {
+        await fetch('/sessions', {method: 'POST'});
+    }
+}
+
 async function main() {
+    await join();
+    await load_config();
     await refresh_ui();
+    connect();
 
     document.getElementById('step-button').onclick = step;
+    document.getElementById('back-button').onclick = step_back;
     document.getElementById('run-button').onclick = run;
+    document.getElementById('pause-button').onclick = pause;
+    document.getElementById('resume-button').onclick = resume;
     document.getElementById('reset-button').onclick = reset;
     document.getElementById('flash-button').onclick = flash;
-    document.getElementById('memory-button').onclick = update_memory;
-
-//     setInterval(async () => {
-//         await update_cycles();
-//     }, 100);
+    document.getElementById('memory-button').onclick = refresh_ui;
+    document.getElementById('config-button').onclick = apply_config;
 }
 
-main();
\ No newline at end of file
+main();
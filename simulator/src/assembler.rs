@@ -1,3 +1,4 @@
+use std::fmt;
 
 use nom::{
     IResult,
@@ -9,7 +10,8 @@ use nom::{
     multi::{many0, many1, separated_list0},
 };
 
-use crate::processor::instruction::{ALUType, AddrMode, ControlType, InstrType, InterruptType, MemoryType};     
+use crate::processor::instruction::{ALUType, AddrMode, ControlType, InstrType, Instruction, InterruptType, MemoryType};
+use crate::processor::stages::decode_fields;
 
 fn parse_alu(input: &str) -> IResult<&str, InstrType> {
     alt((
@@ -57,6 +59,9 @@ fn parse_interrupt(input: &str) -> IResult<&str, InstrType> {
 
 fn parse_regs(input: &str) -> IResult<&str, (AddrMode, u32)> {
     alt((
+        // Longest first, so R10 isn't read as R1 followed by a stray 0
+        value((AddrMode::Reg, 0b01010), tag("R10")),
+        value((AddrMode::Reg, 0b01011), tag("R11")),
         value((AddrMode::Reg, 0b00000), tag("R0")),
         value((AddrMode::Reg, 0b00001), tag("R1")),
         value((AddrMode::Reg, 0b00010), tag("R2")),
@@ -67,12 +72,10 @@ fn parse_regs(input: &str) -> IResult<&str, (AddrMode, u32)> {
         value((AddrMode::Reg, 0b00111), tag("R7")),
         value((AddrMode::Reg, 0b01000), tag("R8")),
         value((AddrMode::Reg, 0b01001), tag("R9")),
-        value((AddrMode::Reg, 0b01010), tag("R10")),
-        value((AddrMode::Reg, 0b01011), tag("R11")),
         value((AddrMode::Reg, 0b01100), tag("SP")),
         value((AddrMode::Reg, 0b01101), tag("BF")),
-        value((AddrMode::Reg, 0b01111), tag("LR")),
-        value((AddrMode::Reg, 0b10000), tag("PC")),
+        value((AddrMode::Reg, 0b01110), tag("LR")),
+        value((AddrMode::Reg, 0b01111), tag("PC")),
     ))(input)
 }
 
@@ -86,7 +89,7 @@ fn parse_hex(input: &str) -> IResult<&str, u32> {
             )
         )
     ),
-    |out: &str| u32::from_str_radix(&str::replace(&out, "_", ""), 16)
+    |out: &str| u32::from_str_radix(&out.replace('_', ""), 16)
     )(input)
 }
 
@@ -101,42 +104,97 @@ fn parse_comma_sep(input: &str) -> IResult<&str, Vec<(AddrMode, u32)>> {
     separated_list0(tag(","), alt((parse_regs, parse_nums)))(input)
 }
 
-fn parse_line(input: &str) -> u32 {
+/// Why a line couldn't be assembled
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    /// Counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+fn parse_line(input: &str) -> Result<u32, String> {
     let input = input.to_ascii_uppercase();
 
-    let (remaining, instr_type) = alt((parse_alu, parse_memory, parse_control, parse_interrupt))(&input).unwrap();
+    let (remaining, instr_type) = alt((parse_alu, parse_memory, parse_control, parse_interrupt))(input.as_str())
+        .map_err(|_| format!("unknown instruction {}", input.split_whitespace().next().unwrap_or_default()))?;
     let remaining: String = remaining.split_whitespace().collect();
 
-    let (_, ops) = parse_comma_sep(&remaining).unwrap();
-    // println!("{:?} {:?}", instr_type, ops);
+    let (rest, ops) = parse_comma_sep(&remaining).map_err(|_| format!("can't read operands {}", remaining))?;
+    if !rest.is_empty() {
+        return Err(format!("can't read operands {}", remaining));
+    }
 
     let mut instr: u32 = match instr_type {
-        InstrType::ALU(opcode) => 0b000 << 29 | (opcode as u32) << 25,
+        InstrType::ALU(opcode) => (opcode as u32) << 25,
         InstrType::Memory(opcode) => 0b001 << 29 | (opcode as u32) << 25,
         InstrType::Control(opcode) => 0b010 << 29 | (opcode as u32) << 25,
         InstrType::Interrupt(opcode) => 0b011 << 29 | (opcode as u32) << 25
     };
+    let immediate = |value: u32, bits: u32| match value < 1 << bits {
+        true => Ok(value),
+        false => Err(format!("{} doesn't fit in {} bits", value, bits)),
+    };
 
-    if ops.len() == 2 {
-        if ops[0].0 == AddrMode::Reg && ops[1].0 == AddrMode::Reg {
-            instr |= 0b000 << 22 | ops[0].1 << 18 | ops[1].1 << 14;
-        }
-        else if ops[0].0 == AddrMode::Reg && ops[1].0 == AddrMode::Imm {
-            instr |= 0b010 << 22 | ops[0].1 << 18 | ops[1].1;
-        }
-    }
+    instr |= match ops[..] {
+        [] => 0,
+        [(AddrMode::Reg, reg_1), (AddrMode::Reg, reg_2)] => reg_1 << 18 | reg_2 << 14,
+        [(AddrMode::Reg, reg), (AddrMode::Imm, value)] => 0b010 << 22 | reg << 18 | immediate(value, 12)?,
+        [(AddrMode::Imm, value)] => 0b011 << 22 | immediate(value, 22)?,
+        [(AddrMode::Reg, reg)] => 0b100 << 22 | reg << 18,
+        _ => return Err(format!("operands {} don't match any addressing mode", remaining)),
+    };
+
+    Ok(instr)
+}
 
-    if ops.len() == 1 {
-        instr |= match ops[0].0 {
-            AddrMode::Imm => 0b011 << 22 | ops[0].1,
-            AddrMode::Reg => 0b100 << 22 | ops[0].1 << 18,
-            _ => panic!("How???")
+/// Assembles a program, one instruction per line.  Blank lines and anything after a
+/// `;` are skipped.
+pub fn try_assemble(input: &str) -> Result<Vec<u32>, AssembleError> {
+    let mut program = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
         }
+        program.push(parse_line(line).map_err(|message| AssembleError { line: i + 1, message })?);
     }
+    Ok(program)
+}
 
-    instr
+/// Assembles a program the way `try_assemble` does, panicking on the first line
+/// that can't be assembled
+pub fn assemble(input: &str) -> Vec<u32> {
+    try_assemble(input).unwrap_or_else(|error| panic!("{}", error))
 }
 
-pub fn assemble(input: &str) -> Vec<u32>{
-    input.split("\n").into_iter().map(|line| parse_line(&line)).collect::<Vec<u32>>()
-}
\ No newline at end of file
+/// Turns a word back into assembly, in the form `assemble` reads.  Words that aren't
+/// legal instructions come out as `.word 0x...`.
+pub fn disassemble(word: u32) -> String {
+    let mut instr = Instruction::new();
+    instr.instr_raw = word as i32;
+    if !decode_fields(&mut instr) {
+        return format!(".word {:#010x}", word);
+    }
+
+    let mnemonic = match instr.instr_type {
+        InstrType::ALU(opcode) => format!("{:?}", opcode),
+        InstrType::Memory(opcode) => format!("{:?}", opcode),
+        InstrType::Control(opcode) => format!("{:?}", opcode),
+        InstrType::Interrupt(opcode) => return format!("{:?}", opcode),
+    };
+    match instr.addr_mode {
+        AddrMode::RegReg => format!("{} {:?}, {:?}", mnemonic, instr.reg_1, instr.reg_2),
+        AddrMode::RegRegOff => format!("{} {:?}, {:?}, {}", mnemonic, instr.reg_1, instr.reg_2, instr.imm),
+        AddrMode::RegImm => format!("{} {:?}, {}", mnemonic, instr.reg_1, instr.imm),
+        AddrMode::Imm => format!("{} {}", mnemonic, instr.imm),
+        AddrMode::Reg => format!("{} {:?}", mnemonic, instr.reg_1),
+    }
+}
@@ -0,0 +1,188 @@
+use crate::Simulator;
+use crate::config::{ConfigError, MachineConfig};
+use crate::history::HistoryConfig;
+use crate::memory::{CacheConfig, Device, HierarchyConfig};
+use crate::processor::{self, PipelineConfig, ResetConfig};
+use crate::processor::pipeline::{StageProcess, StageType};
+use crate::processor::predictor::PredictorKind;
+use crate::processor::registers::Register;
+
+/// Puts a simulator together piece by piece.  Starts from the default machine, and
+/// nothing is checked until `build`, which fails with the same errors a configuration
+/// file would.
+///
+/// ```
+/// use simulator::Simulator;
+/// use simulator::memory::CacheConfig;
+///
+/// let sim = Simulator::builder()
+///     .with_data_cache(CacheConfig::new("L1D", 4096, 4, 1))
+///     .with_shared_cache(CacheConfig::new("L2", 65536, 8, 6))
+///     .with_forwarding(true)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct SimulatorBuilder {
+    config: MachineConfig,
+    stages: Vec<(StageType, StageProcess)>,
+    devices: Vec<(usize, Box<dyn Device>)>,
+    registers: Vec<(Register, i32)>,
+    programs: Vec<(usize, Vec<u32>)>,
+}
+
+impl Default for SimulatorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatorBuilder {
+    pub fn new() -> Self {
+        Self::from_config(MachineConfig::default())
+    }
+
+    pub fn from_config(config: MachineConfig) -> Self {
+        Self {
+            config,
+            stages: processor::default_stages(),
+            devices: vec![],
+            registers: vec![],
+            programs: vec![],
+        }
+    }
+
+    /// Replaces the whole memory hierarchy
+    pub fn with_hierarchy(mut self, hierarchy: HierarchyConfig) -> Self {
+        self.config.memory = hierarchy;
+        self
+    }
+
+    /// Sets the word and block size every level uses, block size is in words
+    pub fn with_geometry(mut self, word_size: usize, block_size: usize) -> Self {
+        self.config.memory.word_size = word_size;
+        self.config.memory.block_size = block_size;
+        self
+    }
+
+    pub fn with_ram(mut self, lines: usize, latency: i32) -> Self {
+        self.config.memory.ram_lines = lines;
+        self.config.memory.ram_latency = latency;
+        self
+    }
+
+    /// `None` sends fetch straight to the shared levels
+    pub fn with_instruction_cache(mut self, cache: impl Into<Option<CacheConfig>>) -> Self {
+        self.config.memory.instruction_cache = cache.into();
+        self
+    }
+
+    /// `None` sends the memory stage straight to the shared levels
+    pub fn with_data_cache(mut self, cache: impl Into<Option<CacheConfig>>) -> Self {
+        self.config.memory.data_cache = cache.into();
+        self
+    }
+
+    /// Adds a level below the L1s, underneath any shared levels added before it
+    pub fn with_shared_cache(mut self, cache: CacheConfig) -> Self {
+        self.config.memory.shared_caches.push(cache);
+        self
+    }
+
+    pub fn with_pipeline(mut self, pipeline: PipelineConfig) -> Self {
+        self.config.pipeline = pipeline;
+        self
+    }
+
+    pub fn with_predictor(mut self, predictor: PredictorKind, btb_entries: usize) -> Self {
+        self.config.pipeline.predictor = predictor;
+        self.config.pipeline.btb_entries = btb_entries;
+        self
+    }
+
+    pub fn with_forwarding(mut self, forwarding: bool) -> Self {
+        self.config.pipeline.forwarding = forwarding;
+        self
+    }
+
+    /// Replaces the stage list.  It has to run from fetch to writeback with the stage
+    /// types in pipeline order, but stages can be repeated or swapped for custom ones.
+    pub fn with_stages(mut self, stages: Vec<(StageType, StageProcess)>) -> Self {
+        self.stages = stages;
+        self
+    }
+
+    /// Maps a device into the data address space at `base`
+    pub fn with_device(mut self, base: usize, device: Box<dyn Device>) -> Self {
+        self.devices.push((base, device));
+        self
+    }
+
+    pub fn with_reset(mut self, pc: i32, sp: i32) -> Self {
+        self.config.reset = ResetConfig { pc, sp };
+        self
+    }
+
+    /// Takes a checkpoint every `interval` cycles and keeps `checkpoints` of them to
+    /// step back with
+    pub fn with_history(mut self, interval: u64, checkpoints: usize) -> Self {
+        self.config.history = HistoryConfig { interval, checkpoints };
+        self
+    }
+
+    /// Starts a register off with a value other than its reset value.  Only applies to
+    /// the freshly built simulator, a reset goes back to the reset values.
+    pub fn with_register(mut self, reg: Register, value: i32) -> Self {
+        self.registers.push((reg, value));
+        self
+    }
+
+    /// Flashes a program into memory once the simulator is built
+    pub fn with_program(mut self, addr: usize, program: &[u32]) -> Self {
+        self.programs.push((addr, program.to_vec()));
+        self
+    }
+
+    pub fn build(self) -> Result<Simulator, ConfigError> {
+        let config = self.config.validated()?;
+        validate_stages(&self.stages)?;
+        validate_devices(&self.devices, config.memory.word_size)?;
+
+        let mut sim = Simulator::build_with(config, &self.stages, self.devices);
+        for (reg, value) in self.registers {
+            sim.processor.set_register(reg, value);
+        }
+        for (addr, program) in self.programs {
+            sim.flash(addr, &program);
+        }
+        Ok(sim)
+    }
+}
+
+fn validate_stages(stages: &[(StageType, StageProcess)]) -> Result<(), ConfigError> {
+    let invalid = |reason: &str| ConfigError::Invalid { field: String::from("stages"), reason: String::from(reason) };
+
+    match (stages.first(), stages.last()) {
+        (Some((StageType::Fetch, _)), Some((StageType::Writeback, _))) => {},
+        _ => return Err(invalid("the pipeline has to start with fetch and end with writeback")),
+    }
+    if stages.windows(2).any(|pair| (pair[0].0 as usize) > (pair[1].0 as usize)) {
+        return Err(invalid("stages have to be listed in pipeline order"));
+    }
+    Ok(())
+}
+
+fn validate_devices(devices: &[(usize, Box<dyn Device>)], word_size: usize) -> Result<(), ConfigError> {
+    for (i, (base, device)) in devices.iter().enumerate() {
+        let invalid = |reason: String| ConfigError::Invalid { field: format!("devices[{}]", i), reason };
+
+        if !base.is_multiple_of(word_size) || device.size() == 0 {
+            return Err(invalid(format!("{} at {:#x} has to be word aligned and take up some space", device.name(), base)));
+        }
+        let overlapping = devices[..i].iter()
+            .find(|(other_base, other)| *base < other_base + other.size() && *other_base < base + device.size());
+        if let Some((other_base, other)) = overlapping {
+            return Err(invalid(format!("{} at {:#x} overlaps {} at {:#x}", device.name(), base, other.name(), other_base)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::HistoryConfig;
+use crate::memory::{CacheConfig, HierarchyConfig};
+use crate::processor::{PipelineConfig, ResetConfig};
+
+/// A complete description of a machine, loaded from a TOML or JSON file so an
+/// experiment can be reproduced without touching any code.  Every section and field
+/// is optional and falls back to the default IronLEG machine, but unknown fields are
+/// rejected so a typo doesn't silently leave a default in place.
+///
+/// ```toml
+/// [memory]
+/// block_size = 16
+/// ram_latency = 20
+///
+/// [memory.data_cache]
+/// size = 4096
+/// associativity = 4
+/// replacement = "lru"
+/// write_policy = "write-through"
+/// allocate_policy = "no-write-allocate"
+/// write_buffer = 4
+///
+/// [[memory.shared_caches]]
+/// name = "L2"
+/// size = 65536
+/// associativity = 8
+/// latency = 6
+/// replacement = { random = { seed = 7 } }
+///
+/// [pipeline]
+/// predictor = "bimodal"
+/// forwarding = true
+///
+/// [reset]
+/// pc = 0
+/// sp = 65532
+///
+/// [history]
+/// checkpoints = 32
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    pub memory: HierarchyConfig,
+    pub pipeline: PipelineConfig,
+    pub reset: ResetConfig,
+    pub history: HistoryConfig,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: String, error: std::io::Error },
+    UnknownFormat(String),
+    Parse(String),
+    /// A field holds a value the machine can't be built with
+    Invalid { field: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f, "couldn't read {}: {}", path, error),
+            ConfigError::UnknownFormat(path) => write!(f, "{} isn't a .toml or .json file", path),
+            ConfigError::Parse(message) => write!(f, "couldn't parse configuration: {}", message),
+            ConfigError::Invalid { field, reason } => write!(f, "invalid value for `{}`: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid(field: &str, reason: String) -> ConfigError {
+    ConfigError::Invalid { field: String::from(field), reason }
+}
+
+impl MachineConfig {
+    /// Reads a configuration file, picking the format from its extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let display = path.display().to_string();
+        let text = fs::read_to_string(path).map_err(|error| ConfigError::Io { path: display.clone(), error })?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => Err(ConfigError::UnknownFormat(display)),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validated()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = serde_json::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validated()
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("machine configuration always serializes")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("machine configuration always serializes")
+    }
+
+    // Caches left unnamed in the file get the usual names for their position
+    pub(crate) fn named(mut self) -> Self {
+        let memory = &mut self.memory;
+        if let Some(cache) = memory.instruction_cache.as_mut().filter(|c| c.name.is_empty()) {
+            cache.name = String::from("L1I");
+        }
+        if let Some(cache) = memory.data_cache.as_mut().filter(|c| c.name.is_empty()) {
+            cache.name = String::from("L1D");
+        }
+        for (i, cache) in memory.shared_caches.iter_mut().enumerate() {
+            if cache.name.is_empty() {
+                cache.name = format!("L{}", i + 2);
+            }
+        }
+        self
+    }
+
+    // Every way of building a machine goes through this, so code-built configs get
+    // the same names and checks a file does
+    pub(crate) fn validated(self) -> Result<Self, ConfigError> {
+        let config = self.named();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that a machine can actually be built from the configuration, naming the
+    /// first field that stops it
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let memory = &self.memory;
+        // Words are read and written as u32s
+        if memory.word_size == 0 || memory.word_size > 4 {
+            return Err(invalid("memory.word_size", format!("{} bytes, must be from 1 to 4", memory.word_size)));
+        }
+        if memory.block_size == 0 {
+            return Err(invalid("memory.block_size", String::from("must be at least 1")));
+        }
+        if memory.ram_lines == 0 {
+            return Err(invalid("memory.ram_lines", String::from("must be at least 1")));
+        }
+        if memory.ram_latency < 1 {
+            return Err(invalid("memory.ram_latency", format!("{} cycles, must be at least 1", memory.ram_latency)));
+        }
+
+        let mut names = HashSet::from([String::from("RAM")]);
+        let levels = memory.instruction_cache.iter().map(|c| (String::from("memory.instruction_cache"), c))
+            .chain(memory.data_cache.iter().map(|c| (String::from("memory.data_cache"), c)))
+            .chain(memory.shared_caches.iter().enumerate().map(|(i, c)| (format!("memory.shared_caches[{}]", i), c)));
+        for (field, cache) in levels {
+            validate_cache(&field, cache, memory)?;
+            if !names.insert(cache.name.clone()) {
+                return Err(invalid(&format!("{}.name", field), format!("\"{}\" is already used by another level", cache.name)));
+            }
+        }
+
+        if self.pipeline.btb_entries == 0 {
+            return Err(invalid("pipeline.btb_entries", String::from("must be at least 1")));
+        }
+
+        if self.history.interval == 0 {
+            return Err(invalid("history.interval", String::from("must be at least 1 cycle")));
+        }
+
+        let ram_bytes = memory.ram_lines * memory.block_size * memory.word_size;
+        if self.reset.pc < 0 || self.reset.pc as usize >= ram_bytes || !(self.reset.pc as usize).is_multiple_of(memory.word_size) {
+            return Err(invalid("reset.pc", format!("{:#x} must be a word aligned address below {:#x}", self.reset.pc, ram_bytes)));
+        }
+        if self.reset.sp < 0 || self.reset.sp as usize > ram_bytes {
+            return Err(invalid("reset.sp", format!("{:#x} is outside the {:#x} bytes of RAM", self.reset.sp, ram_bytes)));
+        }
+        Ok(())
+    }
+}
+
+fn validate_cache(field: &str, cache: &CacheConfig, memory: &HierarchyConfig) -> Result<(), ConfigError> {
+    if cache.name.is_empty() {
+        return Err(invalid(&format!("{}.name", field), String::from("must not be empty")));
+    }
+    if cache.associativity == 0 {
+        return Err(invalid(&format!("{}.associativity", field), String::from("must be at least 1")));
+    }
+    let set_bytes = cache.associativity * memory.block_size * memory.word_size;
+    if cache.size == 0 || !cache.size.is_multiple_of(set_bytes) {
+        return Err(invalid(&format!("{}.size", field), format!(
+            "{} bytes isn't a whole number of {}-way sets of {} byte blocks ({} bytes each)",
+            cache.size, cache.associativity, memory.block_size * memory.word_size, set_bytes
+        )));
+    }
+    if cache.latency < 1 {
+        return Err(invalid(&format!("{}.latency", field), format!("{} cycles, must be at least 1", cache.latency)));
+    }
+    if cache.write_buffer == Some(0) {
+        return Err(invalid(&format!("{}.write_buffer", field), String::from("needs at least 1 entry, leave it out for no buffer")));
+    }
+    Ok(())
+}
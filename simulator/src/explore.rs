@@ -0,0 +1,246 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Simulator;
+use crate::config::{ConfigError, MachineConfig};
+use crate::processor::predictor::PredictorKind;
+use crate::run::{RunLimits, StopReason};
+
+/// The parameters a design space sweep varies.  Every list is one axis and every
+/// combination of them is run, an empty list leaves the parameter as it is in the
+/// base machine.  The cache parameters apply to both L1 caches.
+///
+/// ```toml
+/// cache_size = [1024, 4096, 16384]
+/// associativity = [1, 2, 4]
+/// block_size = [4, 16]
+/// latency = [1, 3]
+/// predictor = ["not-taken", "bimodal"]
+/// forwarding = [false, true]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Grid {
+    pub cache_size: Vec<usize>,
+    pub associativity: Vec<usize>,
+    pub block_size: Vec<usize>,
+    pub latency: Vec<i32>,
+    pub predictor: Vec<PredictorKind>,
+    pub forwarding: Vec<bool>,
+}
+
+/// One combination from a grid, with the base machine's value filled in for any
+/// axis the grid left empty
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub cache_size: usize,
+    pub associativity: usize,
+    pub block_size: usize,
+    pub latency: i32,
+    pub predictor: PredictorKind,
+    pub forwarding: bool,
+}
+
+impl Point {
+    /// The base machine with this point's parameters
+    pub fn apply(&self, base: &MachineConfig) -> MachineConfig {
+        let mut config = base.clone();
+        config.memory.block_size = self.block_size;
+        for cache in config.memory.instruction_cache.iter_mut().chain(config.memory.data_cache.iter_mut()) {
+            cache.size = self.cache_size;
+            cache.associativity = self.associativity;
+            cache.latency = self.latency;
+        }
+        config.pipeline.predictor = self.predictor;
+        config.pipeline.forwarding = self.forwarding;
+        config
+    }
+}
+
+// The axis, or the base value alone when the axis is empty
+fn axis<T: Clone>(values: &[T], base: T) -> Vec<T> {
+    match values.is_empty() {
+        true => vec![base],
+        false => values.to_vec(),
+    }
+}
+
+impl Grid {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    /// How many combinations `points` would list, without listing them.  Saturates
+    /// rather than overflowing, for grids sent by someone else.
+    pub fn point_count(&self) -> usize {
+        [self.cache_size.len(), self.associativity.len(), self.block_size.len(), self.latency.len(), self.predictor.len(), self.forwarding.len()]
+            .into_iter()
+            .fold(1, |count: usize, len| count.saturating_mul(len.max(1)))
+    }
+
+    /// Every combination, in the order the axes are listed with the last one
+    /// changing fastest
+    pub fn points(&self, base: &MachineConfig) -> Vec<Point> {
+        let l1 = base.memory.data_cache.as_ref().or(base.memory.instruction_cache.as_ref()).cloned().unwrap_or_default();
+
+        let mut points = vec![];
+        for cache_size in axis(&self.cache_size, l1.size) {
+            for associativity in axis(&self.associativity, l1.associativity) {
+                for block_size in axis(&self.block_size, base.memory.block_size) {
+                    for latency in axis(&self.latency, l1.latency) {
+                        for predictor in axis(&self.predictor, base.pipeline.predictor) {
+                            for forwarding in axis(&self.forwarding, base.pipeline.forwarding) {
+                                points.push(Point { cache_size, associativity, block_size, latency, predictor, forwarding });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+/// How one point did
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub point: Point,
+    /// Set instead of running when the point doesn't describe a machine that can
+    /// be built, a 3KB 2-way cache for instance
+    pub error: Option<String>,
+    pub stop: Option<StopReason>,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// Cycles per retired instruction
+    pub cpi: f64,
+    /// Every memory level's miss rate, lowest first
+    pub miss_rates: Vec<(String, f64)>,
+}
+
+/// Runs one program on every point of a grid, spread across threads
+#[derive(Clone, Debug)]
+pub struct Exploration {
+    pub base: MachineConfig,
+    pub grid: Grid,
+    pub threads: usize,
+    /// Each run gives up after this many cycles
+    pub max_cycles: u64,
+}
+
+impl Exploration {
+    pub fn new(base: MachineConfig, grid: Grid) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |x| x.get());
+        Self { base, grid, threads, max_cycles: 10_000_000 }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn with_max_cycles(mut self, max_cycles: u64) -> Self {
+        self.max_cycles = max_cycles;
+        self
+    }
+
+    /// Runs every point, returning the outcomes in the order `Grid::points` lists
+    /// them whatever order they finished in
+    pub fn run(&self, program: &[u32]) -> Vec<Outcome> {
+        let points = self.grid.points(&self.base);
+        let next = AtomicUsize::new(0);
+        let outcomes = Mutex::new(vec![None; points.len()]);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(points.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(point) = points.get(i) else { break };
+                    let outcome = self.run_point(point, program);
+                    outcomes.lock().unwrap()[i] = Some(outcome);
+                });
+            }
+        });
+        outcomes.into_inner().unwrap().into_iter().map(|x| x.expect("every point is run")).collect()
+    }
+
+    fn run_point(&self, point: &Point, program: &[u32]) -> Outcome {
+        let mut outcome = Outcome {
+            point: point.clone(),
+            error: None,
+            stop: None,
+            cycles: 0,
+            instructions: 0,
+            cpi: 0.0,
+            miss_rates: vec![],
+        };
+        let mut sim = match Simulator::from_config(&point.apply(&self.base)) {
+            Ok(sim) => sim,
+            Err(error) => {
+                outcome.error = Some(error.to_string());
+                return outcome;
+            },
+        };
+
+        sim.flash(0, program);
+        let result = sim.run(&RunLimits::default().with_max_cycles(self.max_cycles));
+        outcome.stop = Some(result.reason);
+        outcome.cycles = result.cycles;
+        outcome.instructions = result.instructions;
+        outcome.cpi = match result.instructions {
+            0 => 0.0,
+            instructions => result.cycles as f64 / instructions as f64,
+        };
+        outcome.miss_rates = sim.view_levels().into_iter().map(|(name, stats)| (name, stats.miss_rate())).collect();
+        outcome
+    }
+}
+
+// Quoted only when it has to be
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => String::from(field),
+    }
+}
+
+/// One row per outcome, with a miss rate column for each level.  Points that
+/// couldn't be built have the reason in the `stop` column and nothing after it.
+pub fn to_csv(outcomes: &[Outcome]) -> String {
+    let levels: Vec<&str> = outcomes.iter()
+        .find(|x| x.error.is_none())
+        .map_or(vec![], |x| x.miss_rates.iter().map(|(name, _)| name.as_str()).collect());
+
+    let mut csv = String::from("cache_size,associativity,block_size,latency,predictor,forwarding,stop,cycles,instructions,cpi");
+    for level in &levels {
+        write!(csv, ",{}", csv_field(&format!("{}_miss_rate", level))).unwrap();
+    }
+    csv.push('\n');
+
+    for outcome in outcomes {
+        let point = &outcome.point;
+        let predictor = serde_json::to_value(point.predictor).unwrap();
+        write!(csv, "{},{},{},{},{},{}", point.cache_size, point.associativity, point.block_size, point.latency,
+            predictor.as_str().unwrap_or_default(), point.forwarding).unwrap();
+        match (&outcome.error, &outcome.stop) {
+            (Some(error), _) => write!(csv, ",{}", csv_field(&format!("invalid: {}", error))).unwrap(),
+            (None, stop) => {
+                let stop = stop.as_ref().map_or(String::new(), |x| x.to_string());
+                write!(csv, ",{},{},{},{:.4}", csv_field(&stop), outcome.cycles, outcome.instructions, outcome.cpi).unwrap();
+                for level in &levels {
+                    let rate = outcome.miss_rates.iter().find(|(name, _)| name == level).map(|x| x.1);
+                    write!(csv, ",{}", rate.map_or(String::new(), |x| format!("{:.4}", x))).unwrap();
+                }
+            },
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn to_json(outcomes: &[Outcome]) -> String {
+    serde_json::to_string_pretty(outcomes).expect("outcomes always serialize")
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use crate::Simulator;
+use crate::processor::debug::{Access, Breakpoint, DebugEvent, Watchpoint};
+use crate::processor::registers::Register;
+use crate::run::{RunLimits, StopReason};
+
+// Cycles run between checks for an interrupt from the debugger while continuing
+const SLICE: u64 = 4096;
+
+// The largest packet, in bytes, gdb is told it can send.  `qSupported` gives it in hex.
+const PACKET_SIZE: usize = 0x4000;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// The target description gdb reads to learn the registers, in the order `g`
+/// sends them
+pub fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n");
+    xml.push_str("  <feature name=\"org.ironleg.core\">\n");
+    for i in 0..16 {
+        let reg = Register::from_i32(i);
+        let kind = match reg {
+            Register::SP => "data_ptr",
+            Register::LR | Register::PC => "code_ptr",
+            _ => "int",
+        };
+        writeln!(xml, "    <reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>", format!("{:?}", reg).to_lowercase(), kind, i).unwrap();
+    }
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, x| sum.wrapping_add(*x))
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn next_byte(reader: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match reader.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// What to do after a packet
+enum Reply {
+    Send(String),
+    /// Send, then drop the connection
+    Last(String),
+    /// Drop the connection without a word
+    Close,
+}
+
+/// A GDB remote serial protocol server for one simulator.  Registers are sent little
+/// endian in the order `target_xml` lists them, with the PC as the address of the
+/// next instruction to commit.  Breakpoints stop before the instruction retires and
+/// watchpoints once the memory stage has made the access.
+pub struct GdbStub {
+    sim: Simulator,
+    // Ids the simulator gave each breakpoint and watchpoint, by kind and address
+    points: HashMap<(u8, usize), usize>,
+    acks: bool,
+}
+
+impl GdbStub {
+    pub fn new(sim: Simulator) -> Self {
+        Self { sim, points: HashMap::new(), acks: true }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.sim
+    }
+
+    pub fn into_simulator(self) -> Simulator {
+        self.sim
+    }
+
+    /// Talks to one debugger until it detaches, kills the program or hangs up
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.acks = true;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        while let Some(packet) = self.read_packet(&mut reader)? {
+            let reply = self.respond(&packet, &mut || interrupted(&stream));
+            match reply {
+                Reply::Send(text) => self.write_packet(&mut stream, &text)?,
+                Reply::Last(text) => return self.write_packet(&mut stream, &text),
+                Reply::Close => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    // The next packet's contents, or `None` once the debugger has gone.  An interrupt
+    // outside a packet comes back as a packet of its own.
+    fn read_packet(&self, reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>> {
+        loop {
+            let Some(byte) = next_byte(reader)? else { return Ok(None) };
+            match byte {
+                0x03 => return Ok(Some(String::from("\x03"))),
+                b'$' => {},
+                _ => continue,
+            }
+
+            let mut data = vec![];
+            loop {
+                match next_byte(reader)? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b'}') => match next_byte(reader)? {
+                        Some(escaped) => data.push(escaped ^ 0x20),
+                        None => return Ok(None),
+                    },
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut sum = [0; 2];
+            reader.read_exact(&mut sum)?;
+            let valid = std::str::from_utf8(&sum).ok().and_then(|x| u8::from_str_radix(x, 16).ok()) == Some(checksum(&data));
+            if self.acks {
+                reader.get_mut().write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn write_packet(&self, stream: &mut TcpStream, text: &str) -> io::Result<()> {
+        let mut data = vec![];
+        for byte in text.bytes() {
+            match byte {
+                b'#' | b'$' | b'}' | b'*' => data.extend([b'}', byte ^ 0x20]),
+                byte => data.push(byte),
+            }
+        }
+        let mut packet = vec![b'$'];
+        packet.extend(&data);
+        packet.extend(format!("#{:02x}", checksum(&data)).bytes());
+        stream.write_all(&packet)?;
+        stream.flush()
+    }
+
+    fn respond(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Reply {
+        let reply = match packet {
+            "\x03" => format!("S{:02x}", SIGINT),
+            "?" => stop_reply(self.sim.stop_reason().unwrap_or(StopReason::CycleLimit)),
+            "g" => self.read_registers(),
+            "s" => self.resume(RunLimits::default().with_max_instructions(1), interrupted),
+            "c" => self.resume(RunLimits::default(), interrupted),
+            "k" => return Reply::Close,
+            "D" => return Reply::Last(String::from("OK")),
+            "QStartNoAckMode" => {
+                self.acks = false;
+                String::from("OK")
+            },
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            "qSymbol::" => String::from("OK"),
+            _ if packet.starts_with("qSupported") => format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+", PACKET_SIZE),
+            _ if packet.starts_with('H') || packet.starts_with('T') => String::from("OK"),
+            _ => self.respond_with_args(packet).unwrap_or_else(|| String::from("E01")),
+        };
+        Reply::Send(reply)
+    }
+
+    // Packets that carry arguments, `None` when they can't be read or carried out
+    fn respond_with_args(&mut self, packet: &str) -> Option<String> {
+        let (kind, args) = packet.split_at(1);
+        match kind {
+            "G" => self.write_registers(args),
+            "p" => {
+                let values = self.registers();
+                Some(hex_bytes(&values.get(parse_hex(args)?)?.to_le_bytes()))
+            },
+            "P" => {
+                let (reg, value) = args.split_once('=')?;
+                let value = u32::from_le_bytes(parse_hex_bytes(value)?.try_into().ok()?);
+                self.set_register(parse_hex(reg)?, value)
+            },
+            "m" => {
+                let (addr, len) = args.split_once(',')?;
+                let addr = parse_hex(addr)?;
+                // Each byte is two hex digits, and the reply has to fit in a packet
+                let len = parse_hex(len)?.min(PACKET_SIZE / 2);
+                let end = addr.checked_add(len)?;
+                Some(hex_bytes(&(addr..end).map(|x| self.read_byte(x)).collect::<Vec<_>>()))
+            },
+            "M" => {
+                let (addr, data) = args.split_once(':')?;
+                let addr = parse_hex(addr.split_once(',')?.0)?;
+                let bytes = parse_hex_bytes(data)?;
+                addr.checked_add(bytes.len())?;
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    self.write_byte(addr + i, byte);
+                }
+                Some(String::from("OK"))
+            },
+            "Z" | "z" => self.set_point(kind == "Z", args),
+            "q" => {
+                let rest = args.strip_prefix("Xfer:features:read:target.xml:")?;
+                let (offset, len) = rest.split_once(',')?;
+                let xml = target_xml();
+                let start = parse_hex(offset)?.min(xml.len());
+                let end = start.saturating_add(parse_hex(len)?).min(xml.len());
+                let more = if end < xml.len() { 'm' } else { 'l' };
+                Some(format!("{}{}", more, &xml[start..end]))
+            },
+            // Anything else isn't supported, which gdb is told with an empty reply
+            _ => Some(String::new()),
+        }
+    }
+
+    fn registers(&self) -> [i32; 16] {
+        let mut values = self.sim.processor.view_registers();
+        values[Register::PC as usize] = self.sim.next_pc();
+        values
+    }
+
+    fn read_registers(&self) -> String {
+        self.registers().iter().map(|x| hex_bytes(&x.to_le_bytes())).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = parse_hex_bytes(args)?;
+        if bytes.len() != 16 * 4 {
+            return None;
+        }
+        let current = self.registers();
+        for (i, word) in bytes.chunks(4).enumerate() {
+            let value = u32::from_le_bytes(word.try_into().ok()?);
+            // gdb sends every register back, only a changed PC is a problem
+            if value as i32 != current[i] {
+                self.set_register(i, value)?;
+            }
+        }
+        Some(String::from("OK"))
+    }
+
+    // The PC can't be moved without redirecting the pipeline, so it's read only
+    fn set_register(&mut self, reg: usize, value: u32) -> Option<String> {
+        if reg >= Register::PC as usize {
+            return None;
+        }
+        self.sim.processor.set_register(Register::from_i32(reg as i32), value as i32);
+        Some(String::from("OK"))
+    }
+
+    fn read_byte(&self, addr: usize) -> u8 {
+        let word_size = self.sim.config.memory.word_size;
+        let word = self.sim.peek(addr / word_size * word_size);
+        (word >> (8 * (addr % word_size))) as u8
+    }
+
+    fn write_byte(&mut self, addr: usize, byte: u8) {
+        let word_size = self.sim.config.memory.word_size;
+        let aligned = addr / word_size * word_size;
+        let shift = 8 * (addr % word_size);
+        let word = self.sim.peek(aligned) & !(0xFF << shift) | (byte as u32) << shift;
+        self.sim.poke(aligned, word);
+    }
+
+    // `Z0,addr,kind` and friends, the kind being a watchpoint's length
+    fn set_point(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?.parse::<u8>().ok()?;
+        let addr = parse_hex(fields.next()?)?;
+        let len = parse_hex(fields.next()?)?;
+        let end = addr.checked_add(len)?;
+
+        if !insert {
+            let id = self.points.remove(&(kind, addr))?;
+            self.sim.remove_breakpoint(id);
+            return Some(String::from("OK"));
+        }
+        if self.points.contains_key(&(kind, addr)) {
+            return Some(String::from("OK"));
+        }
+        let id = match kind {
+            0 | 1 => self.sim.add_breakpoint(Breakpoint::new(addr as i32)),
+            2 => self.sim.add_watchpoint(Watchpoint::new(addr, end, Access::Write)),
+            3 => self.sim.add_watchpoint(Watchpoint::new(addr, end, Access::Read)),
+            4 => self.sim.add_watchpoint(Watchpoint::new(addr, end, Access::Any)),
+            _ => return Some(String::new()),
+        };
+        self.points.insert((kind, addr), id);
+        Some(String::from("OK"))
+    }
+
+    fn resume(&mut self, limits: RunLimits, interrupted: &mut dyn FnMut() -> bool) -> String {
+        if let Some(reason) = self.sim.stop_reason() {
+            return stop_reply(reason);
+        }
+        // A step is a single instruction, a continue only stops for a reason or an
+        // interrupt, so it runs a slice at a time to check for one
+        if limits.max_instructions.is_some() {
+            return stop_reply(self.sim.run(&limits).reason);
+        }
+        loop {
+            match self.sim.run(&RunLimits::default().with_max_cycles(SLICE)).reason {
+                StopReason::CycleLimit if interrupted() => return format!("S{:02x}", SIGINT),
+                StopReason::CycleLimit => {},
+                reason => return stop_reply(reason),
+            }
+        }
+    }
+}
+
+// The stop reply gdb expects for why the simulator stopped
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Halted => String::from("W00"),
+        StopReason::Fault(_) => format!("S{:02x}", SIGILL),
+        StopReason::Cancelled => format!("S{:02x}", SIGINT),
+        StopReason::Breakpoint(DebugEvent::Breakpoint { .. }) => format!("T{:02x}swbreak:;", SIGTRAP),
+        StopReason::Breakpoint(DebugEvent::Watchpoint { addr, access, .. }) => {
+            let kind = match access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::Any => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+        },
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+// Whether the debugger has sent an interrupt, without waiting for one
+fn interrupted(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0];
+    let result = (&*stream).read(&mut byte);
+    let _ = stream.set_nonblocking(false);
+    // Nothing to read comes back as `WouldBlock`, which isn't an interrupt either
+    matches!(result, Ok(1)) && byte[0] == 0x03
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::MemoryState;
+use crate::processor::pipeline::ProcessorState;
+
+/// How far back the simulator can step.  A checkpoint of the whole machine is taken
+/// every `interval` cycles and stepping back replays forwards from the nearest one,
+/// so history reaches back `interval * checkpoints` cycles and going back costs at
+/// most `interval` cycles of replay.
+///
+/// ```toml
+/// [history]
+/// interval = 1000
+/// checkpoints = 64
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub interval: u64,
+    /// Checkpoints kept, the oldest is dropped once there are more.  0 turns stepping
+    /// back off.
+    pub checkpoints: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { interval: 10_000, checkpoints: 16 }
+    }
+}
+
+/// The whole machine at one cycle.  RAM lines are shared with the running machine
+/// until it writes to them, so taking one is cheap.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub(crate) processor: ProcessorState,
+    pub(crate) memory: MemoryState,
+    // Not saved when fetch and the memory stage share one memory
+    pub(crate) instruction_memory: Option<MemoryState>,
+}
+
+impl Checkpoint {
+    pub fn cycles(&self) -> u128 {
+        self.processor.cycles()
+    }
+
+    pub fn retired(&self) -> u64 {
+        self.processor.retired()
+    }
+
+    /// Whether the two were taken from machines built the same way
+    pub fn same_layout(&self, other: &Checkpoint) -> bool {
+        let instruction_memory = match (&self.instruction_memory, &other.instruction_memory) {
+            (Some(a), Some(b)) => a.same_layout(b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        self.processor.depth() == other.processor.depth()
+            && self.memory.same_layout(&other.memory)
+            && instruction_memory
+    }
+}
+
+/// The checkpoints a simulator has taken, oldest first
+#[derive(Debug, Default)]
+pub struct History {
+    config: HistoryConfig,
+    checkpoints: VecDeque<Checkpoint>,
+}
+
+impl History {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self { config, checkpoints: VecDeque::new() }
+    }
+
+    /// Whether a checkpoint should be taken before running cycle `cycles`
+    pub fn due(&self, cycles: u128) -> bool {
+        if self.config.checkpoints == 0 {
+            return false;
+        }
+        match self.checkpoints.back() {
+            Some(last) => cycles >= last.cycles() + self.config.interval as u128,
+            None => true,
+        }
+    }
+
+    pub fn push(&mut self, checkpoint: Checkpoint) {
+        if self.checkpoints.len() >= self.config.checkpoints {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(checkpoint);
+    }
+
+    /// The newest checkpoint that passes `test`, falling back to the oldest one when
+    /// history doesn't go back far enough
+    pub fn latest(&self, test: impl Fn(&Checkpoint) -> bool) -> Option<&Checkpoint> {
+        self.checkpoints.iter().rev().find(|x| test(x)).or(self.checkpoints.front())
+    }
+
+    /// Forgets every checkpoint taken after cycle `cycles`
+    pub fn truncate_after(&mut self, cycles: u128) {
+        self.checkpoints.retain(|x| x.cycles() <= cycles);
+    }
+
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+    }
+
+    /// The oldest cycle the simulator can step back to
+    pub fn earliest(&self) -> Option<u128> {
+        self.checkpoints.front().map(|x| x.cycles())
+    }
+}
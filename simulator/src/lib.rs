@@ -1,36 +1,425 @@
+use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use crate::processor::pipeline;
-use crate::memory::Memory;
+use crate::processor::debug::{Breakpoint, Watchpoint};
+use crate::processor::pipeline::{self, StageProcess, StageType};
+use crate::memory::{Memory, MemoryStats, MemoryValue, Device, Hierarchy, HierarchyConfig, LineTag};
+use crate::processor::registers::Register;
+use crate::config::{MachineConfig, ConfigError};
+use crate::history::{Checkpoint, History};
+use crate::processor::debug::DebugEvent;
+use crate::processor::diagram::PipelineTrace;
+use crate::processor::trace::CommitFormat;
+use crate::memory::trace::TraceFormat;
+use crate::run::{RunLimits, RunResult, StopReason};
+use crate::snapshot::{Snapshot, SnapshotError};
+
+pub use crate::builder::SimulatorBuilder;
 
 pub mod memory;
 pub mod assembler;
+pub mod builder;
+pub mod config;
+pub mod explore;
+pub mod gdb;
+pub mod history;
 pub mod processor;
+pub mod reference;
+pub mod run;
+pub mod snapshot;
 
 pub struct Simulator {
     pub processor: Box<pipeline::Stage>,
     pub memory: Arc<Mutex<Box<dyn Memory>>>,
+    pub instruction_memory: Arc<Mutex<Box<dyn Memory>>>,
+    pub config: MachineConfig,
+    history: History,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Simulator {
     pub fn new() -> Simulator {
-        let ram = Box::new(memory::RAM::new(65536, 16, 4, 5));
-        let cache = Box::new(memory::Cache::new(16384, 16, 4, 1, 2, ram));
-        let memory: Arc<Mutex<Box<dyn Memory>>> = Arc::new(Mutex::new(cache));
+        Simulator::from_hierarchy(&HierarchyConfig::default())
+    }
+
+    pub fn from_hierarchy(config: &HierarchyConfig) -> Simulator {
+        Simulator::build(MachineConfig { memory: config.clone(), ..MachineConfig::default() })
+    }
+
+    /// Builds the machine a configuration describes, after checking it can be built
+    pub fn from_config(config: &MachineConfig) -> Result<Simulator, ConfigError> {
+        Ok(Simulator::build(config.clone().validated()?))
+    }
+
+    pub fn builder() -> SimulatorBuilder {
+        SimulatorBuilder::new()
+    }
+
+    fn build(config: MachineConfig) -> Simulator {
+        Simulator::build_with(config, &processor::default_stages(), vec![])
+    }
+
+    fn build_with(config: MachineConfig, stages: &[(StageType, StageProcess)], devices: Vec<(usize, Box<dyn Device>)>) -> Simulator {
+        let hierarchy = config.memory.build_with_devices(devices);
+        let processor = processor::build(Arc::clone(&hierarchy.instruction), Arc::clone(&hierarchy.data), &config.pipeline, &config.reset, stages);
+        processor.debugger().lock().unwrap().set_word_size(config.memory.word_size);
 
         Simulator {
-            processor: processor::new(Arc::clone(&memory)),
-            memory: memory,
+            processor,
+            memory: hierarchy.data,
+            instruction_memory: hierarchy.instruction,
+            history: History::new(config.history),
+            config,
+        }
+    }
+
+    /// Runs a single clock cycle.  Returns false, without running anything, once the
+    /// processor has halted.
+    pub fn step(&mut self) -> bool {
+        if self.stop_reason().is_some() {
+            return false;
+        }
+        if self.history.due(self.processor.view_cycles()) {
+            let checkpoint = self.checkpoint();
+            self.history.push(checkpoint);
+        }
+        self.processor.cycle()
+    }
+
+    /// Runs up to `n` cycles, stopping early if the processor halts.  Returns how many
+    /// cycles actually ran.
+    pub fn step_n(&mut self, n: u64) -> u64 {
+        let mut stepped = 0;
+        while stepped < n && self.step() {
+            stepped += 1;
+        }
+        stepped
+    }
+
+    /// Steps until `predicate` holds after a cycle, returning true, or until the
+    /// processor halts, returning false
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&Simulator) -> bool) -> bool {
+        while self.step() {
+            if predicate(self) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Runs until the program halts or faults, or one of `limits` is hit
+    pub fn run(&mut self, limits: &RunLimits) -> RunResult {
+        let start = Instant::now();
+        let start_retired = self.processor.view_retired();
+        let mut cycles = 0;
+        // Anything hit while stepping by hand has already been seen
+        self.processor.debugger().lock().unwrap().take_event();
+
+        let reason = loop {
+            if let Some(reason) = self.stop_reason() {
+                break reason;
+            }
+            if limits.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled()) {
+                break StopReason::Cancelled;
+            }
+            if limits.max_cycles.is_some_and(|max| cycles >= max) {
+                break StopReason::CycleLimit;
+            }
+            if limits.max_instructions.is_some_and(|max| self.processor.view_retired() - start_retired >= max) {
+                break StopReason::InstructionLimit;
+            }
+            // Reading the clock every cycle would cost more than the cycle itself
+            if cycles % 1024 == 0 && limits.timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                break StopReason::Timeout;
+            }
+            if let Some(event) = self.check_retire() {
+                break StopReason::Breakpoint(event);
+            }
+            self.step();
+            cycles += 1;
+            if let Some(event) = self.processor.debugger().lock().unwrap().take_event() {
+                break StopReason::Breakpoint(event);
+            }
+        };
+
+        RunResult {
+            reason,
+            cycles,
+            instructions: self.processor.view_retired() - start_retired,
+        }
+    }
+
+    // Retire breakpoints stop the run before the cycle the instruction would commit in
+    fn check_retire(&self) -> Option<DebugEvent> {
+        let instr = self.processor.view_retiring()?;
+        let registers = self.processor.view_registers();
+        self.processor.debugger().lock().unwrap().check_retire(instr.meta.id, instr.meta.pc, &registers)
+    }
+
+    /// Sets a breakpoint, returning the id it can be removed with
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.processor.debugger().lock().unwrap().add_breakpoint(breakpoint)
+    }
+
+    /// Sets a watchpoint, returning the id it can be removed with
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.processor.debugger().lock().unwrap().add_watchpoint(watchpoint)
+    }
+
+    /// Removes a breakpoint or watchpoint, returning whether the id existed
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        self.processor.debugger().lock().unwrap().remove(id)
+    }
+
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.processor.debugger().lock().unwrap().breakpoints().to_vec()
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.processor.debugger().lock().unwrap().watchpoints().to_vec()
+    }
+
+    /// Why the processor has stopped, or `None` if it can still be stepped
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if self.processor.is_halted() {
+            return Some(StopReason::Halted);
+        }
+        self.processor.view_fault().map(StopReason::Fault)
+    }
+
+    /// Writes a line to `out` for every instruction that commits from now on, see
+    /// `Commit`.  Cycles replayed by `step_back` aren't written again.
+    pub fn log_commits(&mut self, out: impl Write + Send + 'static, format: CommitFormat) {
+        self.processor.tracer().lock().unwrap().log_commits(Box::new(out), format);
+    }
+
+    /// Stops the commit log and flushes it, returning the first error writing it hit
+    pub fn finish_commit_log(&mut self) -> io::Result<()> {
+        self.processor.tracer().lock().unwrap().finish_commits()
+    }
+
+    /// Writes a line to `out` for every memory access fetch or the memory stage
+    /// completes from now on.  Accesses that never complete, because they were
+    /// squashed, aren't written.  Cycles replayed by `step_back` aren't written again.
+    pub fn trace_memory(&mut self, out: impl Write + Send + 'static, format: TraceFormat) {
+        self.processor.tracer().lock().unwrap().log_memory(Box::new(out), format, self.config.memory.word_size);
+    }
+
+    /// Stops the memory trace and flushes it, returning the first error writing it hit
+    pub fn finish_memory_trace(&mut self) -> io::Result<()> {
+        self.processor.tracer().lock().unwrap().finish_memory()
+    }
+
+    /// Starts recording which stage every instruction is in each cycle, for the most
+    /// recent `capacity` instructions, or stops if `capacity` is 0
+    pub fn record_pipeline(&mut self, capacity: usize) {
+        self.processor.tracer().lock().unwrap().record_pipeline(capacity);
+    }
+
+    /// The pipeline diagram recorded so far, if recording is on
+    pub fn pipeline_trace(&self) -> Option<PipelineTrace> {
+        self.processor.tracer().lock().unwrap().pipeline().cloned()
+    }
+
+    // The diagram describes one timeline, anything that jumps the machine elsewhere
+    // starts it again
+    fn clear_pipeline_trace(&self) {
+        if let Some(diagram) = self.processor.tracer().lock().unwrap().pipeline_mut() {
+            diagram.clear();
+        }
+    }
+
+    /// Captures the whole machine, to be put back with `restore`
+    pub fn checkpoint(&self) -> Checkpoint {
+        let unified = Arc::ptr_eq(&self.memory, &self.instruction_memory);
+        Checkpoint {
+            processor: self.processor.save(),
+            memory: self.memory.lock().unwrap().save(),
+            instruction_memory: (!unified).then(|| self.instruction_memory.lock().unwrap().save()),
+        }
+    }
+
+    /// Puts the machine back the way it was when `checkpoint` was taken from this
+    /// simulator.  History starts over from there.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) {
+        self.restore_state(checkpoint);
+        self.history.clear();
+        self.clear_pipeline_trace();
+    }
+
+    fn restore_state(&mut self, checkpoint: &Checkpoint) {
+        self.processor.restore(&checkpoint.processor);
+        self.memory.lock().unwrap().restore(&checkpoint.memory);
+        if let Some(state) = &checkpoint.instruction_memory {
+            self.instruction_memory.lock().unwrap().restore(state);
+        }
+    }
+
+    /// Captures the whole machine along with its configuration, to share or save
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.config.clone(), self.checkpoint())
+    }
+
+    /// Builds the machine a snapshot was taken from and puts it in the saved state.
+    /// Machines with devices or custom stages have to be built first and then use
+    /// `load_snapshot`.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<Simulator, SnapshotError> {
+        let mut sim = Simulator::from_config(&snapshot.config).map_err(SnapshotError::Config)?;
+        sim.load_snapshot(snapshot)?;
+        Ok(sim)
+    }
+
+    /// Puts this machine in a snapshot's state, as long as the snapshot was taken from
+    /// a machine built the same way
+    pub fn load_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.config.memory != self.config.memory || snapshot.config.pipeline != self.config.pipeline {
+            return Err(SnapshotError::Mismatch(String::from("it was taken on a machine with a different configuration")));
+        }
+        if !snapshot.state.same_layout(&self.checkpoint()) {
+            return Err(SnapshotError::Mismatch(String::from("it was taken on a machine with different stages or devices")));
+        }
+        self.restore(&snapshot.state);
+        Ok(())
+    }
+
+    /// Goes back `cycles` cycles, or as far as history reaches, by restoring the
+    /// nearest checkpoint and running forwards again.  Returns how many cycles it
+    /// actually went back.
+    ///
+    /// Only changes made through the simulator are seen by history.  Anything done to
+    /// `processor` or `memory` directly is lost or replayed wrongly.
+    pub fn step_back(&mut self, cycles: u64) -> u64 {
+        let now = self.processor.view_cycles();
+        let target = now.saturating_sub(cycles as u128);
+        let Some(checkpoint) = self.history.latest(|x| x.cycles() <= target).cloned() else { return 0 };
+
+        let target = target.max(checkpoint.cycles());
+        self.replay(&checkpoint, |sim| sim.processor.view_cycles() >= target);
+        (now - self.processor.view_cycles()) as u64
+    }
+
+    /// Goes back to the cycle the instruction `instructions` before the last one to
+    /// retire retired in, or as far as history reaches.  Returns how many
+    /// instructions it actually went back.
+    pub fn step_back_instructions(&mut self, instructions: u64) -> u64 {
+        if instructions == 0 {
+            return 0;
+        }
+        let now = self.processor.view_retired();
+        let target = now.saturating_sub(instructions);
+        // The first cycle with the target retired is wanted, so start from before it
+        let Some(checkpoint) = self.history.latest(|x| x.retired() < target).cloned() else { return 0 };
+
+        let target = target.max(checkpoint.retired());
+        self.replay(&checkpoint, |sim| sim.processor.view_retired() >= target);
+        now - self.processor.view_retired()
+    }
+
+    /// The oldest cycle `step_back` can reach
+    pub fn history_start(&self) -> Option<u128> {
+        self.history.earliest()
+    }
+
+    // The machine is deterministic, and breakpoints don't change its timing, so the
+    // replay retraces exactly what happened the first time
+    fn replay(&mut self, checkpoint: &Checkpoint, mut done: impl FnMut(&Simulator) -> bool) {
+        self.restore_state(checkpoint);
+        self.history.truncate_after(checkpoint.cycles());
+        let tracer = self.processor.tracer();
+        if let Some(diagram) = tracer.lock().unwrap().pipeline_mut() {
+            diagram.truncate_from(checkpoint.cycles());
+        }
+        tracer.lock().unwrap().set_replaying(true);
+        while !done(self) && self.step() {}
+        tracer.lock().unwrap().set_replaying(false);
+        self.processor.debugger().lock().unwrap().take_event();
+    }
+
+    /// Every memory level with its statistics, the data side lowest first and then the
+    /// instruction cache if there is one
+    pub fn view_levels(&self) -> Vec<(String, MemoryStats)> {
+        Hierarchy { instruction: Arc::clone(&self.instruction_memory), data: Arc::clone(&self.memory) }.view_levels()
+    }
+
+    /// Every line in one set of the cache called `name`, with its tag and contents.
+    /// `None` when there's no such cache or the set is out of range.
+    pub fn view_cache_set(&self, name: &str, set: usize) -> Option<Vec<(LineTag, Vec<usize>)>> {
+        let memory = &self.config.memory;
+        let cache = memory.instruction_cache.iter()
+            .chain(&memory.data_cache)
+            .chain(&memory.shared_caches)
+            .find(|x| x.name == name)?;
+        let lines = cache.size / memory.word_size / memory.block_size;
+        if set >= lines / cache.associativity {
+            return None;
+        }
+
+        // The instruction cache only shows up on the instruction side
+        for side in [&self.memory, &self.instruction_memory] {
+            let side = side.lock().unwrap();
+            let Some(level) = side.view_names().iter().position(|x| x == name) else { continue };
+            return (set * cache.associativity..(set + 1) * cache.associativity)
+                .map(|line| Some((side.view_tags(line)[level]?, side.view_line(line).swap_remove(level))))
+                .collect();
         }
+        None
+    }
+
+    /// The word at `addr` as the memory stage would see it, without disturbing any
+    /// cache or counter
+    pub fn peek(&self, addr: usize) -> u32 {
+        self.memory.lock().unwrap().peek(addr) as u32
+    }
+
+    /// Overwrites the word at `addr` in whichever level holds it, without any timing.
+    /// Like `flash` this forgets the history, which can't replay the write.
+    pub fn poke(&mut self, addr: usize, value: u32) {
+        self.memory.lock().unwrap().poke(addr, &MemoryValue::Value(value as usize));
+        self.discard_instructions(addr, 1);
+        self.history.clear();
+    }
+
+    // Writes from outside the pipeline go through the data side, so a split
+    // instruction cache would otherwise go on fetching what was there before
+    fn discard_instructions(&self, addr: usize, words: usize) {
+        if Arc::ptr_eq(&self.memory, &self.instruction_memory) {
+            return;
+        }
+        let word_size = self.config.memory.word_size;
+        let mut instruction_memory = self.instruction_memory.lock().unwrap();
+        for i in 0..words {
+            instruction_memory.discard(addr + i * word_size);
+        }
+    }
+
+    /// The address of the next instruction to commit, where a debugger would say the
+    /// program is.  That's the oldest instruction in flight that hasn't been
+    /// squashed, or the fetch PC when there's none.
+    pub fn next_pc(&self) -> i32 {
+        self.processor.view_pipeline_instrs().into_iter().rev()
+            .flatten()
+            .find(|instr| instr.meta.initialized && !instr.meta.squashed)
+            .map_or(self.processor.view_registers()[Register::PC as usize], |instr| instr.meta.pc)
     }
 
-    pub fn flash(&mut self, addr: usize, program: &Vec<u32>) {
-        let program: Vec<usize> = program.into_iter().map(|x| *x as usize).collect();
+    pub fn flash(&mut self, addr: usize, program: &[u32]) {
+        let program: Vec<usize> = program.iter().map(|x| *x as usize).collect();
         self.memory.lock().unwrap().flash(addr, &program);
+        self.discard_instructions(addr, program.len());
+        self.history.clear();
     }
 
     pub fn reset(&mut self) {
         self.processor.reset();
+        self.instruction_memory.lock().unwrap().reset();
         self.memory.lock().unwrap().reset();
+        self.history.clear();
+        self.clear_pipeline_trace();
     }
 }
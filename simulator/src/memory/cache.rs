@@ -1,5 +1,12 @@
-use super::{Memory, Transparency};
-use super::{MemoryValue, MemoryAccess};    
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Memory, Transparency, LineTag};
+use super::{MemoryValue, MemoryAccess, MemoryStats, MemoryState};
+use super::{blank_line, NO_TAG};
+use super::{Replacement, ReplacementPolicy};
 use crate::processor::pipeline::StageType;
 
 #[derive(Debug)]
@@ -7,19 +14,127 @@ struct CacheLocation {
     offset: usize,
     index: usize,
     tag: usize,
+    block: usize,
+}
+
+/// What happens to the lower level when a cache line is written.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WritePolicy {
+    /// Lines are marked dirty and only written down when they are evicted
+    WriteBack,
+    /// Every write is passed straight through to the lower level as well
+    WriteThrough,
+}
+
+/// Whether a write miss brings the block into the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AllocatePolicy {
+    WriteAllocate,
+    /// Write misses go around the cache to the lower level without filling a line
+    NoWriteAllocate,
+}
+
+/// How a cache's contents relate to the caches above it in the hierarchy.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Inclusion {
+    /// Non-inclusive non-exclusive, no guarantees either way
+    Nine,
+    /// Everything above is also held here.  Evicting a line back-invalidates it in
+    /// every level above
+    Inclusive,
+    /// Nothing above is held here.  Lines move up on a hit and the levels above send
+    /// all of their victims down, clean or dirty
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MissKind {
+    Compulsory,
+    Capacity,
+    Conflict,
+}
+
+/// Shadow state used to break misses down into the "three Cs".  A miss is compulsory
+/// if the block has never been referenced before, a capacity miss if a fully associative
+/// LRU cache with the same number of lines would also have missed, and a conflict miss
+/// otherwise.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct MissClassifier {
+    capacity: usize,
+    seen: BTreeSet<usize>,
+    fully_associative: Vec<usize>,
+}
+
+impl MissClassifier {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: BTreeSet::new(),
+            fully_associative: Vec::with_capacity(capacity),
+        }
+    }
+
+    // Must be called once for every completed access, hit or miss, so the shadow
+    // cache sees the same reference stream as the real one
+    fn classify(&mut self, block: usize) -> MissKind {
+        if self.seen.insert(block) {
+            self.promote(block);
+            return MissKind::Compulsory;
+        }
+        match self.promote(block) {
+            true => MissKind::Conflict,
+            false => MissKind::Capacity,
+        }
+    }
+
+    // Moves the block to the most recently used position, returning whether it was present
+    fn promote(&mut self, block: usize) -> bool {
+        let present = match self.fully_associative.iter().position(|x| *x == block) {
+            Some(position) => {
+                self.fully_associative.remove(position);
+                true
+            },
+            None => {
+                if self.fully_associative.len() >= self.capacity {
+                    self.fully_associative.remove(0);
+                }
+                false
+            }
+        };
+        self.fully_associative.push(block);
+        present
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct CacheLine {
     addr: usize,
     valid: bool,
     dirty: bool,
     tag: usize,
-    uses: usize,
     contents: Vec<usize>,
 }
 
+/// A saved cache and everything below it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheState {
+    access: MemoryAccess,
+    stats: MemoryStats,
+    classifier: MissClassifier,
+    replacement: Vec<u64>,
+    back_invalidations: Vec<usize>,
+    // The block a miss is being serviced for and the line it will go in
+    pending_victim: Option<(usize, usize)>,
+    pending_miss: bool,
+    contents: Vec<CacheLine>,
+    pub(super) lower_level: MemoryState,
+}
+
 pub struct Cache {
+    name: String,
     size: usize,
     num_lines: usize,
     block_size: usize,
@@ -27,6 +142,16 @@ pub struct Cache {
     associativity: usize,
     pub lower_level: Box<dyn Memory>,
     access: MemoryAccess,
+    stats: MemoryStats,
+    classifier: MissClassifier,
+    replacement: Box<dyn ReplacementPolicy>,
+    write_policy: WritePolicy,
+    allocate_policy: AllocatePolicy,
+    inclusion: Inclusion,
+    back_invalidations: Vec<usize>,
+    // The block a miss is being serviced for and the line it will go in
+    pending_victim: Option<(usize, usize)>,
+    pending_miss: bool,
     contents: Vec<CacheLine>,
 }
 
@@ -34,6 +159,7 @@ impl Cache {
     pub fn new(size: usize, block_size: usize, word_size: usize, latency: i32, associativity: usize, lower_level: Box<dyn Memory>) -> Self {
         let num_lines = size / word_size / block_size;
         Self {
+            name: String::from("Cache"),
             size,
             num_lines,
             block_size,
@@ -41,18 +167,54 @@ impl Cache {
             associativity,
             lower_level,
             access: MemoryAccess::new(latency, None),
+            stats: MemoryStats::default(),
+            classifier: MissClassifier::new(num_lines),
+            replacement: Replacement::Decay.build(num_lines / associativity, associativity),
+            write_policy: WritePolicy::WriteBack,
+            allocate_policy: AllocatePolicy::WriteAllocate,
+            inclusion: Inclusion::Nine,
+            back_invalidations: vec![],
+            pending_victim: None,
+            pending_miss: false,
             contents: Cache::create_blank_cache_contents(block_size, num_lines),
         }
     }
 
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = String::from(name);
+        self
+    }
+
+    /// Write-around is write through combined with no write allocate.
+    pub fn with_write_policy(mut self, write_policy: WritePolicy, allocate_policy: AllocatePolicy) -> Self {
+        self.write_policy = write_policy;
+        self.allocate_policy = allocate_policy;
+        self
+    }
+
+    pub fn with_replacement(self, replacement: Replacement) -> Self {
+        let policy = replacement.build(self.num_lines / self.associativity, self.associativity);
+        self.with_replacement_policy(policy)
+    }
+
+    /// Uses a custom policy.  It must already be sized for this cache's sets and ways.
+    pub fn with_replacement_policy(mut self, policy: Box<dyn ReplacementPolicy>) -> Self {
+        self.replacement = policy;
+        self
+    }
+
+    pub fn with_inclusion(mut self, inclusion: Inclusion) -> Self {
+        self.inclusion = inclusion;
+        self
+    }
+
     fn create_blank_cache_contents(block_size: usize, num_lines: usize) -> Vec<CacheLine> {
         vec![CacheLine {
             addr: 0,
             valid: false,
             dirty: false,
             tag: 0,
-            uses: 0,
-            contents: vec![0; block_size],
+            contents: blank_line(block_size),
         }; num_lines]
     }
 
@@ -60,54 +222,47 @@ impl Cache {
         addr / self.word_size * self.word_size
     }
 
+    fn block_addr(&self, addr: usize) -> usize {
+        addr / (self.word_size * self.block_size) * (self.word_size * self.block_size)
+    }
+
     fn cache_location(&self, addr: usize) -> CacheLocation {
         let addr = self.align(addr);
         CacheLocation {
             offset: (addr / self.word_size) % self.block_size,
             index:  (addr / (self.word_size * self.block_size)) * self.associativity % self.num_lines,
             tag:    (addr / (self.size / self.associativity)),
+            block:  addr / (self.word_size * self.block_size),
         }
     }
 
     fn find_line_in_cache(&self, location: &CacheLocation) -> Option<usize> {
-        for i in (location.index)..(location.index + self.associativity) {
-            if self.contents[i].valid && self.contents[i].tag == location.tag {
-                return Some(i); 
-            }
-        }
-        None
+        ((location.index)..(location.index + self.associativity))
+            .find(|&i| self.contents[i].valid && self.contents[i].tag == location.tag)
     }
 
     fn find_line_to_replace(&mut self, location: &CacheLocation) -> usize {
-        // Ideally, evict a line that isn't valid. Only possible for multi-cache processor
-        for i in (location.index)..(location.index + self.associativity) {
-            if !self.contents[i].valid {
-                return i;
+        // A miss can take several cycles to service, stick with the same victim until it's
+        // done.  A victim picked for another block, which never finished, is no use here.
+        if let Some((block, index)) = self.pending_victim {
+            if block == location.block {
+                return index;
             }
         }
 
-        // Otherwise, apply replacement policy
-        self.get_replacement(location)
-    }
-
-    fn get_replacement(&mut self, location: &CacheLocation) -> usize {
-        let mut least_used_index: usize = location.index;
-
-        for i in (location.index)..(location.index + self.associativity) {
+        // Ideally, evict a line that isn't valid. Only possible for multi-cache processor
+        let index = match ((location.index)..(location.index + self.associativity)).find(|&i| !self.contents[i].valid) {
+            Some(index) => index,
 
-            // Add decay so no line gets "stuck" from uses a long time ago
-            self.contents[i].uses = if self.contents[i].uses > 4 { 
-                self.contents[i].uses.ilog2() as usize 
-            } else { 
-                self.contents[i].uses 
-            };
+            // Otherwise, apply replacement policy
+            None => location.index + self.replacement.victim(location.index / self.associativity),
+        };
+        self.pending_victim = Some((location.block, index));
+        index
+    }
 
-            // Return the least used index after decay is applied
-            if self.contents[i].uses < self.contents[least_used_index].uses {
-                least_used_index = i;
-            }
-        }
-        least_used_index
+    fn touch(&mut self, index: usize) {
+        self.replacement.touch(index / self.associativity, index % self.associativity);
     }
 
     fn write_to_lower_level(&mut self, index: usize, stage: StageType) -> bool {
@@ -115,11 +270,65 @@ impl Cache {
         if !self.lower_level.write(self.contents[index].addr, &cloned_value, stage) {
             return false;
         }
+        if self.contents[index].dirty {
+            self.stats.writebacks += 1;
+        }
         self.contents[index].dirty = false;
-        self.contents[index].uses = 0;
         true
     }
 
+    // Gets a line ready to be replaced.  Dirty lines have to be written down first, and an
+    // exclusive lower level takes every victim since it doesn't hold a copy of its own.
+    fn evict(&mut self, index: usize, stage: StageType) -> bool {
+        if !self.contents[index].valid {
+            return true;
+        }
+
+        let write_down = self.contents[index].dirty || self.lower_level.inclusion() == Inclusion::Exclusive;
+        if write_down && !self.write_to_lower_level(index, stage) {
+            return false;
+        }
+
+        self.stats.evictions += 1;
+        if self.inclusion == Inclusion::Inclusive {
+            self.back_invalidations.push(self.block_addr(self.contents[index].addr));
+        }
+        self.invalidate(index);
+        true
+    }
+
+    fn invalidate(&mut self, index: usize) {
+        let cache_line = &mut self.contents[index];
+        cache_line.valid = false;
+        cache_line.dirty = false;
+        cache_line.tag = NO_TAG;
+    }
+
+    // Drops anything an inclusive level below has evicted, and passes the evictions on
+    // up since the levels above this one can't hold the blocks either
+    fn apply_back_invalidations(&mut self) {
+        for addr in self.lower_level.take_back_invalidations() {
+            let location = self.cache_location(addr);
+            if let Some(index) = self.find_line_in_cache(&location) {
+                // The block is gone from the level below, the newest copy goes straight past it
+                if self.contents[index].dirty {
+                    let value = MemoryValue::Line(self.contents[index].contents.clone());
+                    self.lower_level.poke(addr, &value);
+                }
+                self.invalidate(index);
+                self.stats.back_invalidations += 1;
+            }
+            self.back_invalidations.push(addr);
+        }
+    }
+
+    fn fill_line_from_lower_level(&mut self, addr: usize, index: usize, location: &CacheLocation, value: &MemoryValue) {
+        self.insert_value_into_cache(addr, index, location, value);
+        self.contents[index].dirty = false;
+        self.replacement.fill(index / self.associativity, index % self.associativity);
+        self.pending_victim = None;
+    }
+
     fn insert_value_into_cache(&mut self, addr: usize, index: usize, location: &CacheLocation, value: &MemoryValue) {
         let cache_line = &mut self.contents[index];
         match value {
@@ -130,16 +339,40 @@ impl Cache {
         cache_line.valid = true;
         cache_line.dirty = true;
         cache_line.tag = location.tag;
-        cache_line.uses += 1;
     }
 
-}
+    fn complete_write(&mut self, addr: usize, hit: bool) {
+        self.pending_victim = None;
+        self.pending_miss = false;
+        self.record_access(addr, hit, true);
+        self.access.reset_access_state();
+    }
 
-impl Memory for Cache {
-    fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
+    fn record_access(&mut self, addr: usize, hit: bool, is_write: bool) {
+        let kind = self.classifier.classify(self.align(addr) / (self.word_size * self.block_size));
+        let stats = &mut self.stats;
+        match (hit, is_write) {
+            (true, false) => stats.read_hits += 1,
+            (true, true) => stats.write_hits += 1,
+            (false, false) => stats.read_misses += 1,
+            (false, true) => stats.write_misses += 1,
+        }
+        if !hit {
+            match kind {
+                MissKind::Compulsory => stats.compulsory_misses += 1,
+                MissKind::Capacity => stats.capacity_misses += 1,
+                MissKind::Conflict => stats.conflict_misses += 1,
+            }
+        }
+    }
+
+    fn read_value(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
 
         // Apply a delay to simulate the time it would take to access a real cache
-        if !self.access.attempt_access(stage) { return None; }
+        if !self.access.attempt_access(stage) {
+            self.stats.wait_cycles += 1;
+            return None;
+        }
 
         // Location is the tag / offset / line number that the address would occupy if 
         // it is currently in the cache
@@ -147,28 +380,58 @@ impl Memory for Cache {
 
         // First, try to find the requested value in the cache. If it's there, we're done
         if let Some(cache_line_index) = self.find_line_in_cache(&location) {
+
+            // An exclusive cache hands the line up to the level above instead of sharing it,
+            // any changes have to be written down first so they aren't lost
+            let moving_up = line && self.inclusion == Inclusion::Exclusive;
+            if moving_up && self.contents[cache_line_index].dirty && !self.write_to_lower_level(cache_line_index, stage) {
+                self.stats.wait_cycles += 1;
+                return None;
+            }
+
             self.access.reset_access_state();
-            self.contents[cache_line_index].uses += 1;
-            return match line {
-                true => Some(MemoryValue::Line(self.contents[cache_line_index].contents.clone())),
-                false => Some(MemoryValue::Value(self.contents[cache_line_index].contents[location.offset])),
+            self.touch(cache_line_index);
+            self.record_access(addr, true, false);
+            let value = match line {
+                true => MemoryValue::Line(self.contents[cache_line_index].contents.clone()),
+                false => MemoryValue::Value(self.contents[cache_line_index].contents[location.offset]),
+            };
+            if moving_up {
+                self.invalidate(cache_line_index);
             }
+            return Some(value);
         } 
 
+        // Blocks only come in to an exclusive cache as victims from above, so a miss just
+        // passes the lower level's data straight through
+        if line && self.inclusion == Inclusion::Exclusive {
+            return match self.lower_level.read(addr, stage, true) {
+                Some(value) => {
+                    self.record_access(addr, false, false);
+                    self.access.reset_access_state();
+                    Some(value)
+                },
+                None => {
+                    self.stats.wait_cycles += 1;
+                    None
+                }
+            };
+        }
+
         // Data not found in the cache, we'll have to grab it from the lower level of memory 
         let index_to_replace = self.find_line_to_replace(&location);
 
         // Replacement line was previously written to.  It will need to be written down to the lower
         // level before we can replace it.
-        if self.contents[index_to_replace].dirty {
-            if !self.write_to_lower_level(index_to_replace, stage) {
-                return None;
-            }
+        if !self.evict(index_to_replace, stage) {
+            self.stats.wait_cycles += 1;
+            return None;
         }
 
         // Now that the data has been replaced, write the new data into it from the lower level
         if let Some(value) = &self.lower_level.read(addr, stage, true) {
-            self.insert_value_into_cache(addr, index_to_replace, &location, value);
+            self.fill_line_from_lower_level(addr, index_to_replace, &location, value);
+            self.record_access(addr, false, false);
 
             self.access.reset_access_state();
             return match line {
@@ -176,75 +439,211 @@ impl Memory for Cache {
                 false => Some(MemoryValue::Value(self.contents[index_to_replace].contents[location.offset])),
             }
         }
+        self.stats.wait_cycles += 1;
         None
     }
 
-    fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
-        if !self.access.attempt_access(stage) { return false; }
+    fn write_value(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
+        if !self.access.attempt_access(stage) {
+            self.stats.wait_cycles += 1;
+            return false;
+        }
 
         // Location is the tag / offset / line number that the address would occupy if 
         // it is currently in the cache
         let location = self.cache_location(addr);
 
+        // A miss is remembered until the write completes, with write through the line can
+        // already be filled by the time the lower level finishes
+        if self.find_line_in_cache(&location).is_none() {
+            self.pending_miss = true;
+        }
+        let hit = !self.pending_miss;
+
+        // Without write allocate a miss goes around the cache, straight to the lower level
+        if !hit && self.allocate_policy == AllocatePolicy::NoWriteAllocate {
+            if !self.lower_level.write(addr, value, stage) {
+                self.stats.wait_cycles += 1;
+                return false;
+            }
+            self.complete_write(addr, false);
+            return true;
+        }
+
         // Get a place to write to
         let cache_line_index = match self.find_line_in_cache(&location) {
             Some(location) => location,
             None => self.find_line_to_replace(&location),
         };
 
-        // Cache line is dirty and not the same as our current line.  Needs to be 
-        // written to lower level before being overwritten 
-        if self.contents[cache_line_index].dirty && self.contents[cache_line_index].tag != location.tag {
-            if !self.write_to_lower_level(cache_line_index, stage) {
-                return false;
-            }
+        // Cache line holds something else.  It needs to be written to the lower level if it's
+        // dirty before being overwritten 
+        if self.contents[cache_line_index].tag != location.tag && !self.evict(cache_line_index, stage) {
+            self.stats.wait_cycles += 1;
+            return false;
         }
 
-        // Retrieve the most recent data from the lower level and put it into the cache
+        // Retrieve the most recent data from the lower level and put it into the cache.  A whole
+        // line being written doesn't need anything from below
         if self.contents[cache_line_index].tag != location.tag {
-            if let Some(value) = &self.lower_level.read(addr, stage, true) {
-                self.insert_value_into_cache(addr, cache_line_index, &location, value);
-            } else {
-                return false;
+            match value {
+                MemoryValue::Line(_) => self.replacement.fill(cache_line_index / self.associativity, cache_line_index % self.associativity),
+                MemoryValue::Value(_) => match &self.lower_level.read(addr, stage, true) {
+                    Some(value) => self.fill_line_from_lower_level(addr, cache_line_index, &location, value),
+                    None => {
+                        self.stats.wait_cycles += 1;
+                        return false;
+                    }
+                },
             }
         }
 
+        // Write through has to wait for the lower level to take the write as well
+        if self.write_policy == WritePolicy::WriteThrough && !self.lower_level.write(addr, value, stage) {
+            self.stats.wait_cycles += 1;
+            return false;
+        }
+
         // Put the new data into the now free cache line
         self.insert_value_into_cache(addr, cache_line_index, &location, value);
+        self.touch(cache_line_index);
+        if self.write_policy == WritePolicy::WriteThrough {
+            self.contents[cache_line_index].dirty = false;
+        }
 
-        self.access.reset_access_state();
+        self.complete_write(addr, hit);
         true
     }
+}
+
+impl Memory for Cache {
+    fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
+        self.back_invalidations.clear();
+        self.apply_back_invalidations();
+        let value = self.read_value(addr, stage, line);
+        self.apply_back_invalidations();
+        value
+    }
+
+    fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
+        self.back_invalidations.clear();
+        self.apply_back_invalidations();
+        let done = self.write_value(addr, value, stage);
+        self.apply_back_invalidations();
+        done
+    }
+
+    fn poke(&mut self, addr: usize, value: &MemoryValue) {
+        let location = self.cache_location(addr);
+        match self.find_line_in_cache(&location) {
+            Some(index) => {
+                match value {
+                    MemoryValue::Line(val) => self.contents[index].contents = val.clone(),
+                    MemoryValue::Value(val) => self.contents[index].contents[location.offset] = *val,
+                }
+                self.contents[index].dirty = true;
+            },
+            None => self.lower_level.poke(addr, value),
+        }
+    }
+
+    fn peek(&self, addr: usize) -> usize {
+        let location = self.cache_location(addr);
+        match self.find_line_in_cache(&location) {
+            Some(index) => self.contents[index].contents[location.offset],
+            None => self.lower_level.peek(addr),
+        }
+    }
+
+    fn inclusion(&self) -> Inclusion {
+        self.inclusion
+    }
+
+    fn take_back_invalidations(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.back_invalidations)
+    }
+
+    fn shared(&self) -> Option<Arc<Mutex<Box<dyn Memory>>>> {
+        self.lower_level.shared()
+    }
+
+    fn discard(&mut self, addr: usize) {
+        if let Some(index) = self.find_line_in_cache(&self.cache_location(addr)) {
+            self.invalidate(index);
+        }
+        self.lower_level.discard(addr);
+    }
+
+    fn tick(&mut self) {
+        self.back_invalidations.clear();
+        self.lower_level.tick();
+        self.apply_back_invalidations();
+    }
 
     fn reset_state(&mut self) {
         self.lower_level.reset_state();
         self.access.reset_access_state();
+        self.pending_victim = None;
+        self.pending_miss = false;
     }
 
-    fn flash(&mut self, addr: usize, program: &Vec<usize>) {
+    fn flash(&mut self, addr: usize, program: &[usize]) {
         self.lower_level.flash(addr, program);
     }
 
     fn reset(&mut self) {
         self.contents = Cache::create_blank_cache_contents(self.block_size, self.num_lines);
+        self.stats = MemoryStats::default();
+        self.classifier = MissClassifier::new(self.num_lines);
+        self.replacement.reset();
+        self.back_invalidations.clear();
+        self.pending_victim = None;
+        self.pending_miss = false;
         self.lower_level.reset();
     }
+
+    fn save(&self) -> MemoryState {
+        MemoryState::Cache(Box::new(CacheState {
+            access: self.access,
+            stats: self.stats,
+            classifier: self.classifier.clone(),
+            replacement: self.replacement.save(),
+            back_invalidations: self.back_invalidations.clone(),
+            pending_victim: self.pending_victim,
+            pending_miss: self.pending_miss,
+            contents: self.contents.clone(),
+            lower_level: self.lower_level.save(),
+        }))
+    }
+
+    fn restore(&mut self, state: &MemoryState) {
+        let MemoryState::Cache(state) = state else { panic!("saved state isn't from a cache") };
+        self.access = state.access;
+        self.stats = state.stats;
+        self.classifier = state.classifier.clone();
+        self.replacement.restore(&state.replacement);
+        self.back_invalidations = state.back_invalidations.clone();
+        self.pending_victim = state.pending_victim;
+        self.pending_miss = state.pending_miss;
+        self.contents = state.contents.clone();
+        self.lower_level.restore(&state.lower_level);
+    }
 }
 
 impl Transparency for Cache {
-    fn view_line(&self, line_num: usize) -> Vec<&Vec<usize>> {
+    fn view_line(&self, line_num: usize) -> Vec<Vec<usize>> {
         let mut contents = self.lower_level.view_line(line_num);
-        if line_num < self.size {
-            contents.push(&self.contents[line_num].contents);
+        if line_num < self.num_lines {
+            contents.push(self.contents[line_num].contents.clone());
         } else {
-            contents.push(&self.contents[0].contents)
+            contents.push(blank_line(self.block_size))
         }
         contents
     }
 
-    fn view_access(&self) -> Vec<&MemoryAccess> {
+    fn view_access(&self) -> Vec<MemoryAccess> {
         let mut access = self.lower_level.view_access();
-        access.push(&self.access);
+        access.push(self.access);
         access
     }
 
@@ -253,4 +652,27 @@ impl Transparency for Cache {
         size.push(self.size);
         size
     }
+
+    fn view_stats(&self) -> Vec<MemoryStats> {
+        let mut stats = self.lower_level.view_stats();
+        stats.push(self.stats);
+        stats
+    }
+
+    fn view_names(&self) -> Vec<String> {
+        let mut names = self.lower_level.view_names();
+        names.push(self.name.clone());
+        names
+    }
+
+    fn view_tags(&self, line_num: usize) -> Vec<Option<LineTag>> {
+        let mut tags = self.lower_level.view_tags(line_num);
+        tags.push(self.contents.get(line_num).map(|line| LineTag {
+            valid: line.valid,
+            dirty: line.dirty,
+            tag: line.tag,
+            block: self.block_addr(line.addr),
+        }));
+        tags
+    }
 }
@@ -0,0 +1,209 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Memory, Transparency, LineTag};
+use super::{MemoryValue, MemoryAccess, MemoryStats, MemoryState, Inclusion};
+use crate::processor::pipeline::StageType;
+
+/// A memory mapped peripheral.  Devices are uncached and answer every access in the
+/// cycle it is made, addresses are given as a byte offset from the device's base.
+pub trait Device: Send {
+    fn name(&self) -> &str;
+
+    /// Bytes of address space the device takes up
+    fn size(&self) -> usize;
+
+    fn read(&mut self, offset: usize) -> usize;
+
+    fn write(&mut self, offset: usize, value: usize);
+
+    fn reset(&mut self) {}
+
+    /// Whatever the device needs to carry on from where it is now, nothing by default
+    fn save(&self) -> Vec<usize> {
+        vec![]
+    }
+
+    fn restore(&mut self, _state: &[usize]) {}
+}
+
+/// A write only character output.  Every word written to it appends its low byte to
+/// the output, reads always return 0.
+pub struct Console {
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self { output: Arc::new(Mutex::new(vec![])) }
+    }
+
+    /// A handle to everything written so far, still valid once the console has been
+    /// handed over to a simulator
+    pub fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        Arc::clone(&self.output)
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> &str {
+        "Console"
+    }
+
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read(&mut self, _offset: usize) -> usize {
+        0
+    }
+
+    fn write(&mut self, _offset: usize, value: usize) {
+        self.output.lock().unwrap().push(value as u8);
+    }
+
+    fn reset(&mut self) {
+        self.output.lock().unwrap().clear();
+    }
+
+    fn save(&self) -> Vec<usize> {
+        self.output.lock().unwrap().iter().map(|x| *x as usize).collect()
+    }
+
+    fn restore(&mut self, state: &[usize]) {
+        *self.output.lock().unwrap() = state.iter().map(|x| *x as u8).collect();
+    }
+}
+
+/// Sits in front of the data side of the hierarchy and sends any access that falls in
+/// a device's range to the device instead.  Everything else, including every view,
+/// goes straight through to the level underneath.
+pub struct DeviceBus {
+    devices: Vec<(usize, Box<dyn Device>)>,
+    pub lower_level: Box<dyn Memory>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceBusState {
+    pub(super) devices: Vec<Vec<usize>>,
+    pub(super) lower_level: Box<MemoryState>,
+}
+
+impl DeviceBus {
+    pub fn new(devices: Vec<(usize, Box<dyn Device>)>, lower_level: Box<dyn Memory>) -> Self {
+        Self { devices, lower_level }
+    }
+
+    fn device(&mut self, addr: usize) -> Option<(usize, &mut Box<dyn Device>)> {
+        self.devices.iter_mut()
+            .find(|(base, device)| addr >= *base && addr < *base + device.size())
+            .map(|(base, device)| (addr - *base, device))
+    }
+}
+
+impl Memory for DeviceBus {
+    fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
+        match self.device(addr) {
+            Some((offset, device)) => Some(MemoryValue::Value(device.read(offset))),
+            None => self.lower_level.read(addr, stage, line),
+        }
+    }
+
+    fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
+        match (self.device(addr), value) {
+            (Some((offset, device)), MemoryValue::Value(value)) => { device.write(offset, *value); true },
+            (Some(_), MemoryValue::Line(_)) => true,
+            (None, _) => self.lower_level.write(addr, value, stage),
+        }
+    }
+
+    fn poke(&mut self, addr: usize, value: &MemoryValue) {
+        self.lower_level.poke(addr, value);
+    }
+
+    // Reading a device could change it, so peeking only ever sees memory
+    fn peek(&self, addr: usize) -> usize {
+        self.lower_level.peek(addr)
+    }
+
+    fn inclusion(&self) -> Inclusion {
+        self.lower_level.inclusion()
+    }
+
+    fn take_back_invalidations(&mut self) -> Vec<usize> {
+        self.lower_level.take_back_invalidations()
+    }
+
+    fn shared(&self) -> Option<Arc<Mutex<Box<dyn Memory>>>> {
+        self.lower_level.shared()
+    }
+
+    fn discard(&mut self, addr: usize) {
+        self.lower_level.discard(addr);
+    }
+
+    fn tick(&mut self) {
+        self.lower_level.tick();
+    }
+
+    fn reset_state(&mut self) {
+        self.lower_level.reset_state();
+    }
+
+    fn flash(&mut self, addr: usize, program: &[usize]) {
+        self.lower_level.flash(addr, program);
+    }
+
+    fn reset(&mut self) {
+        self.devices.iter_mut().for_each(|(_, device)| device.reset());
+        self.lower_level.reset();
+    }
+
+    fn save(&self) -> MemoryState {
+        MemoryState::Devices(DeviceBusState {
+            devices: self.devices.iter().map(|(_, device)| device.save()).collect(),
+            lower_level: Box::new(self.lower_level.save()),
+        })
+    }
+
+    fn restore(&mut self, state: &MemoryState) {
+        let MemoryState::Devices(state) = state else { panic!("saved state isn't from a device bus") };
+        for ((_, device), saved) in self.devices.iter_mut().zip(&state.devices) {
+            device.restore(saved);
+        }
+        self.lower_level.restore(&state.lower_level);
+    }
+}
+
+impl Transparency for DeviceBus {
+    fn view_line(&self, line_num: usize) -> Vec<Vec<usize>> {
+        self.lower_level.view_line(line_num)
+    }
+
+    fn view_access(&self) -> Vec<MemoryAccess> {
+        self.lower_level.view_access()
+    }
+
+    fn view_size(&self) -> Vec<usize> {
+        self.lower_level.view_size()
+    }
+
+    fn view_stats(&self) -> Vec<MemoryStats> {
+        self.lower_level.view_stats()
+    }
+
+    fn view_names(&self) -> Vec<String> {
+        self.lower_level.view_names()
+    }
+
+    fn view_tags(&self, line_num: usize) -> Vec<Option<LineTag>> {
+        self.lower_level.view_tags(line_num)
+    }
+}
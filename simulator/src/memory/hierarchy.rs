@@ -0,0 +1,157 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Memory, MemoryStats, Cache, RAM, SharedMemory, WriteBuffer, Device, DeviceBus};
+use super::{Replacement, WritePolicy, AllocatePolicy, Inclusion};
+
+/// Everything needed to build one cache level.  Block and word size come from the
+/// hierarchy, every level moves the same sized lines.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub name: String,
+    pub size: usize,
+    pub associativity: usize,
+    pub latency: i32,
+    pub replacement: Replacement,
+    pub write_policy: WritePolicy,
+    pub allocate_policy: AllocatePolicy,
+    pub inclusion: Inclusion,
+    /// Entries in a write buffer between this cache and the level below it
+    pub write_buffer: Option<usize>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self::new("", 16384, 2, 1)
+    }
+}
+
+impl CacheConfig {
+    pub fn new(name: &str, size: usize, associativity: usize, latency: i32) -> Self {
+        Self {
+            name: String::from(name),
+            size,
+            associativity,
+            latency,
+            replacement: Replacement::Decay,
+            write_policy: WritePolicy::WriteBack,
+            allocate_policy: AllocatePolicy::WriteAllocate,
+            inclusion: Inclusion::Nine,
+            write_buffer: None,
+        }
+    }
+
+    pub fn build(&self, block_size: usize, word_size: usize, lower_level: Box<dyn Memory>) -> Cache {
+        let lower_level: Box<dyn Memory> = match self.write_buffer {
+            Some(entries) => Box::new(WriteBuffer::new(entries, block_size, word_size, lower_level)),
+            None => lower_level,
+        };
+        Cache::new(self.size, block_size, word_size, self.latency, self.associativity, lower_level)
+            .with_name(&self.name)
+            .with_replacement(self.replacement)
+            .with_write_policy(self.write_policy, self.allocate_policy)
+            .with_inclusion(self.inclusion)
+    }
+}
+
+/// A whole memory hierarchy, from the L1 caches down to RAM.  With neither an
+/// instruction nor a data cache, fetch and the memory stage share the top of the
+/// shared levels.  Otherwise each side gets its own L1, or goes straight to the
+/// shared levels if its cache is left out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HierarchyConfig {
+    pub word_size: usize,
+    pub block_size: usize,
+    pub ram_lines: usize,
+    pub ram_latency: i32,
+    pub instruction_cache: Option<CacheConfig>,
+    pub data_cache: Option<CacheConfig>,
+    /// Levels below the L1s, listed from the one nearest the processor down to RAM
+    pub shared_caches: Vec<CacheConfig>,
+}
+
+impl Default for HierarchyConfig {
+    fn default() -> Self {
+        Self {
+            word_size: 4,
+            block_size: 16,
+            ram_lines: 65536,
+            ram_latency: 5,
+            instruction_cache: Some(CacheConfig::new("L1I", 16384, 2, 1)),
+            data_cache: Some(CacheConfig::new("L1D", 16384, 2, 1)),
+            shared_caches: vec![],
+        }
+    }
+}
+
+/// The two ways in to a built hierarchy.  They are the same memory when there are
+/// no separate L1 caches.
+pub struct Hierarchy {
+    pub instruction: Arc<Mutex<Box<dyn Memory>>>,
+    pub data: Arc<Mutex<Box<dyn Memory>>>,
+}
+
+impl Hierarchy {
+    /// Every level with its statistics, the data side lowest first and then the
+    /// instruction side's own levels, above what it shares with the data side
+    pub fn view_levels(&self) -> Vec<(String, MemoryStats)> {
+        let data = self.data.lock().unwrap();
+        let mut levels: Vec<(String, MemoryStats)> = data.view_names().into_iter().zip(data.view_stats()).collect();
+        if !Arc::ptr_eq(&self.instruction, &self.data) {
+            let instruction = self.instruction.lock().unwrap();
+            let shared = match (instruction.shared(), data.shared()) {
+                (Some(x), Some(y)) if Arc::ptr_eq(&x, &y) => x.lock().unwrap().view_names().len(),
+                _ => 0,
+            };
+            levels.extend(instruction.view_names().into_iter().zip(instruction.view_stats()).skip(shared));
+        }
+        levels
+    }
+}
+
+impl HierarchyConfig {
+    pub fn build(&self) -> Hierarchy {
+        self.build_with_devices(vec![])
+    }
+
+    /// Builds the hierarchy with memory mapped devices in front of the data side,
+    /// each given as its base address and the device itself
+    pub fn build_with_devices(&self, devices: Vec<(usize, Box<dyn Device>)>) -> Hierarchy {
+        let with_devices = |memory: Box<dyn Memory>| -> Box<dyn Memory> {
+            match devices.is_empty() {
+                true => memory,
+                false => Box::new(DeviceBus::new(devices, memory)),
+            }
+        };
+
+        let mut memory: Box<dyn Memory> = Box::new(RAM::new(self.ram_lines, self.block_size, self.word_size, self.ram_latency));
+        for level in self.shared_caches.iter().rev() {
+            memory = Box::new(level.build(self.block_size, self.word_size, memory));
+        }
+
+        if self.instruction_cache.is_none() && self.data_cache.is_none() {
+            let memory = Arc::new(Mutex::new(with_devices(memory)));
+            return Hierarchy {
+                instruction: Arc::clone(&memory),
+                data: memory,
+            };
+        }
+
+        let shared = SharedMemory::new(memory);
+        let instruction: Box<dyn Memory> = match &self.instruction_cache {
+            Some(level) => Box::new(level.build(self.block_size, self.word_size, Box::new(shared.handle()))),
+            None => Box::new(shared.handle()),
+        };
+        let data: Box<dyn Memory> = match &self.data_cache {
+            Some(level) => Box::new(level.build(self.block_size, self.word_size, Box::new(shared))),
+            None => Box::new(shared),
+        };
+        Hierarchy {
+            instruction: Arc::new(Mutex::new(instruction)),
+            data: Arc::new(Mutex::new(with_devices(data))),
+        }
+    }
+}
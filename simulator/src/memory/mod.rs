@@ -1,18 +1,49 @@
 pub mod ram;
 pub mod cache;
+pub mod device;
+pub mod hierarchy;
+pub mod replacement;
+pub mod shared;
+pub mod trace;
+pub mod write_buffer;
 
-
-pub use self::ram::RAM;
-pub use self::cache::Cache;
+pub use self::ram::{RAM, RamState};
+pub use self::cache::{Cache, CacheState, WritePolicy, AllocatePolicy, Inclusion};
+pub use self::device::{Device, DeviceBus, DeviceBusState, Console};
+pub use self::hierarchy::{CacheConfig, HierarchyConfig, Hierarchy};
+pub use self::shared::{SharedMemory, SharedState};
+pub use self::write_buffer::{WriteBuffer, WriteBufferState};
+pub use self::replacement::{Replacement, ReplacementPolicy};
 pub use crate::processor::pipeline::StageType;
 
-#[derive(Debug, Clone)]
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+// Tag for a line that has been invalidated, so it can never match an address again
+const NO_TAG: usize = usize::MAX;
+
+fn blank_line(block_size: usize) -> Vec<usize> {
+    vec![0; block_size]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MemoryValue {
     Value(usize),
     Line(Vec<usize>),
 }
 
-#[derive(Clone, Copy, Debug)]
+/// A cache line's bookkeeping, everything `view_line` leaves out
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineTag {
+    pub valid: bool,
+    pub dirty: bool,
+    pub tag: usize,
+    /// Address of the first byte of the block the line holds
+    pub block: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct MemoryAccess {
     pub latency: i32,
     pub cycles_to_completion: i32,
@@ -47,16 +78,138 @@ impl MemoryAccess {
     }
 }
 
+/// Traffic counters for a single level of the memory hierarchy.  Accesses are only
+/// counted once they complete, so an access that waits several cycles is still a
+/// single hit or miss; the cycles it spent waiting are counted in `wait_cycles`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub read_hits: u64,
+    pub read_misses: u64,
+    pub write_hits: u64,
+    pub write_misses: u64,
+    pub compulsory_misses: u64,
+    pub capacity_misses: u64,
+    pub conflict_misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+    pub back_invalidations: u64,
+    pub wait_cycles: u64,
+}
+
+impl MemoryStats {
+    pub fn accesses(&self) -> u64 {
+        self.hits() + self.misses()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.read_hits + self.write_hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    pub fn miss_rate(&self) -> f64 {
+        match self.accesses() {
+            0 => 0.0,
+            accesses => self.misses() as f64 / accesses as f64,
+        }
+    }
+}
+
 pub trait Transparency {
-    fn view_line(&self, line_num: usize) -> Vec<&Vec<usize>>;
-    fn view_access(&self) -> Vec<&MemoryAccess>;
+    fn view_line(&self, line_num: usize) -> Vec<Vec<usize>>;
+    fn view_access(&self) -> Vec<MemoryAccess>;
     fn view_size(&self) -> Vec<usize>;
+    fn view_stats(&self) -> Vec<MemoryStats>;
+    fn view_names(&self) -> Vec<String>;
+    /// Every level's tag for a line, lowest first, with `None` for levels that don't
+    /// have tags like RAM
+    fn view_tags(&self, line_num: usize) -> Vec<Option<LineTag>>;
 }
 
 pub trait Memory: Transparency + Send {
     fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue>;
     fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool;
-    fn flash(&mut self, addr: usize, program: &Vec<usize>);
+    fn flash(&mut self, addr: usize, program: &[usize]);
+
+    /// Writes straight into whichever level currently holds the address, without any
+    /// timing.  The write lands in the highest level with a copy and is marked dirty
+    /// there, or goes all the way down to RAM.
+    fn poke(&mut self, addr: usize, value: &MemoryValue);
+
+    /// Reads the newest value of a word without any timing or side effects, from
+    /// whichever level holds the freshest copy
+    fn peek(&self, addr: usize) -> usize;
+
+    /// The relationship this level keeps with the levels above it.
+    fn inclusion(&self) -> Inclusion {
+        Inclusion::Nine
+    }
+
+    /// Block addresses the levels above have to drop to keep an inclusive level below
+    /// them inclusive.  Only valid straight after a call in to this level.
+    fn take_back_invalidations(&mut self) -> Vec<usize> {
+        vec![]
+    }
+
+    /// The shared memory this level reaches down to, if there is one
+    fn shared(&self) -> Option<Arc<Mutex<Box<dyn Memory>>>> {
+        None
+    }
+
+    /// Drops any copy of the block holding `addr` from this level and the ones below it
+    /// only it uses, for when the memory changed without going through them.  Shared
+    /// levels are left alone, the change went through them already.
+    fn discard(&mut self, _addr: usize) {}
+
+    /// Called once every processor cycle so a level can make progress on work nobody
+    /// is waiting on, like draining a write buffer.
+    fn tick(&mut self) {}
+
     fn reset_state(&mut self);
     fn reset(&mut self);
+
+    /// Captures everything about this level and the levels below it that changes as
+    /// the machine runs, contents, statistics and accesses in flight
+    fn save(&self) -> MemoryState;
+
+    /// Puts back a state saved from a hierarchy built the same way.  Panics if the
+    /// state came from a different kind of level.
+    fn restore(&mut self, state: &MemoryState);
+}
+
+/// A saved level of memory, holding the states of the levels below it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MemoryState {
+    Ram(RamState),
+    Cache(Box<CacheState>),
+    WriteBuffer(WriteBufferState),
+    Shared(SharedState),
+    Devices(DeviceBusState),
+}
+
+impl MemoryState {
+    fn lower_level(&self) -> Option<&MemoryState> {
+        match self {
+            MemoryState::Ram(_) => None,
+            MemoryState::Cache(state) => Some(&state.lower_level),
+            MemoryState::WriteBuffer(state) => Some(&state.lower_level),
+            MemoryState::Shared(state) => state.memory.as_deref(),
+            MemoryState::Devices(state) => Some(&state.lower_level),
+        }
+    }
+
+    /// Whether both states were saved from hierarchies with the same kinds of level
+    /// stacked in the same order, so one can be restored where the other came from
+    pub fn same_layout(&self, other: &MemoryState) -> bool {
+        let same_level = match (self, other) {
+            (MemoryState::Devices(a), MemoryState::Devices(b)) => a.devices.len() == b.devices.len(),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        };
+        same_level && match (self.lower_level(), other.lower_level()) {
+            (Some(a), Some(b)) => a.same_layout(b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
 }
@@ -1,6 +1,9 @@
+use std::sync::Arc;
 
-use super::{Memory, Transparency};
-use super::{MemoryValue, MemoryAccess};
+use serde::{Deserialize, Serialize};
+
+use super::{Memory, Transparency, LineTag};
+use super::{MemoryValue, MemoryAccess, MemoryStats, MemoryState};
 use crate::processor::pipeline::StageType;
 
 pub struct RAM {
@@ -8,20 +11,75 @@ pub struct RAM {
     block_size: usize,
     word_size: usize,
     access: MemoryAccess,
-    contents: Vec<Vec<usize>>,
+    stats: MemoryStats,
+    // Lines are shared with saved states until they're written, so saving all of RAM
+    // only costs a reference per line
+    contents: Vec<Arc<Vec<usize>>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RamState {
+    access: MemoryAccess,
+    stats: MemoryStats,
+    contents: Lines,
+}
+
+/// RAM's lines, written out with only the ones that aren't all zeros so a saved
+/// state isn't mostly empty memory
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "SparseLines", from = "SparseLines")]
+struct Lines(Vec<Arc<Vec<usize>>>);
+
+#[derive(Serialize, Deserialize)]
+struct SparseLines {
+    lines: usize,
+    block_size: usize,
+    used: Vec<(usize, Vec<usize>)>,
+}
+
+impl From<Lines> for SparseLines {
+    fn from(lines: Lines) -> Self {
+        Self {
+            lines: lines.0.len(),
+            block_size: lines.0.first().map_or(0, |line| line.len()),
+            used: lines.0.iter().enumerate()
+                .filter(|(_, line)| line.iter().any(|x| *x != 0))
+                .map(|(i, line)| (i, line.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl From<SparseLines> for Lines {
+    fn from(sparse: SparseLines) -> Self {
+        let mut contents = RAM::blank_contents(sparse.block_size, sparse.lines);
+        for (i, line) in sparse.used {
+            if i < contents.len() {
+                contents[i] = Arc::new(line);
+            }
+        }
+        Lines(contents)
+    }
 }
 
 impl RAM {
     pub fn new(size: usize, block_size: usize, word_size: usize, latency: i32) -> Self {
         Self {
-            size: size,
-            block_size: block_size,
-            word_size: word_size,
+            size,
+            block_size,
+            word_size,
             access: MemoryAccess::new(latency, None),
-            contents: vec![vec![0; block_size]; size],
+            stats: MemoryStats::default(),
+            contents: RAM::blank_contents(block_size, size),
         }
     }
 
+    // Every line starts out sharing the same blank block
+    fn blank_contents(block_size: usize, size: usize) -> Vec<Arc<Vec<usize>>> {
+        let blank = Arc::new(vec![0; block_size]);
+        vec![blank; size]
+    }
+
     fn align(&self, addr: usize) -> usize {
         ((addr % self.size) / self.word_size) * self.word_size
     }
@@ -35,59 +93,108 @@ impl RAM {
 
 impl Memory for RAM {
     fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
-        if !self.access.attempt_access(stage) { return None; }
+        if !self.access.attempt_access(stage) {
+            self.stats.wait_cycles += 1;
+            return None;
+        }
         self.access.reset_access_state();
+        self.stats.read_hits += 1;
 
         let addr = self.addr_to_offset(addr);
         match line {
-            true => Some(MemoryValue::Line(self.contents[addr.0].clone())),
+            true => Some(MemoryValue::Line(self.contents[addr.0].to_vec())),
             false => Some(MemoryValue::Value(self.contents[addr.0][addr.1])),
         }
     }
 
     fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
-        if !self.access.attempt_access(stage) { return false; }
+        if !self.access.attempt_access(stage) {
+            self.stats.wait_cycles += 1;
+            return false;
+        }
         self.access.reset_access_state();
+        self.stats.write_hits += 1;
 
         let addr = self.addr_to_offset(addr);
         match value {
-            MemoryValue::Line(val) => self.contents[addr.0] = val.clone(),
-            MemoryValue::Value(val) => self.contents[addr.0][addr.1] = *val,
+            MemoryValue::Line(val) => self.contents[addr.0] = Arc::new(val.clone()),
+            MemoryValue::Value(val) => Arc::make_mut(&mut self.contents[addr.0])[addr.1] = *val,
         }
         true
     }
 
+    fn poke(&mut self, addr: usize, value: &MemoryValue) {
+        let addr = self.addr_to_offset(addr);
+        match value {
+            MemoryValue::Line(val) => self.contents[addr.0] = Arc::new(val.clone()),
+            MemoryValue::Value(val) => Arc::make_mut(&mut self.contents[addr.0])[addr.1] = *val,
+        }
+    }
+
+    fn peek(&self, addr: usize) -> usize {
+        let addr = self.addr_to_offset(addr);
+        self.contents[addr.0][addr.1]
+    }
+
     fn reset_state(&mut self) {
         self.access.reset_access_state();
     }
 
-    fn flash(&mut self, addr: usize, program: &Vec<usize>) {
+    fn flash(&mut self, addr: usize, program: &[usize]) {
         let addr = self.align(addr);
         for i in (0..(program.len() * 4)).step_by(4) {
             let addr = self.addr_to_offset(addr + i);
-            self.contents[addr.0][addr.1] = program[i / 4];
+            Arc::make_mut(&mut self.contents[addr.0])[addr.1] = program[i / 4];
         }
     }
 
     fn reset(&mut self) {
-        self.contents = vec![vec![0; self.block_size]; self.size];
+        self.contents = RAM::blank_contents(self.block_size, self.size);
+        self.stats = MemoryStats::default();
+    }
+
+    fn save(&self) -> MemoryState {
+        MemoryState::Ram(RamState {
+            access: self.access,
+            stats: self.stats,
+            contents: Lines(self.contents.clone()),
+        })
+    }
+
+    fn restore(&mut self, state: &MemoryState) {
+        let MemoryState::Ram(state) = state else { panic!("saved state isn't from RAM") };
+        self.access = state.access;
+        self.stats = state.stats;
+        self.contents = state.contents.0.clone();
     }
 }
 
 impl Transparency for RAM {
-    fn view_line(&self, line_num: usize) -> Vec<&Vec<usize>> {
+    fn view_line(&self, line_num: usize) -> Vec<Vec<usize>> {
         vec![if line_num < self.size {
-            &self.contents[line_num]
+            self.contents[line_num].to_vec()
         } else {
-            &self.contents[0]
+            self.contents[0].to_vec()
         }]
     }
 
-    fn view_access(&self) -> Vec<&MemoryAccess> {
-        vec![&self.access]
+    fn view_access(&self) -> Vec<MemoryAccess> {
+        vec![self.access]
     }
 
     fn view_size(&self) -> Vec<usize> {
         vec![self.size]
     }
+
+    fn view_stats(&self) -> Vec<MemoryStats> {
+        vec![self.stats]
+    }
+
+    fn view_names(&self) -> Vec<String> {
+        vec![String::from("RAM")]
+    }
+
+    fn view_tags(&self, _line_num: usize) -> Vec<Option<LineTag>> {
+        vec![None]
+    }
 }
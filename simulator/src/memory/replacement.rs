@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+
+/// Decides which way of a set to evict once every way holds a valid line.  Invalid
+/// ways are always filled first by the cache itself, so a policy is only consulted
+/// for a real replacement.
+pub trait ReplacementPolicy: Send {
+    /// A resident line was read or written
+    fn touch(&mut self, set: usize, way: usize);
+
+    /// A new block was brought into the way from the lower level
+    fn fill(&mut self, set: usize, way: usize);
+
+    /// Picks the way to evict from a full set
+    fn victim(&mut self, set: usize) -> usize;
+
+    fn reset(&mut self);
+
+    /// Everything the policy has learned so far, flattened into a list of numbers
+    fn save(&self) -> Vec<u64>;
+
+    /// Puts back a list from `save` on a policy built for the same sets and ways
+    fn restore(&mut self, state: &[u64]);
+}
+
+/// The built in replacement policies, used to pick one when a cache is constructed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random { seed: u64 },
+    TreePlru,
+    Lfu,
+    Decay,
+}
+
+impl Replacement {
+    pub fn build(&self, sets: usize, ways: usize) -> Box<dyn ReplacementPolicy> {
+        match *self {
+            Replacement::Lru => Box::new(Lru::new(sets, ways)),
+            Replacement::Fifo => Box::new(Fifo::new(sets, ways)),
+            Replacement::Random { seed } => Box::new(Random::new(ways, seed)),
+            Replacement::TreePlru => Box::new(TreePlru::new(sets, ways)),
+            Replacement::Lfu => Box::new(Lfu::new(sets, ways)),
+            Replacement::Decay => Box::new(Decay::new(sets, ways)),
+        }
+    }
+}
+
+/// True least recently used, tracked with a per line timestamp.
+pub struct Lru {
+    ways: usize,
+    clock: u64,
+    last_used: Vec<u64>,
+}
+
+impl Lru {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self { ways, clock: 0, last_used: vec![0; sets * ways] }
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.last_used[set * self.ways + way] = self.clock;
+    }
+
+    fn fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        oldest(&self.last_used[set * self.ways..(set + 1) * self.ways])
+    }
+
+    fn reset(&mut self) {
+        self.clock = 0;
+        self.last_used.iter_mut().for_each(|x| *x = 0);
+    }
+
+    fn save(&self) -> Vec<u64> {
+        let mut state = vec![self.clock];
+        state.extend_from_slice(&self.last_used);
+        state
+    }
+
+    fn restore(&mut self, state: &[u64]) {
+        self.clock = state[0];
+        self.last_used.copy_from_slice(&state[1..]);
+    }
+}
+
+/// First in first out.  Hits do not change the order lines will be evicted in.
+pub struct Fifo {
+    ways: usize,
+    clock: u64,
+    filled: Vec<u64>,
+}
+
+impl Fifo {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self { ways, clock: 0, filled: vec![0; sets * ways] }
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+
+    fn fill(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.filled[set * self.ways + way] = self.clock;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        oldest(&self.filled[set * self.ways..(set + 1) * self.ways])
+    }
+
+    fn reset(&mut self) {
+        self.clock = 0;
+        self.filled.iter_mut().for_each(|x| *x = 0);
+    }
+
+    fn save(&self) -> Vec<u64> {
+        let mut state = vec![self.clock];
+        state.extend_from_slice(&self.filled);
+        state
+    }
+
+    fn restore(&mut self, state: &[u64]) {
+        self.clock = state[0];
+        self.filled.copy_from_slice(&state[1..]);
+    }
+}
+
+/// Uniformly random eviction.  Uses a seeded splitmix64 generator so the same seed
+/// always produces the same sequence of victims for the same trace.
+pub struct Random {
+    ways: usize,
+    seed: u64,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(ways: usize, seed: u64) -> Self {
+        Self { ways, seed, state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+
+    fn fill(&mut self, _set: usize, _way: usize) {}
+
+    fn victim(&mut self, _set: usize) -> usize {
+        (self.next() % self.ways as u64) as usize
+    }
+
+    fn reset(&mut self) {
+        self.state = self.seed;
+    }
+
+    fn save(&self) -> Vec<u64> {
+        vec![self.state]
+    }
+
+    fn restore(&mut self, state: &[u64]) {
+        self.state = state[0];
+    }
+}
+
+/// Tree pseudo-LRU.  Each set keeps a binary tree of `ways - 1` bits, where every bit
+/// points towards the half of the set that was used less recently.  Associativities
+/// that aren't a power of two are rounded up, and victims beyond the last way are
+/// clamped to it.
+pub struct TreePlru {
+    ways: usize,
+    leaves: usize,
+    bits: Vec<bool>,
+}
+
+impl TreePlru {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        let leaves = ways.next_power_of_two();
+        Self { ways, leaves, bits: vec![false; sets * leaves] }
+    }
+}
+
+impl ReplacementPolicy for TreePlru {
+    fn touch(&mut self, set: usize, way: usize) {
+        let tree = &mut self.bits[set * self.leaves..(set + 1) * self.leaves];
+
+        // Walk from the root to the leaf, pointing every node away from the used way
+        let mut node = 1;
+        let mut span = self.leaves / 2;
+        while span > 0 {
+            let right = way & span != 0;
+            tree[node] = !right;
+            node = node * 2 + right as usize;
+            span /= 2;
+        }
+    }
+
+    fn fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let tree = &self.bits[set * self.leaves..(set + 1) * self.leaves];
+
+        let mut node = 1;
+        let mut way = 0;
+        let mut span = self.leaves / 2;
+        while span > 0 {
+            if tree[node] {
+                way |= span;
+            }
+            node = node * 2 + tree[node] as usize;
+            span /= 2;
+        }
+        way.min(self.ways - 1)
+    }
+
+    fn reset(&mut self) {
+        self.bits.iter_mut().for_each(|x| *x = false);
+    }
+
+    fn save(&self) -> Vec<u64> {
+        self.bits.iter().map(|x| *x as u64).collect()
+    }
+
+    fn restore(&mut self, state: &[u64]) {
+        self.bits.iter_mut().zip(state).for_each(|(bit, x)| *bit = *x != 0);
+    }
+}
+
+/// Least frequently used.  The count starts over whenever a new block is filled.
+pub struct Lfu {
+    ways: usize,
+    uses: Vec<usize>,
+}
+
+impl Lfu {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self { ways, uses: vec![0; sets * ways] }
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.uses[set * self.ways + way] += 1;
+    }
+
+    fn fill(&mut self, set: usize, way: usize) {
+        self.uses[set * self.ways + way] = 1;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        oldest(&self.uses[set * self.ways..(set + 1) * self.ways])
+    }
+
+    fn reset(&mut self) {
+        self.uses.iter_mut().for_each(|x| *x = 0);
+    }
+
+    fn save(&self) -> Vec<u64> {
+        self.uses.iter().map(|x| *x as u64).collect()
+    }
+
+    fn restore(&mut self, state: &[u64]) {
+        self.uses.iter_mut().zip(state).for_each(|(count, x)| *count = *x as usize);
+    }
+}
+
+/// Use counting with decay, the original IronLEG policy.  Counts above 4 are squashed
+/// down to their log2 every time a victim is chosen, so no line gets "stuck" from uses
+/// a long time ago.
+pub struct Decay {
+    ways: usize,
+    uses: Vec<usize>,
+}
+
+impl Decay {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self { ways, uses: vec![0; sets * ways] }
+    }
+}
+
+impl ReplacementPolicy for Decay {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.uses[set * self.ways + way] += 1;
+    }
+
+    fn fill(&mut self, set: usize, way: usize) {
+        self.uses[set * self.ways + way] = 1;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let uses = &mut self.uses[set * self.ways..(set + 1) * self.ways];
+        for count in uses.iter_mut() {
+            if *count > 4 {
+                *count = count.ilog2() as usize;
+            }
+        }
+        oldest(uses)
+    }
+
+    fn reset(&mut self) {
+        self.uses.iter_mut().for_each(|x| *x = 0);
+    }
+
+    fn save(&self) -> Vec<u64> {
+        self.uses.iter().map(|x| *x as u64).collect()
+    }
+
+    fn restore(&mut self, state: &[u64]) {
+        self.uses.iter_mut().zip(state).for_each(|(count, x)| *count = *x as usize);
+    }
+}
+
+// Index of the smallest value, preferring the lowest way on ties
+fn oldest<T: Ord + Copy>(values: &[T]) -> usize {
+    let mut lowest = 0;
+    for (i, value) in values.iter().enumerate() {
+        if *value < values[lowest] {
+            lowest = i;
+        }
+    }
+    lowest
+}
@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Memory, Transparency, LineTag};
+use super::{MemoryValue, MemoryAccess, MemoryStats, MemoryState, Inclusion};
+use crate::processor::pipeline::StageType;
+
+/// A handle to a level of memory that is shared by more than one upper level, like
+/// an L2 or RAM sitting under split instruction and data caches.  Every handle sees
+/// the same contents and the same access state machine, so the upper levels contend
+/// for it exactly like they would for a real shared bus.
+pub struct SharedMemory {
+    memory: Arc<Mutex<Box<dyn Memory>>>,
+    back_invalidations: Arc<Mutex<Vec<Vec<usize>>>>,
+    id: usize,
+}
+
+/// Only the original handle saves the memory underneath, the others would just be
+/// saving it again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SharedState {
+    pub(super) memory: Option<Box<MemoryState>>,
+    back_invalidations: Vec<Vec<usize>>,
+}
+
+impl SharedMemory {
+    pub fn new(memory: Box<dyn Memory>) -> Self {
+        Self {
+            memory: Arc::new(Mutex::new(memory)),
+            back_invalidations: Arc::new(Mutex::new(vec![vec![]])),
+            id: 0,
+        }
+    }
+
+    /// Another way in to the same memory.  Only the original handle passes `tick` on,
+    /// so the shared level still only advances once per cycle.
+    pub fn handle(&self) -> Self {
+        let mut back_invalidations = self.back_invalidations.lock().unwrap();
+        back_invalidations.push(vec![]);
+        Self {
+            memory: Arc::clone(&self.memory),
+            back_invalidations: Arc::clone(&self.back_invalidations),
+            id: back_invalidations.len() - 1,
+        }
+    }
+
+    // Every level above has to hear about an eviction, not just the one whose access
+    // caused it
+    fn share_back_invalidations(&self, memory: &mut Box<dyn Memory>) {
+        let evicted = memory.take_back_invalidations();
+        if !evicted.is_empty() {
+            for pending in self.back_invalidations.lock().unwrap().iter_mut() {
+                pending.extend_from_slice(&evicted);
+            }
+        }
+    }
+}
+
+impl Memory for SharedMemory {
+    fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
+        let mut memory = self.memory.lock().unwrap();
+        let value = memory.read(addr, stage, line);
+        self.share_back_invalidations(&mut memory);
+        value
+    }
+
+    fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
+        let mut memory = self.memory.lock().unwrap();
+        let done = memory.write(addr, value, stage);
+        self.share_back_invalidations(&mut memory);
+        done
+    }
+
+    fn poke(&mut self, addr: usize, value: &MemoryValue) {
+        self.memory.lock().unwrap().poke(addr, value);
+    }
+
+    fn peek(&self, addr: usize) -> usize {
+        self.memory.lock().unwrap().peek(addr)
+    }
+
+    fn inclusion(&self) -> Inclusion {
+        self.memory.lock().unwrap().inclusion()
+    }
+
+    fn shared(&self) -> Option<Arc<Mutex<Box<dyn Memory>>>> {
+        Some(Arc::clone(&self.memory))
+    }
+
+    fn take_back_invalidations(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.back_invalidations.lock().unwrap()[self.id])
+    }
+
+    fn tick(&mut self) {
+        if self.id == 0 {
+            let mut memory = self.memory.lock().unwrap();
+            memory.tick();
+            self.share_back_invalidations(&mut memory);
+        }
+    }
+
+    fn reset_state(&mut self) {
+        self.memory.lock().unwrap().reset_state();
+    }
+
+    fn flash(&mut self, addr: usize, program: &[usize]) {
+        self.memory.lock().unwrap().flash(addr, program);
+    }
+
+    fn reset(&mut self) {
+        self.memory.lock().unwrap().reset();
+        self.back_invalidations.lock().unwrap().iter_mut().for_each(|pending| pending.clear());
+    }
+
+    fn save(&self) -> MemoryState {
+        MemoryState::Shared(match self.id {
+            0 => SharedState {
+                memory: Some(Box::new(self.memory.lock().unwrap().save())),
+                back_invalidations: self.back_invalidations.lock().unwrap().clone(),
+            },
+            _ => SharedState { memory: None, back_invalidations: vec![] },
+        })
+    }
+
+    fn restore(&mut self, state: &MemoryState) {
+        let MemoryState::Shared(state) = state else { panic!("saved state isn't from a shared level") };
+        if let Some(memory) = &state.memory {
+            self.memory.lock().unwrap().restore(memory);
+            *self.back_invalidations.lock().unwrap() = state.back_invalidations.clone();
+        }
+    }
+}
+
+impl Transparency for SharedMemory {
+    fn view_line(&self, line_num: usize) -> Vec<Vec<usize>> {
+        self.memory.lock().unwrap().view_line(line_num)
+    }
+
+    fn view_access(&self) -> Vec<MemoryAccess> {
+        self.memory.lock().unwrap().view_access()
+    }
+
+    fn view_size(&self) -> Vec<usize> {
+        self.memory.lock().unwrap().view_size()
+    }
+
+    fn view_stats(&self) -> Vec<MemoryStats> {
+        self.memory.lock().unwrap().view_stats()
+    }
+
+    fn view_names(&self) -> Vec<String> {
+        self.memory.lock().unwrap().view_names()
+    }
+
+    fn view_tags(&self, line_num: usize) -> Vec<Option<LineTag>> {
+        self.memory.lock().unwrap().view_tags(line_num)
+    }
+}
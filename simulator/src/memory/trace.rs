@@ -0,0 +1,186 @@
+use std::fmt;
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+
+use super::{HierarchyConfig, MemoryStats, MemoryValue, StageType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// How a memory trace is written
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TraceFormat {
+    /// `cycle stage kind address size`, with the stage as its first letter, the kind
+    /// as `R` or `W` and the address in hex
+    Native,
+    /// Dinero's `din` format, `label address` where the label is 0 for a data read,
+    /// 1 for a data write and 2 for an instruction fetch.  Cycles and sizes are lost.
+    Din,
+}
+
+/// One access the pipeline made, counted once when it completed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub cycle: u128,
+    pub addr: usize,
+    pub size: usize,
+    pub kind: AccessKind,
+    pub stage: StageType,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TraceError {
+    /// Counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+fn stage_letter(stage: StageType) -> char {
+    format!("{:?}", stage).chars().next().unwrap()
+}
+
+fn stage_from_letter(letter: &str) -> Option<StageType> {
+    [StageType::Fetch, StageType::Decode, StageType::Execute, StageType::Memory, StageType::Writeback]
+        .into_iter()
+        .find(|stage| letter.len() == 1 && letter.starts_with(stage_letter(*stage)))
+}
+
+impl TraceRecord {
+    pub fn to_line(&self, format: TraceFormat) -> String {
+        match format {
+            TraceFormat::Native => {
+                let kind = match self.kind {
+                    AccessKind::Read => 'R',
+                    AccessKind::Write => 'W',
+                };
+                format!("{} {} {} {:x} {}", self.cycle, stage_letter(self.stage), kind, self.addr, self.size)
+            },
+            TraceFormat::Din => {
+                let label = match (self.kind, self.stage) {
+                    (AccessKind::Write, _) => 1,
+                    (AccessKind::Read, StageType::Fetch) => 2,
+                    (AccessKind::Read, _) => 0,
+                };
+                format!("{} {:x}", label, self.addr)
+            },
+        }
+    }
+
+    /// Reads one line back.  `din` lines have no cycle or size, so they're numbered
+    /// by `index` and given `word_size`.
+    pub fn parse(line: &str, format: TraceFormat, index: u128, word_size: usize) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let addr = |field: &str| {
+            let field = field.trim_start_matches("0x");
+            usize::from_str_radix(field, 16).map_err(|_| format!("bad address {}", field))
+        };
+
+        match format {
+            TraceFormat::Native => {
+                let [cycle, stage, kind, address, size] = fields[..] else {
+                    return Err(String::from("expected cycle, stage, kind, address and size"));
+                };
+                Ok(TraceRecord {
+                    cycle: cycle.parse().map_err(|_| format!("bad cycle {}", cycle))?,
+                    addr: addr(address)?,
+                    size: size.parse().map_err(|_| format!("bad size {}", size))?,
+                    kind: match kind {
+                        "R" => AccessKind::Read,
+                        "W" => AccessKind::Write,
+                        _ => return Err(format!("bad kind {}", kind)),
+                    },
+                    stage: stage_from_letter(stage).ok_or_else(|| format!("bad stage {}", stage))?,
+                })
+            },
+            TraceFormat::Din => {
+                // Anything after the address is ignored, as Dinero does
+                let [label, address, ..] = fields[..] else {
+                    return Err(String::from("expected a label and an address"));
+                };
+                let (kind, stage) = match label {
+                    "0" => (AccessKind::Read, StageType::Memory),
+                    "1" => (AccessKind::Write, StageType::Memory),
+                    "2" => (AccessKind::Read, StageType::Fetch),
+                    _ => return Err(format!("unsupported label {}", label)),
+                };
+                Ok(TraceRecord { cycle: index, addr: addr(address)?, size: word_size, kind, stage })
+            },
+        }
+    }
+}
+
+/// Reads a whole trace, skipping blank lines and lines starting with `#`
+pub fn read_trace(input: impl BufRead, format: TraceFormat, word_size: usize) -> Result<Vec<TraceRecord>, TraceError> {
+    let mut records = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| TraceError { line: i + 1, message: e.to_string() })?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let record = TraceRecord::parse(line, format, records.len() as u128, word_size)
+            .map_err(|message| TraceError { line: i + 1, message })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// What a trace did to a hierarchy
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayResult {
+    pub accesses: u64,
+    /// Cycles spent waiting on memory, with every access made one after the other
+    pub cycles: u64,
+    /// Every level, lowest first, with the instruction cache last when there's one
+    pub levels: Vec<(String, MemoryStats)>,
+}
+
+impl ReplayResult {
+    pub fn level(&self, name: &str) -> Option<&MemoryStats> {
+        self.levels.iter().find(|x| x.0 == name).map(|x| &x.1)
+    }
+}
+
+/// Runs a trace through a freshly built hierarchy with no processor attached.
+/// Fetches go to the instruction side and everything else to the data side, each
+/// access is retried every cycle until it completes before the next one starts.
+/// Writes store zeros, only the traffic matters.
+pub fn replay<'a>(records: impl IntoIterator<Item = &'a TraceRecord>, config: &HierarchyConfig) -> ReplayResult {
+    let hierarchy = config.build();
+    let mut result = ReplayResult { accesses: 0, cycles: 0, levels: vec![] };
+
+    for record in records {
+        let memory = match record.stage {
+            StageType::Fetch => &hierarchy.instruction,
+            _ => &hierarchy.data,
+        };
+        let mut memory = memory.lock().unwrap();
+        loop {
+            memory.tick();
+            result.cycles += 1;
+            let done = match record.kind {
+                AccessKind::Read => memory.read(record.addr, record.stage, false).is_some(),
+                AccessKind::Write => memory.write(record.addr, &MemoryValue::Value(0), record.stage),
+            };
+            if done {
+                break;
+            }
+        }
+        result.accesses += 1;
+    }
+
+    result.levels = hierarchy.view_levels();
+    result
+}
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Memory, Transparency, LineTag};
+use super::{MemoryValue, MemoryAccess, MemoryStats, MemoryState, Inclusion};
+use crate::processor::pipeline::StageType;
+
+/// A small FIFO of pending writes that sits in front of a lower level of memory.
+/// Writes are accepted straight away while there is room and drained into the lower
+/// level one at a time in the background, so the writer only pays the lower level's
+/// latency when the buffer is full.  Reads of a block with a write still waiting in
+/// the buffer stall until that write has drained.
+///
+/// The buffer isn't a level of its own, every view passes straight through to the
+/// level underneath it.
+pub struct WriteBuffer {
+    capacity: usize,
+    block_size: usize,
+    word_size: usize,
+    entries: VecDeque<(usize, MemoryValue)>,
+    pub lower_level: Box<dyn Memory>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WriteBufferState {
+    entries: VecDeque<(usize, MemoryValue)>,
+    pub(super) lower_level: Box<MemoryState>,
+}
+
+impl WriteBuffer {
+    pub fn new(capacity: usize, block_size: usize, word_size: usize, lower_level: Box<dyn Memory>) -> Self {
+        Self {
+            capacity,
+            block_size,
+            word_size,
+            entries: VecDeque::with_capacity(capacity),
+            lower_level,
+        }
+    }
+
+    pub fn occupancy(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn block(&self, addr: usize) -> usize {
+        addr / (self.word_size * self.block_size)
+    }
+
+    fn drain(&mut self, stage: StageType) -> bool {
+        let Some((addr, value)) = self.entries.front() else { return true };
+        if !self.lower_level.write(*addr, value, stage) {
+            return false;
+        }
+        self.entries.pop_front();
+        true
+    }
+
+    // Folds a write into one already waiting for the same block when possible
+    fn coalesce(&mut self, addr: usize, value: &MemoryValue) -> bool {
+        let block = self.block(addr);
+        let offset = (addr / self.word_size) % self.block_size;
+        let (word_size, block_size) = (self.word_size, self.block_size);
+
+        for (entry_addr, entry) in self.entries.iter_mut().rev() {
+            if *entry_addr / (word_size * block_size) != block {
+                continue;
+            }
+            return match (entry, value) {
+                (MemoryValue::Line(line), MemoryValue::Value(val)) => { line[offset] = *val; true },
+                (entry @ MemoryValue::Line(_), MemoryValue::Line(_)) => { *entry = value.clone(); true },
+                (MemoryValue::Value(val), MemoryValue::Value(new)) if *entry_addr / word_size == addr / word_size => { *val = *new; true },
+                _ => false,
+            };
+        }
+        false
+    }
+}
+
+impl Memory for WriteBuffer {
+    fn read(&mut self, addr: usize, stage: StageType, line: bool) -> Option<MemoryValue> {
+        // Never let a read overtake a write to the same block
+        let block = self.block(addr);
+        if self.entries.iter().any(|(entry_addr, _)| self.block(*entry_addr) == block) {
+            self.drain(stage);
+            return None;
+        }
+        self.lower_level.read(addr, stage, line)
+    }
+
+    fn write(&mut self, addr: usize, value: &MemoryValue, stage: StageType) -> bool {
+        if self.coalesce(addr, value) {
+            return true;
+        }
+
+        // Full, the writer has to wait for the oldest entry to make room
+        if self.entries.len() >= self.capacity {
+            self.drain(stage);
+            return false;
+        }
+
+        self.entries.push_back((addr, value.clone()));
+        true
+    }
+
+    fn poke(&mut self, addr: usize, value: &MemoryValue) {
+        if !self.coalesce(addr, value) {
+            self.lower_level.poke(addr, value);
+        }
+    }
+
+    fn peek(&self, addr: usize) -> usize {
+        let offset = (addr / self.word_size) % self.block_size;
+        let newest = self.entries.iter().rev().find_map(|(entry_addr, entry)| match entry {
+            MemoryValue::Line(line) if self.block(*entry_addr) == self.block(addr) => Some(line[offset]),
+            MemoryValue::Value(val) if *entry_addr / self.word_size == addr / self.word_size => Some(*val),
+            _ => None,
+        });
+        newest.unwrap_or_else(|| self.lower_level.peek(addr))
+    }
+
+    fn inclusion(&self) -> Inclusion {
+        self.lower_level.inclusion()
+    }
+
+    fn take_back_invalidations(&mut self) -> Vec<usize> {
+        self.lower_level.take_back_invalidations()
+    }
+
+    fn shared(&self) -> Option<Arc<Mutex<Box<dyn Memory>>>> {
+        self.lower_level.shared()
+    }
+
+    fn tick(&mut self) {
+        // Background drains have no stage of their own, writeback never touches memory
+        // so its slot in the access state machine is free to stand in for the buffer
+        self.drain(StageType::Writeback);
+        self.lower_level.tick();
+    }
+
+    fn reset_state(&mut self) {
+        self.lower_level.reset_state();
+    }
+
+    fn flash(&mut self, addr: usize, program: &[usize]) {
+        self.lower_level.flash(addr, program);
+    }
+
+    fn reset(&mut self) {
+        self.entries.clear();
+        self.lower_level.reset();
+    }
+
+    fn save(&self) -> MemoryState {
+        MemoryState::WriteBuffer(WriteBufferState {
+            entries: self.entries.clone(),
+            lower_level: Box::new(self.lower_level.save()),
+        })
+    }
+
+    fn restore(&mut self, state: &MemoryState) {
+        let MemoryState::WriteBuffer(state) = state else { panic!("saved state isn't from a write buffer") };
+        self.entries = state.entries.clone();
+        self.lower_level.restore(&state.lower_level);
+    }
+}
+
+impl Transparency for WriteBuffer {
+    fn view_line(&self, line_num: usize) -> Vec<Vec<usize>> {
+        self.lower_level.view_line(line_num)
+    }
+
+    fn view_access(&self) -> Vec<MemoryAccess> {
+        self.lower_level.view_access()
+    }
+
+    fn view_size(&self) -> Vec<usize> {
+        self.lower_level.view_size()
+    }
+
+    fn view_stats(&self) -> Vec<MemoryStats> {
+        self.lower_level.view_stats()
+    }
+
+    fn view_names(&self) -> Vec<String> {
+        self.lower_level.view_names()
+    }
+
+    fn view_tags(&self, line_num: usize) -> Vec<Option<LineTag>> {
+        self.lower_level.view_tags(line_num)
+    }
+}
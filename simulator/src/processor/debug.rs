@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+
+use super::registers::Register;
+
+/// Where in the pipeline a breakpoint is checked.  Fetch sees every instruction,
+/// including ones down a wrong path, retire only sees the ones that commit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BreakStage {
+    Fetch,
+    #[default]
+    Retire,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A register test that has to pass for a conditional breakpoint to fire
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    pub reg: Register,
+    pub op: Comparison,
+    pub value: i32,
+}
+
+impl Condition {
+    pub fn holds(&self, registers: &[i32; 16]) -> bool {
+        let reg = registers[self.reg as usize];
+        match self.op {
+            Comparison::Eq => reg == self.value,
+            Comparison::Ne => reg != self.value,
+            Comparison::Lt => reg < self.value,
+            Comparison::Le => reg <= self.value,
+            Comparison::Gt => reg > self.value,
+            Comparison::Ge => reg >= self.value,
+        }
+    }
+}
+
+/// Stops on the instruction at `addr`.  At retire the pipeline stops before the
+/// instruction commits, at fetch it stops once the instruction has been fetched.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// Assigned when the breakpoint is added
+    #[serde(default)]
+    pub id: usize,
+    pub addr: i32,
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    #[serde(default)]
+    pub stage: BreakStage,
+}
+
+impl Breakpoint {
+    pub fn new(addr: i32) -> Self {
+        Self { id: 0, addr, condition: None, stage: BreakStage::Retire }
+    }
+
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    pub fn at(mut self, stage: BreakStage) -> Self {
+        self.stage = stage;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    Read,
+    Write,
+    /// Either a read or a write
+    Any,
+}
+
+/// Stops once the memory stage has read or written any byte address in
+/// `start..end`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Watchpoint {
+    #[serde(default)]
+    pub id: usize,
+    pub start: usize,
+    pub end: usize,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    pub fn new(start: usize, end: usize, access: Access) -> Self {
+        Self { id: 0, start, end, access }
+    }
+}
+
+/// What made the pipeline stop
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DebugEvent {
+    Breakpoint { id: usize, pc: i32 },
+    Watchpoint { id: usize, addr: usize, access: Access, value: usize },
+}
+
+/// Breakpoints and watchpoints, shared by every stage.  A stage that trips one
+/// records the event here and the simulator stops once the cycle is over, retire
+/// breakpoints are checked by the simulator itself before the cycle starts.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    next_id: usize,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    event: Option<DebugEvent>,
+    // The instruction that stopped at a retire breakpoint, let through next time so
+    // execution can carry on from it
+    resume: Option<u64>,
+    // Bytes each access covers
+    word_size: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self { word_size: 1, ..Self::default() }
+    }
+
+    pub fn set_word_size(&mut self, word_size: usize) {
+        self.word_size = word_size;
+    }
+
+    pub fn add_breakpoint(&mut self, mut breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        breakpoint.id = self.next_id;
+        self.breakpoints.push(breakpoint);
+        self.next_id
+    }
+
+    pub fn add_watchpoint(&mut self, mut watchpoint: Watchpoint) -> usize {
+        self.next_id += 1;
+        watchpoint.id = self.next_id;
+        self.watchpoints.push(watchpoint);
+        self.next_id
+    }
+
+    /// Removes the breakpoint or watchpoint with the given id, returning whether there
+    /// was one
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|x| x.id != id);
+        self.watchpoints.retain(|x| x.id != id);
+        count != self.breakpoints.len() + self.watchpoints.len()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn take_event(&mut self) -> Option<DebugEvent> {
+        self.event.take()
+    }
+
+    fn record(&mut self, event: DebugEvent) {
+        // The first event of a cycle is the one that gets reported
+        if self.event.is_none() {
+            self.event = Some(event);
+        }
+    }
+
+    fn matching_breakpoint(&self, pc: i32, stage: BreakStage, registers: &[i32; 16]) -> Option<usize> {
+        self.breakpoints.iter()
+            .find(|x| x.addr == pc && x.stage == stage && x.condition.is_none_or(|c| c.holds(registers)))
+            .map(|x| x.id)
+    }
+
+    /// Called by fetch for every instruction it fetches
+    pub fn check_fetch(&mut self, pc: i32, registers: &[i32; 16]) {
+        if let Some(id) = self.matching_breakpoint(pc, BreakStage::Fetch, registers) {
+            self.record(DebugEvent::Breakpoint { id, pc });
+        }
+    }
+
+    /// Called by the simulator before the cycle instruction `id` would retire in.
+    /// Stopping there doesn't cost the pipeline a cycle, so a program takes just as
+    /// long with breakpoints set as without.
+    pub fn check_retire(&mut self, id: u64, pc: i32, registers: &[i32; 16]) -> Option<DebugEvent> {
+        if self.resume == Some(id) {
+            return None;
+        }
+        let breakpoint = self.matching_breakpoint(pc, BreakStage::Retire, registers)?;
+        self.resume = Some(id);
+        Some(DebugEvent::Breakpoint { id: breakpoint, pc })
+    }
+
+    /// Called by the memory stage once an access has completed.  The access covers a
+    /// word from `addr`, so it hits a watchpoint if any of its bytes are watched.
+    pub fn check_access(&mut self, addr: usize, access: Access, value: usize) {
+        let end = addr.saturating_add(self.word_size);
+        let hit = self.watchpoints.iter()
+            .find(|x| addr < x.end && end > x.start && (x.access == Access::Any || x.access == access))
+            .map(|x| x.id);
+        if let Some(id) = hit {
+            self.record(DebugEvent::Watchpoint { id, addr, access, value });
+        }
+    }
+
+    /// Forgets any pending stop, breakpoints and watchpoints stay set
+    pub fn reset(&mut self) {
+        self.event = None;
+        self.resume = None;
+    }
+
+    /// The instruction let through its retire breakpoint, the only part of the
+    /// debugger that belongs to the machine's state rather than the user's
+    pub fn resume_point(&self) -> Option<u64> {
+        self.resume
+    }
+
+    pub fn set_resume_point(&mut self, id: Option<u64>) {
+        self.event = None;
+        self.resume = id;
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::assembler::disassemble;
+
+/// How an instruction left the pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Fate {
+    Retired(u128),
+    /// Thrown away by a mispredicted branch ahead of it
+    Squashed(u128),
+    /// Stopped the machine when it reached writeback
+    Faulted(u128),
+}
+
+/// Cycles `start` to `end`, inclusive, spent in one stage
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Occupancy {
+    pub stage: usize,
+    pub start: u128,
+    pub end: u128,
+}
+
+/// Everywhere one instruction went, in fetch order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstrTrace {
+    pub id: u64,
+    pub pc: i32,
+    pub raw: u32,
+    /// False while fetch is still waiting on the word
+    pub fetched: bool,
+    pub stages: Vec<Occupancy>,
+    pub fate: Option<Fate>,
+}
+
+impl InstrTrace {
+    fn first_cycle(&self) -> u128 {
+        self.stages.first().map_or(0, |x| x.start)
+    }
+
+    fn last_cycle(&self) -> u128 {
+        match self.fate {
+            Some(Fate::Squashed(cycle)) => cycle,
+            _ => self.stages.last().map_or(0, |x| x.end),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self.fetched {
+            true => format!("{:#06x} {}", self.pc, disassemble(self.raw)),
+            false => String::from("(fetching)"),
+        }
+    }
+}
+
+/// What each stage of the pipeline held every cycle, kept for the most recent
+/// `capacity` instructions.  Exported as a Kanata log for Konata, or as a text chart.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PipelineTrace {
+    /// Short names of the stages, fetch first
+    pub stages: Vec<String>,
+    pub capacity: usize,
+    instructions: BTreeMap<u64, InstrTrace>,
+}
+
+impl PipelineTrace {
+    pub fn new(stages: Vec<String>, capacity: usize) -> Self {
+        Self { stages, capacity, instructions: BTreeMap::new() }
+    }
+
+    /// Notes that instruction `id` spent `cycle` in `stage`
+    pub fn occupy(&mut self, cycle: u128, stage: usize, id: u64, pc: i32, raw: u32, fetched: bool) {
+        let instr = self.instructions.entry(id).or_insert_with(|| InstrTrace { id, pc, raw, fetched, stages: vec![], fate: None });
+        instr.pc = pc;
+        instr.raw = raw;
+        instr.fetched = fetched;
+        match instr.stages.last_mut() {
+            Some(last) if last.stage == stage => last.end = cycle,
+            _ => instr.stages.push(Occupancy { stage, start: cycle, end: cycle }),
+        }
+
+        while self.instructions.len() > self.capacity {
+            self.instructions.pop_first();
+        }
+    }
+
+    /// Records how an instruction left, only the first call for each counts
+    pub fn finish(&mut self, id: u64, fate: Fate) {
+        if let Some(instr) = self.instructions.get_mut(&id) {
+            instr.fate.get_or_insert(fate);
+        }
+    }
+
+    pub fn is_finished(&self, id: u64) -> bool {
+        self.instructions.get(&id).is_some_and(|x| x.fate.is_some())
+    }
+
+    /// Forgets everything from cycle `cycle` on, ready for those cycles to be run again
+    pub fn truncate_from(&mut self, cycle: u128) {
+        self.instructions.retain(|_, instr| {
+            instr.stages.retain(|x| x.start < cycle);
+            if let Some(last) = instr.stages.last_mut() {
+                last.end = last.end.min(cycle - 1);
+            }
+            if matches!(instr.fate, Some(Fate::Retired(x) | Fate::Squashed(x) | Fate::Faulted(x)) if x >= cycle) {
+                instr.fate = None;
+            }
+            !instr.stages.is_empty()
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.instructions.clear();
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = &InstrTrace> {
+        self.instructions.values()
+    }
+
+    /// The trace in the Kanata log format version 0004, as read by Konata
+    pub fn to_kanata(&self) -> String {
+        // (cycle, order within the cycle, line), retiring after stages end and stages
+        // ending before the next one starts
+        let mut events: Vec<(u128, u8, String)> = vec![];
+        let mut retired = 0;
+        for (seq, instr) in self.instructions.values().enumerate() {
+            let start = instr.first_cycle();
+            events.push((start, 0, format!("I\t{}\t{}\t0", seq, instr.id)));
+            events.push((start, 0, format!("L\t{}\t0\t{}", seq, instr.label())));
+            for occupancy in &instr.stages {
+                events.push((occupancy.start, 2, format!("S\t{}\t0\t{}", seq, self.stages[occupancy.stage])));
+                events.push((occupancy.end + 1, 1, format!("E\t{}\t0\t{}", seq, self.stages[occupancy.stage])));
+            }
+            match instr.fate {
+                Some(Fate::Retired(cycle)) => {
+                    events.push((cycle + 1, 3, format!("R\t{}\t{}\t0", seq, retired)));
+                    retired += 1;
+                },
+                Some(Fate::Squashed(cycle) | Fate::Faulted(cycle)) => {
+                    events.push((cycle.max(instr.last_cycle()) + 1, 3, format!("R\t{}\t0\t1", seq)));
+                },
+                None => {},
+            }
+        }
+        events.sort_by_key(|x| (x.0, x.1));
+
+        let mut log = String::from("Kanata\t0004\n");
+        let mut now = events.first().map_or(0, |x| x.0);
+        writeln!(log, "C=\t{}", now).unwrap();
+        for (cycle, _, line) in events {
+            if cycle > now {
+                writeln!(log, "C\t{}", cycle - now).unwrap();
+                now = cycle;
+            }
+            writeln!(log, "{}", line).unwrap();
+        }
+        log
+    }
+
+    /// One row per instruction and one column per cycle from `first` to `last`,
+    /// showing the first letter of the stage it was in, `x` where it was squashed
+    /// and `!` where it faulted
+    pub fn to_chart(&self, first: u128, last: u128) -> String {
+        let rows: Vec<&InstrTrace> = self.instructions.values()
+            .filter(|x| x.first_cycle() <= last && x.last_cycle() >= first)
+            .collect();
+        let width = rows.iter().map(|x| x.label().len()).max().unwrap_or(0);
+
+        let mut chart = String::new();
+        let cycles: Vec<String> = (first..=last).map(|x| (x % 10).to_string()).collect();
+        writeln!(chart, "{:>width$}  {}", format!("cycle {}", first), cycles.concat(), width = width).unwrap();
+        for instr in rows {
+            let mut row: Vec<char> = vec![' '; (last - first + 1) as usize];
+            let mut mark = |cycle: u128, c: char| {
+                if (first..=last).contains(&cycle) {
+                    row[(cycle - first) as usize] = c;
+                }
+            };
+            for occupancy in &instr.stages {
+                let letter = self.stages[occupancy.stage].chars().next().unwrap_or('?');
+                for cycle in occupancy.start..=occupancy.end {
+                    mark(cycle, letter);
+                }
+            }
+            match instr.fate {
+                Some(Fate::Squashed(cycle)) => mark(cycle, 'x'),
+                Some(Fate::Faulted(cycle)) => mark(cycle, '!'),
+                _ => {},
+            }
+            let row: String = row.into_iter().collect();
+            writeln!(chart, "{:<width$}  {}", instr.label(), row.trim_end(), width = width).unwrap();
+        }
+        chart
+    }
+}
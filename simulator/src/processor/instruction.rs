@@ -1,8 +1,8 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use super::registers::{Register, Registers};
 
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum InstrType {
     ALU(ALUType),
     Memory(MemoryType),
@@ -10,7 +10,7 @@ pub enum InstrType {
     Interrupt(InterruptType),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum AddrMode {
     RegReg,
     RegRegOff,
@@ -32,7 +32,7 @@ impl AddrMode {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ALUType {
     MOV,
     ADD,
@@ -69,7 +69,7 @@ impl ALUType {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum MemoryType {
     LDR,
     STR,
@@ -85,7 +85,7 @@ impl MemoryType {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ControlType {
     BEQ,
     BLT,
@@ -110,7 +110,7 @@ impl ControlType {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum InterruptType {
     NOP,
     HLT,
@@ -126,15 +126,25 @@ impl InterruptType {
 }
 
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InstrMeta {
+    /// Fetch order, unique for the life of the pipeline
+    pub id: u64,
+    pub pc: i32,
+    /// Where fetch went next, checked against the real outcome of a branch
+    pub predicted_pc: i32,
+    /// Address a memory instruction accesses, latched in execute
+    pub mem_addr: usize,
+    /// Set instead of panicking when the instruction can't be carried out, and only
+    /// raised if it reaches writeback
+    pub fault: Option<String>,
     pub writeback: bool,
     pub squashed: bool,
     pub result: i32,
     pub initialized: bool,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Instruction {
     pub instr_raw: i32,
     pub instr_type: InstrType,
@@ -146,6 +156,12 @@ pub struct Instruction {
     pub meta: InstrMeta,
 }
 
+impl Default for Instruction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Instruction {
     pub fn new() -> Self {
         Self {
@@ -157,6 +173,11 @@ impl Instruction {
             dest: Register::R0,
             imm: 0,
             meta: InstrMeta {
+                id: 0,
+                pc: 0,
+                predicted_pc: 0,
+                mem_addr: 0,
+                fault: None,
                 writeback: true,
                 squashed: false,
                 result: 0,
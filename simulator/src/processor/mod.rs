@@ -1,24 +1,106 @@
 use std::sync::{Arc, Mutex};
 
-use self::pipeline::StageType;
+use serde::{Deserialize, Serialize};
+
+use self::pipeline::{StageContext, StageProcess, StageType};
+use self::debug::Debugger;
+use self::predictor::{Predictor, PredictorKind};
 use self::registers::Registers;
+use self::trace::Tracer;
 
 use crate::memory::Memory;
 
+pub mod debug;
+pub mod diagram;
 pub mod instruction;
 pub mod registers;
 pub mod pipeline;
+pub mod predictor;
 pub mod stages;
+pub mod trace;
+
+
+/// Options for the pipeline itself.  The defaults match the original IronLEG
+/// pipeline, no forwarding and every branch predicted not taken.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub predictor: PredictorKind,
+    pub btb_entries: usize,
+    pub forwarding: bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            predictor: PredictorKind::NotTaken,
+            btb_entries: 64,
+            forwarding: false,
+        }
+    }
+}
+
+/// Register values the processor comes out of reset with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResetConfig {
+    pub pc: i32,
+    pub sp: i32,
+}
+
+/// The classic five stages, in pipeline order
+pub fn default_stages() -> Vec<(StageType, StageProcess)> {
+    vec![
+        (StageType::Fetch, stages::fetch),
+        (StageType::Decode, stages::decode),
+        (StageType::Execute, stages::execute),
+        (StageType::Memory, stages::memory),
+        (StageType::Writeback, stages::writeback),
+    ]
+}
 
+// One letter per stage type, numbered from the second of a type on, so a diagram can
+// tell repeated stages apart
+fn stage_names(stages: &[(StageType, StageProcess)]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for (stage_type, _) in stages {
+        let letter = format!("{:?}", stage_type)[..1].to_string();
+        let count = names.iter().filter(|x| x.starts_with(&letter)).count();
+        names.push(match count {
+            0 => letter,
+            n => format!("{}{}", letter, n + 1),
+        });
+    }
+    names
+}
 
-pub fn new(mem: Arc<Mutex<Box<dyn Memory>>>) -> Box<pipeline::Stage> {
-    let regs = Arc::new(Mutex::new(Registers::new()));
+/// Builds the five stage pipeline.  Fetch reads through `imem` and every other stage
+/// uses `dmem`, pass the same memory for both to get a unified cache.
+pub fn new(imem: Arc<Mutex<Box<dyn Memory>>>, mem: Arc<Mutex<Box<dyn Memory>>>, config: &PipelineConfig, reset: &ResetConfig) -> Box<pipeline::Stage> {
+    build(imem, mem, config, reset, &default_stages())
+}
 
-    let fetch_stage = Box::new(pipeline::Stage::create(Arc::clone(&mem), Arc::clone(&regs), StageType::Fetch, None, false));
-    let decode_stage = Box::new(pipeline::Stage::create(Arc::clone(&mem), Arc::clone(&regs), StageType::Decode, Some(fetch_stage), false));
-    let execute_stage = Box::new(pipeline::Stage::create(Arc::clone(&mem), Arc::clone(&regs), StageType::Execute, Some(decode_stage), false));
-    let memory_stage = Box::new(pipeline::Stage::create(Arc::clone(&mem), Arc::clone(&regs), StageType::Memory, Some(execute_stage), false));
-    let writeback_stage = Box::new(pipeline::Stage::create(Arc::clone(&mem), Arc::clone(&regs), StageType::Writeback, Some(memory_stage), true));
+/// Builds a pipeline from a list of stages, from fetch through to the stage that
+/// retires instructions.  Fetch stages read through `imem`, the rest use `mem`.
+pub fn build(imem: Arc<Mutex<Box<dyn Memory>>>, mem: Arc<Mutex<Box<dyn Memory>>>, config: &PipelineConfig, reset: &ResetConfig, stages: &[(StageType, StageProcess)]) -> Box<pipeline::Stage> {
+    let context = StageContext {
+        mem,
+        regs: Arc::new(Mutex::new(Registers::with_reset(reset.pc, reset.sp))),
+        predictor: Arc::new(Mutex::new(Predictor::new(config.predictor, config.btb_entries))),
+        debugger: Arc::new(Mutex::new(Debugger::new())),
+        tracer: Arc::new(Mutex::new(Tracer::new())),
+        forwarding: config.forwarding,
+    };
+    let fetch_context = StageContext { mem: imem, ..context.clone() };
+    context.tracer.lock().unwrap().set_stages(stage_names(stages));
 
-    writeback_stage
-}
\ No newline at end of file
+    let mut pipeline = None;
+    for (i, (stage_type, process)) in stages.iter().enumerate() {
+        let context = match stage_type {
+            StageType::Fetch => fetch_context.clone(),
+            _ => context.clone(),
+        };
+        pipeline = Some(Box::new(pipeline::Stage::create(context, *process, pipeline, i == stages.len() - 1)));
+    }
+    pipeline.expect("a pipeline needs at least one stage")
+}
@@ -1,20 +1,95 @@
 use std::sync::{Arc, Mutex};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use super::debug::Debugger;
+use super::diagram::Fate;
 use super::instruction::Instruction;
-use super::registers::Registers;
-use super::stages;
+use super::predictor::{Predictor, PredictorStats};
+use super::registers::{Register, Registers};
+use super::trace::Tracer;
 use crate::memory::Memory;
 
 pub use super::stages::StageType;
 
-#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
 pub enum StageResult {
     DONE,
     WAIT,
     SQUASH,
     COMPLETE,
     HALT,
+    FAULT,
+}
+
+/// Everything a stage works with besides the instruction it holds.  The handles are
+/// shared between stages, only `mem` differs when the caches are split.
+#[derive(Clone)]
+pub struct StageContext {
+    pub mem: Arc<Mutex<Box<dyn Memory>>>,
+    pub regs: Arc<Mutex<Registers>>,
+    pub predictor: Arc<Mutex<Predictor>>,
+    pub debugger: Arc<Mutex<Debugger>>,
+    pub tracer: Arc<Mutex<Tracer>>,
+    /// Let results skip ahead of writeback to the instructions waiting on them
+    pub forwarding: bool,
+}
+
+/// What a stage does with the instruction it holds each cycle, see `stages`
+pub type StageProcess = fn(&StageContext, &mut Instruction) -> StageResult;
+
+/// A stage's latch, what it holds and where it's up to
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StageState {
+    status: StageResult,
+    cycles: u128,
+    fetched: u64,
+    retired: u64,
+    instruction: Option<Instruction>,
+}
+
+/// A saved pipeline, with the registers, predictor and every stage's latch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProcessorState {
+    registers: Registers,
+    predictor: Predictor,
+    resume_point: Option<u64>,
+    // Head first
+    stages: Vec<StageState>,
+}
+
+impl ProcessorState {
+    pub fn cycles(&self) -> u128 {
+        self.stages[0].cycles
+    }
+
+    pub fn retired(&self) -> u64 {
+        self.stages[0].retired
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stages.len()
+    }
+}
+
+// Enough of an instruction to place it in the pipeline diagram
+struct Sighting {
+    id: u64,
+    pc: i32,
+    raw: u32,
+    fetched: bool,
+    squashed: bool,
+}
+
+impl Sighting {
+    fn of(instr: &Instruction) -> Self {
+        Self {
+            id: instr.meta.id,
+            pc: instr.meta.pc,
+            raw: instr.instr_raw as u32,
+            fetched: instr.meta.initialized,
+            squashed: instr.meta.squashed,
+        }
+    }
 }
 
 pub struct Stage {
@@ -22,31 +97,27 @@ pub struct Stage {
     is_head: bool,
     pipeline_on: bool,
     cycles: u128,
+    fetched: u64,
+    retired: u64,
     pub instruction: Option<Instruction>,
-    mem: Arc<Mutex<Box<dyn Memory>>>,
-    regs: Arc<Mutex<Registers>>,
+    context: StageContext,
     prev_stage: Option<Box<Stage>>,
-    process: fn(Arc<Mutex<Box<dyn Memory>>>, Arc<Mutex<Registers>>, &mut Instruction) -> StageResult,
+    process: StageProcess,
 }
 
 impl Stage {
-    pub fn create(mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>, stage_type: StageType, prev_stage: Option<Box<Stage>>, is_head: bool) -> Stage {
+    pub fn create(context: StageContext, process: StageProcess, prev_stage: Option<Box<Stage>>, is_head: bool) -> Stage {
         Stage {
             status: StageResult::DONE,
             pipeline_on: true,
-            is_head: is_head,
+            is_head,
             cycles: 0,
+            fetched: 0,
+            retired: 0,
             instruction: None,
-            mem: mem,
-            regs: regs,
-            prev_stage: prev_stage,
-            process: match stage_type {
-                StageType::Fetch => stages::fetch, 
-                StageType::Decode => stages::decode,
-                StageType::Execute => stages::execute,
-                StageType::Memory => stages::memory,
-                StageType::Writeback => stages::writeback,
-            },
+            context,
+            prev_stage,
+            process,
         }
         
     }
@@ -69,13 +140,19 @@ impl Stage {
             },
             None => {
                 if self.instruction.is_none() && self.pipeline_on {
-                    self.instruction = Some(Instruction::new())
+                    self.fetched += 1;
+                    let mut instr = Instruction::new();
+                    instr.meta.id = self.fetched;
+                    self.instruction = Some(instr)
                 }
             }
         };
     }
 
     pub fn squash(&mut self) {
+        // Anything this stage had in flight in memory belongs to a squashed instruction
+        self.context.mem.lock().unwrap().reset_state();
+
         if let Some(instr) = &mut self.instruction {
             if instr.meta.initialized {
                 instr.meta.squashed = true;
@@ -86,35 +163,139 @@ impl Stage {
         }
     }
 
-    pub async fn cycle(&mut self) -> bool {
-        if self.status == StageResult::HALT { return false; }
+    /// Clocks this stage and every stage behind it once.  Returns false without doing
+    /// anything once the pipeline has halted or faulted.
+    pub fn cycle(&mut self) -> bool {
+        if matches!(self.status, StageResult::HALT | StageResult::FAULT) { return false; }
         
+        if self.is_head {
+            self.tick_memories(&mut vec![]);
+            self.context.tracer.lock().unwrap().begin_cycle(self.cycles);
+        }
+
         self.load();
+        let mut committing = None;
         if let Some(instr) = &mut self.instruction {
             if instr.meta.squashed { self.status =  StageResult::DONE }
             if self.status !=  StageResult::DONE || self.is_head {
-                self.status = (self.process)(Arc::clone(&self.mem), Arc::clone(&self.regs), instr);
+                self.status = (self.process)(&self.context, instr);
+            }
+            if self.is_head { committing = Some(Sighting::of(instr)) }
+            if self.is_head && !instr.meta.squashed && matches!(self.status, StageResult::DONE | StageResult::SQUASH | StageResult::HALT) {
+                self.retired += 1;
             }
             if self.status == StageResult::SQUASH { self.squash(); self.status = StageResult::DONE }
             if self.status ==  StageResult::DONE && self.is_head { self.instruction = None }
         }
         if let Some(prev) = &mut self.prev_stage {
-            Box::pin(prev.cycle()).await;
+            prev.cycle();
+        }
+        if self.is_head {
+            self.record(committing);
         }
         
         self.cycles += 1;
         true
     }
 
+    // Adds what every stage held this cycle to the pipeline diagram, once the whole
+    // pipeline has moved.  Writeback may already have let go of its instruction, so
+    // it's passed in.
+    fn record(&self, committing: Option<Sighting>) {
+        let mut tracer = self.context.tracer.lock().unwrap();
+        let Some(diagram) = tracer.pipeline_mut() else { return };
+
+        let mut sightings = vec![committing.map(|x| (x, self.status))];
+        let mut stage = self.prev_stage.as_deref();
+        while let Some(current) = stage {
+            sightings.push(current.instruction.as_ref().map(|x| (Sighting::of(x), current.status)));
+            stage = current.prev_stage.as_deref();
+        }
+
+        let depth = sightings.len();
+        for (i, sighting) in sightings.into_iter().enumerate() {
+            let Some((seen, status)) = sighting else { continue };
+            if seen.squashed {
+                diagram.finish(seen.id, Fate::Squashed(self.cycles));
+                continue;
+            }
+            diagram.occupy(self.cycles, depth - 1 - i, seen.id, seen.pc, seen.raw, seen.fetched);
+            if i == 0 {
+                match status {
+                    StageResult::DONE | StageResult::SQUASH | StageResult::HALT => diagram.finish(seen.id, Fate::Retired(self.cycles)),
+                    StageResult::FAULT => diagram.finish(seen.id, Fate::Faulted(self.cycles)),
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    // Ticks every memory used by the pipeline exactly once, stages share handles
+    fn tick_memories<'a>(&'a self, ticked: &mut Vec<&'a Arc<Mutex<Box<dyn Memory>>>>) {
+        if !ticked.iter().any(|mem| Arc::ptr_eq(mem, &self.context.mem)) {
+            self.context.mem.lock().unwrap().tick();
+            ticked.push(&self.context.mem);
+        }
+        if let Some(prev_stage) = &self.prev_stage {
+            prev_stage.tick_memories(ticked);
+        }
+    }
+
     pub fn reset(&mut self) {
         self.cycles = 0;
+        self.fetched = 0;
+        self.retired = 0;
         self.instruction = None;
         self.status = StageResult::DONE;
-        self.regs.lock().unwrap().reset();
+        self.context.regs.lock().unwrap().reset();
+        self.context.predictor.lock().unwrap().reset();
+        self.context.debugger.lock().unwrap().reset();
         if let Some(prev_stage) = &mut self.prev_stage {
             prev_stage.reset();
         }
     }
+
+    /// Captures the whole processor, called on the head
+    pub fn save(&self) -> ProcessorState {
+        let mut stages = vec![];
+        let mut stage = Some(self);
+        while let Some(current) = stage {
+            stages.push(StageState {
+                status: current.status,
+                cycles: current.cycles,
+                fetched: current.fetched,
+                retired: current.retired,
+                instruction: current.instruction.clone(),
+            });
+            stage = current.prev_stage.as_deref();
+        }
+
+        ProcessorState {
+            registers: self.context.regs.lock().unwrap().clone(),
+            predictor: self.context.predictor.lock().unwrap().clone(),
+            resume_point: self.context.debugger.lock().unwrap().resume_point(),
+            stages,
+        }
+    }
+
+    /// Puts back a processor saved from a pipeline with the same stages.  Breakpoints
+    /// and watchpoints aren't part of the state and stay as they are.
+    pub fn restore(&mut self, state: &ProcessorState) {
+        *self.context.regs.lock().unwrap() = state.registers.clone();
+        *self.context.predictor.lock().unwrap() = state.predictor.clone();
+        self.context.debugger.lock().unwrap().set_resume_point(state.resume_point);
+
+        let mut stage = Some(self);
+        for saved in &state.stages {
+            let current = stage.expect("saved state has more stages than the pipeline");
+            current.status = saved.status;
+            current.cycles = saved.cycles;
+            current.fetched = saved.fetched;
+            current.retired = saved.retired;
+            current.instruction = saved.instruction.clone();
+            stage = current.prev_stage.as_deref_mut();
+        }
+    }
 } 
 
 // Functions for external visibility separated out for clarity
@@ -138,14 +319,59 @@ impl Stage {
     }
 
     pub fn view_registers(&self) -> [i32; 16] {
-        self.regs.lock().unwrap().registers
+        self.context.regs.lock().unwrap().registers
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.status == StageResult::HALT
     }
 
-    pub async fn view_cycles(&self) -> u128 {
+    /// Why the pipeline stopped, if an instruction faulted when it reached writeback
+    pub fn view_fault(&self) -> Option<String> {
+        match (self.status, &self.instruction) {
+            (StageResult::FAULT, Some(instr)) => instr.meta.fault.clone(),
+            _ => None,
+        }
+    }
+
+    /// The instruction writeback will take next cycle, if it's one that will commit
+    pub fn view_retiring(&self) -> Option<&Instruction> {
+        let instr = match (&self.instruction, &self.prev_stage) {
+            (Some(instr), _) => Some(instr),
+            (None, Some(prev)) if prev.status == StageResult::DONE => prev.instruction.as_ref(),
+            _ => None,
+        };
+        instr.filter(|instr| instr.meta.initialized && !instr.meta.squashed)
+    }
+
+    /// Instructions that made it all the way through writeback
+    pub fn view_retired(&self) -> u64 {
+        self.retired
+    }
+
+    pub fn view_cycles(&self) -> u128 {
         self.cycles
     }
 
+    /// Overwrites a register straight away, for setting up state from outside the
+    /// pipeline
+    pub fn set_register(&self, reg: Register, value: i32) {
+        self.context.regs.lock().unwrap().set_reg(reg, value);
+    }
+
     pub fn view_register_status(&self) -> [bool; 16] {
-        self.regs.lock().unwrap().in_use
+        self.context.regs.lock().unwrap().in_use
+    }
+
+    pub fn debugger(&self) -> Arc<Mutex<Debugger>> {
+        Arc::clone(&self.context.debugger)
+    }
+
+    pub fn tracer(&self) -> Arc<Mutex<Tracer>> {
+        Arc::clone(&self.context.tracer)
+    }
+
+    pub fn view_predictor_stats(&self) -> PredictorStats {
+        self.context.predictor.lock().unwrap().view_stats()
     }
 }
\ No newline at end of file
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+/// How fetch guesses the next PC.  Branches resolve in writeback, so every wrong
+/// guess squashes everything behind the branch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PredictorKind {
+    /// Always fetch the next instruction in memory, the original IronLEG behaviour
+    #[default]
+    NotTaken,
+    /// Follow any branch the target buffer has seen before
+    AlwaysTaken,
+    /// Two bit saturating counters, one per target buffer entry
+    Bimodal,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PredictorStats {
+    pub predictions: u64,
+    pub mispredictions: u64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct BtbEntry {
+    pc: i32,
+    target: i32,
+    counter: u8,
+}
+
+/// A direct mapped branch target buffer, plus whatever direction state the chosen
+/// predictor needs.  Fetch can't tell a branch from anything else, so an instruction
+/// is only ever predicted taken once it has been seen to branch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Predictor {
+    kind: PredictorKind,
+    entries: Vec<Option<BtbEntry>>,
+    stats: PredictorStats,
+}
+
+impl Predictor {
+    pub fn new(kind: PredictorKind, entries: usize) -> Self {
+        Self {
+            kind,
+            entries: vec![None; entries.max(1)],
+            stats: PredictorStats::default(),
+        }
+    }
+
+    fn index(&self, pc: i32) -> usize {
+        (pc as usize / 4) % self.entries.len()
+    }
+
+    /// The PC to fetch after the instruction at `pc`
+    pub fn predict(&self, pc: i32) -> i32 {
+        let fallthrough = pc + 4;
+        let Some(entry) = self.entries[self.index(pc)] else { return fallthrough };
+        if entry.pc != pc {
+            return fallthrough;
+        }
+        match self.kind {
+            PredictorKind::NotTaken => fallthrough,
+            PredictorKind::AlwaysTaken => entry.target,
+            PredictorKind::Bimodal if entry.counter >= 2 => entry.target,
+            PredictorKind::Bimodal => fallthrough,
+        }
+    }
+
+    /// Trains on a resolved branch, `correct` is whether fetch followed the right path
+    pub fn update(&mut self, pc: i32, taken: bool, target: i32, correct: bool) {
+        self.stats.predictions += 1;
+        if !correct {
+            self.stats.mispredictions += 1;
+        }
+
+        let index = self.index(pc);
+        match &mut self.entries[index] {
+            Some(entry) if entry.pc == pc => {
+                if taken {
+                    entry.target = target;
+                    entry.counter = (entry.counter + 1).min(3);
+                } else {
+                    entry.counter = entry.counter.saturating_sub(1);
+                }
+            },
+            // Only taken branches are worth a slot
+            entry if taken => *entry = Some(BtbEntry { pc, target, counter: 2 }),
+            _ => {},
+        }
+    }
+
+    pub fn view_stats(&self) -> PredictorStats {
+        self.stats
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.iter_mut().for_each(|x| *x = None);
+        self.stats = PredictorStats::default();
+    }
+}
@@ -1,6 +1,6 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Register {
     R0,
     R1,
@@ -23,16 +23,16 @@ pub enum Register {
 impl Register {
     pub fn from_i32(reg: i32) -> Register {
         match reg {
-            00 => Register::R0,
-            01 => Register::R1,
-            02 => Register::R2,
-            03 => Register::R3,
-            04 => Register::R4,
-            05 => Register::R5,
-            06 => Register::R6,
-            07 => Register::R7,
-            08 => Register::R8,
-            09 => Register::R9,
+            0 => Register::R0,
+            1 => Register::R1,
+            2 => Register::R2,
+            3 => Register::R3,
+            4 => Register::R4,
+            5 => Register::R5,
+            6 => Register::R6,
+            7 => Register::R7,
+            8 => Register::R8,
+            9 => Register::R9,
             10 => Register::R10,
             11 => Register::R11,
             12 => Register::SP,
@@ -44,22 +44,56 @@ impl Register {
     }
 }
 
-#[derive(Debug)]
+/// The register file and its scoreboard.  Every instruction claims its destination
+/// in decode and gives it back in writeback, readers wait while a register is claimed
+/// unless forwarding has already made the new value available.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Registers {
     pub registers: [i32; 16],
     pub in_use: [bool; 16],
+    // In flight writers of each register, and the id of the youngest one
+    writers: [u32; 16],
+    owner: [u64; 16],
+    // The newest result forwarded ahead of writeback, and whether it came from the
+    // youngest writer.  Older readers still pick up an older writer's value after a
+    // younger writer has claimed the register.
+    bypass: [Option<i32>; 16],
+    ready: [bool; 16],
+    reset_pc: i32,
+    reset_sp: i32,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Registers {
     pub fn new() -> Registers {
-        Registers {
+        Registers::with_reset(0, 0)
+    }
+
+    /// A register file that comes out of reset with the given PC and SP
+    pub fn with_reset(pc: i32, sp: i32) -> Registers {
+        let mut regs = Registers {
             registers: [0; 16],
             in_use: [false; 16],
-        }
+            writers: [0; 16],
+            owner: [0; 16],
+            bypass: [None; 16],
+            ready: [false; 16],
+            reset_pc: pc,
+            reset_sp: sp,
+        };
+        regs.reset();
+        regs
     }
 
+    /// The newest value of a register, forwarded results take priority over the
+    /// register file
     pub fn get_reg(&self, reg: Register) -> i32 {
-        self.registers[reg as usize]
+        self.bypass[reg as usize].unwrap_or(self.registers[reg as usize])
     }
 
     pub fn set_reg(&mut self, reg: Register, value: i32) {
@@ -74,13 +108,52 @@ impl Registers {
         self.in_use[reg as usize]
     }
 
+    /// Whether a reader can go ahead, either nothing is about to write the register
+    /// or its newest value has already been forwarded
+    pub fn is_ready(&self, reg: Register) -> bool {
+        !self.in_use[reg as usize] || self.ready[reg as usize]
+    }
+
+    /// Marks `id` as the youngest writer of `reg`
+    pub fn claim(&mut self, reg: Register, id: u64) {
+        let reg = reg as usize;
+        self.writers[reg] += 1;
+        self.owner[reg] = id;
+        self.ready[reg] = false;
+        self.in_use[reg] = true;
+    }
+
+    /// Makes a result visible to readers before it is written back.  Ignored unless
+    /// `id` is still the youngest writer, an older result would be stale.
+    pub fn forward(&mut self, reg: Register, id: u64, value: i32) {
+        if self.owner[reg as usize] == id && self.writers[reg as usize] > 0 {
+            self.bypass[reg as usize] = Some(value);
+            self.ready[reg as usize] = true;
+        }
+    }
+
+    /// Gives back a claim made in decode, once the value (if any) is in the register file
+    pub fn release(&mut self, reg: Register, id: u64) {
+        let reg = reg as usize;
+        self.writers[reg] = self.writers[reg].saturating_sub(1);
+        if self.owner[reg] == id {
+            self.bypass[reg] = None;
+            self.ready[reg] = false;
+        }
+        self.in_use[reg] = self.writers[reg] > 0;
+    }
+
     pub fn clear_in_use(&mut self) {
         self.in_use.iter_mut().for_each(|x| *x = false);
+        self.writers = [0; 16];
+        self.bypass = [None; 16];
+        self.ready = [false; 16];
     }
 
     pub fn reset(&mut self) {
         self.clear_in_use();
         self.registers = [0; 16];
+        self.registers[Register::PC as usize] = self.reset_pc;
+        self.registers[Register::SP as usize] = self.reset_sp;
     }
 }
-
@@ -1,13 +1,17 @@
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
 
-use crate::memory::{Memory, MemoryValue};
+use crate::assembler::disassemble;
+use crate::memory::MemoryValue;
+use crate::memory::trace::AccessKind;
 
-use super::registers::{Register, Registers};
+use super::debug::Access;
+use super::registers::Register;
 use super::instruction::{Instruction, ALUType, AddrMode, ControlType, InstrType, InterruptType, MemoryType};
-use super::pipeline::StageResult;
+use super::pipeline::{StageContext, StageResult};
+use super::trace::Commit;
 
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum StageType {
     Fetch,
     Decode,
@@ -17,19 +21,49 @@ pub enum StageType {
 }
 
 
-pub fn fetch<'a>(mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>, instr: &mut Instruction) -> StageResult {
-    let instr_addr = regs.lock().unwrap().get_reg(Register::PC) as usize;
-    if let Some(MemoryValue::Value(value)) = mem.lock().unwrap().read(instr_addr, StageType::Fetch, false) {
+/// Moves an instruction along without touching it, for padding out a deeper pipeline
+pub fn pass(_ctx: &StageContext, _instr: &mut Instruction) -> StageResult {
+    StageResult::DONE
+}
+
+pub fn fetch(ctx: &StageContext, instr: &mut Instruction) -> StageResult {
+    let instr_addr = ctx.regs.lock().unwrap().get_reg(Register::PC);
+    if let Some(MemoryValue::Value(value)) = ctx.mem.lock().unwrap().read(instr_addr as usize, StageType::Fetch, false) {
+        ctx.tracer.lock().unwrap().memory_access(instr_addr as usize, AccessKind::Read, StageType::Fetch);
         instr.instr_raw = value as i32;
         instr.meta.initialized = true;
-        regs.lock().unwrap().set_reg(Register::PC, (instr_addr + 4) as i32);
+        instr.meta.pc = instr_addr;
+        instr.meta.predicted_pc = ctx.predictor.lock().unwrap().predict(instr_addr);
+
+        let mut regs = ctx.regs.lock().unwrap();
+        ctx.debugger.lock().unwrap().check_fetch(instr_addr, &regs.registers);
+        regs.set_reg(Register::PC, instr.meta.predicted_pc);
         return StageResult::DONE;
     }
     StageResult::WAIT
 }
 
-pub fn decode<'a>(_mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>, instr: &mut Instruction) -> StageResult {
+// Whether any field decode turns into an enum is out of range
+fn is_illegal(raw: i32) -> bool {
+    let highest_opcode = match (raw >> 29) & 0x7 {
+        0b000 => ALUType::LSR as i32,
+        0b001 => MemoryType::STR as i32,
+        0b010 => ControlType::BLE as i32,
+        0b011 => InterruptType::HLT as i32,
+        _ => return true,
+    };
+    (raw >> 25) & 0xF > highest_opcode || (raw >> 22) & 0x7 > 0b100
+}
+
+/// Fills in everything that comes straight out of the raw word: the instruction
+/// type, addressing mode, registers and immediate.  Returns false, leaving the
+/// instruction alone, if the word isn't a legal instruction.  Shared by the pipeline
+/// and the reference interpreter, so they can never disagree about what a word means.
+pub fn decode_fields(instr: &mut Instruction) -> bool {
     let raw = instr.instr_raw;
+    if is_illegal(raw) {
+        return false;
+    }
 
     let opcode = (raw >> 25) & 0xF;
     instr.instr_type = match raw >> 29 {
@@ -42,36 +76,22 @@ pub fn decode<'a>(_mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>
     
     instr.addr_mode = AddrMode::from_i32((raw >> 22) & 0x7);
 
-
-    let mut regs = regs.lock().unwrap();
     match instr.addr_mode {
         AddrMode::RegReg => {
             instr.reg_1 = Register::from_i32((raw >> 18) & 0xF);
             instr.reg_2 = Register::from_i32((raw >> 14) & 0xF);
             instr.dest = instr.reg_1;
-
-            if regs.is_in_use(instr.reg_1) || regs.is_in_use(instr.reg_2) {
-                return StageResult::WAIT;
-            }
         },
         AddrMode::RegRegOff => {
             instr.reg_1 = Register::from_i32((raw >> 18) & 0xF);
             instr.reg_2 = Register::from_i32((raw >> 14) & 0xF);
             instr.imm = raw & 0xFFFF;
             instr.dest = instr.reg_1;
-
-            if regs.is_in_use(instr.reg_1) || regs.is_in_use(instr.reg_2) {
-                return StageResult::WAIT;
-            }
         },
         AddrMode::RegImm => {
             instr.reg_1 = Register::from_i32((raw >> 18) & 0xF);
             instr.imm = raw & 0xFFF;
             instr.dest = instr.reg_1;
-
-            if regs.is_in_use(instr.reg_1) {
-                return StageResult::WAIT;
-            }
         },
         AddrMode::Imm => {
             instr.imm = raw & 0x3FFFFF
@@ -79,10 +99,6 @@ pub fn decode<'a>(_mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>
         AddrMode::Reg => {
             instr.reg_1 = Register::from_i32((raw >> 18) & 0xF);
             instr.dest = instr.reg_1;
-
-            if regs.is_in_use(instr.reg_1) {
-                return StageResult::WAIT;
-            }
         },
     }
 
@@ -93,33 +109,68 @@ pub fn decode<'a>(_mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>
     }
 
     if let InstrType::Control(_) = instr.instr_type {
-        if regs.is_in_use(Register::BF) { return StageResult::WAIT; }
         instr.dest = Register::PC;
     }
+    true
+}
+
+pub fn decode(ctx: &StageContext, instr: &mut Instruction) -> StageResult {
+    // Could just be data fetched down the wrong path, so only fault if it retires
+    if !decode_fields(instr) {
+        instr.meta.fault = Some(format!("illegal instruction {:#010x} at {:#x}", instr.instr_raw, instr.meta.pc));
+        instr.meta.writeback = false;
+        return StageResult::DONE;
+    }
+
+    let mut regs = ctx.regs.lock().unwrap();
+    let (reads_1, reads_2) = match instr.addr_mode {
+        AddrMode::RegReg | AddrMode::RegRegOff => (true, true),
+        AddrMode::RegImm | AddrMode::Reg => (true, false),
+        AddrMode::Imm => (false, false),
+    };
+    if (reads_1 && !regs.is_ready(instr.reg_1)) || (reads_2 && !regs.is_ready(instr.reg_2)) {
+        return StageResult::WAIT;
+    }
+    if let InstrType::Control(_) = instr.instr_type {
+        if !regs.is_ready(Register::BF) { return StageResult::WAIT; }
+    }
             
-    regs.set_in_use(instr.dest, true);
+    regs.claim(instr.dest, instr.meta.id);
     StageResult::DONE
 }
 
-pub fn execute<'a>(_mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>, instr: &mut Instruction) -> StageResult {
-    let regs = regs.lock().unwrap();
+pub fn execute(ctx: &StageContext, instr: &mut Instruction) -> StageResult {
+    if instr.meta.fault.is_some() { return StageResult::DONE }
+
+    let mut regs = ctx.regs.lock().unwrap();
     match instr.instr_type {
         InstrType::ALU(opcode) => {
+            let arg_1 = instr.get_arg_1(&regs);
+            let arg_2 = instr.get_arg_2(&regs).wrapping_add(instr.imm);
+            if matches!(opcode, ALUType::IDIV | ALUType::MOD) && arg_2 == 0 {
+                instr.meta.fault = Some(format!("division by zero at {:#x}", instr.meta.pc));
+                return StageResult::DONE;
+            }
+
+            // Registers wrap like real hardware rather than tripping overflow checks
             instr.meta.result = match opcode {
-                ALUType::MOV  => instr.get_arg_2(&regs) + instr.imm,
-                ALUType::ADD  => instr.get_arg_1(&regs) + (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::SUB  => instr.get_arg_1(&regs) - (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::IMUL => instr.get_arg_1(&regs) * (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::IDIV => instr.get_arg_1(&regs) / (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::AND  => instr.get_arg_1(&regs) & (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::OR   => instr.get_arg_1(&regs) | (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::XOR  => instr.get_arg_1(&regs) ^ (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::CMP  => instr.get_arg_1(&regs) - (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::MOD  => instr.get_arg_1(&regs) % (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::NOT  => !(instr.get_arg_1(&regs) + instr.imm),
-                ALUType::LSL  => instr.get_arg_1(&regs) << (instr.get_arg_2(&regs) + instr.imm),
-                ALUType::LSR  => instr.get_arg_1(&regs) >> (instr.get_arg_2(&regs) + instr.imm),
+                ALUType::MOV  => arg_2,
+                ALUType::ADD  => arg_1.wrapping_add(arg_2),
+                ALUType::SUB  => arg_1.wrapping_sub(arg_2),
+                ALUType::IMUL => arg_1.wrapping_mul(arg_2),
+                ALUType::IDIV => arg_1.wrapping_div(arg_2),
+                ALUType::AND  => arg_1 & arg_2,
+                ALUType::OR   => arg_1 | arg_2,
+                ALUType::XOR  => arg_1 ^ arg_2,
+                ALUType::CMP  => arg_1.wrapping_sub(arg_2),
+                ALUType::MOD  => arg_1.wrapping_rem(arg_2),
+                ALUType::NOT  => !arg_1.wrapping_add(instr.imm),
+                ALUType::LSL  => arg_1.wrapping_shl(arg_2 as u32),
+                ALUType::LSR  => arg_1.wrapping_shr(arg_2 as u32),
             };
+            if ctx.forwarding {
+                regs.forward(instr.dest, instr.meta.id, instr.meta.result);
+            }
             StageResult::DONE
         },
         InstrType::Control(opcode) => {
@@ -132,34 +183,48 @@ pub fn execute<'a>(_mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>
                 ControlType::BGE  => regs.get_reg(Register::BF) >= 0,
                 ControlType::BLE  => regs.get_reg(Register::BF) <= 0,
             } { 
-                instr.meta.result = instr.get_arg_1(&regs) + instr.imm
+                instr.meta.result = instr.get_arg_1(&regs).wrapping_add(instr.imm)
             } else { 
                 instr.meta.writeback = false 
             }
             StageResult::DONE
         },
-        InstrType::Memory(_opcode) => StageResult::DONE,
+        InstrType::Memory(opcode) => {
+            // Operands are latched here, a forwarded value from a younger instruction
+            // could show up while the access is still waiting on memory
+            instr.meta.mem_addr = instr.get_arg_2(&regs) as usize;
+            if opcode == MemoryType::STR {
+                instr.meta.result = instr.get_arg_1(&regs);
+            }
+            StageResult::DONE
+        },
         InstrType::Interrupt(_opcode) => StageResult::DONE,
     }
 }
 
-pub fn memory<'a>(mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>, instr: &mut Instruction) -> StageResult {
-    let regs = regs.lock().unwrap();
-    let mut mem = mem.lock().unwrap();
+pub fn memory(ctx: &StageContext, instr: &mut Instruction) -> StageResult {
+    let mut mem = ctx.mem.lock().unwrap();
 
     if let InstrType::Memory(mem_type) = instr.instr_type  {
-        let mem_addr = instr.get_arg_2(&regs) as usize;
+        let mem_addr = instr.meta.mem_addr;
         return match mem_type {
             MemoryType::LDR => {
                 if let Some(MemoryValue::Value(response)) = mem.read(mem_addr, StageType::Memory, false) {
                     instr.meta.result = response as i32;
+                    ctx.tracer.lock().unwrap().memory_access(mem_addr, AccessKind::Read, StageType::Memory);
+                    ctx.debugger.lock().unwrap().check_access(mem_addr, Access::Read, response);
+                    if ctx.forwarding {
+                        ctx.regs.lock().unwrap().forward(instr.dest, instr.meta.id, instr.meta.result);
+                    }
                     return StageResult::DONE;
                 }
                 StageResult::WAIT
             },
             MemoryType::STR => {
-                let val_to_store = instr.get_arg_1(&regs) as usize;
+                let val_to_store = instr.meta.result as usize;
                 if mem.write(mem_addr, &MemoryValue::Value(val_to_store), StageType::Memory) {
+                    ctx.tracer.lock().unwrap().memory_access(mem_addr, AccessKind::Write, StageType::Memory);
+                    ctx.debugger.lock().unwrap().check_access(mem_addr, Access::Write, val_to_store);
                     instr.meta.writeback = false;
                     return StageResult::DONE;
                 }
@@ -170,21 +235,31 @@ pub fn memory<'a>(mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>,
     StageResult::DONE
 }
 
-pub fn writeback<'a>(mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers>>, instr: &mut Instruction) -> StageResult {
+pub fn writeback(ctx: &StageContext, instr: &mut Instruction) -> StageResult {
     if instr.meta.squashed { return StageResult::DONE }
+    if instr.meta.fault.is_some() { return StageResult::FAULT }
+
+    let mut regs = ctx.regs.lock().unwrap();
 
-    let mut regs = regs.lock().unwrap();
-    if instr.meta.writeback {
+    // Branches are resolved here.  Anything fetch guessed wrong, including a branch
+    // target for something that turned out not to be a branch, squashes the pipeline.
+    let next_pc = match instr.instr_type {
+        InstrType::Control(_) if instr.meta.writeback => instr.meta.result,
+        _ => instr.meta.pc + 4,
+    };
+    let correct = next_pc == instr.meta.predicted_pc;
+    if let InstrType::Control(_) = instr.instr_type {
+        ctx.predictor.lock().unwrap().update(instr.meta.pc, instr.meta.writeback, instr.meta.result, correct);
+    } else if instr.meta.writeback {
         regs.set_reg(instr.dest, instr.meta.result);
     }
-    regs.set_in_use(instr.dest, false);
+    regs.release(instr.dest, instr.meta.id);
+    log_commit(ctx, instr);
 
-    if instr.meta.writeback {
-        if let InstrType::Control(_) = instr.instr_type {
-            regs.clear_in_use();
-            mem.lock().unwrap().reset_state();
-            return StageResult::SQUASH;
-        }
+    if !correct {
+        regs.set_reg(Register::PC, next_pc);
+        regs.clear_in_use();
+        return StageResult::SQUASH;
     }
 
     if let InstrType::Interrupt(InterruptType::HLT) = instr.instr_type {
@@ -192,4 +267,33 @@ pub fn writeback<'a>(mem: Arc<Mutex<Box<dyn Memory>>>, regs: Arc<Mutex<Registers
     }
 
     StageResult::DONE
-}
\ No newline at end of file
+}
+
+// Branches and interrupts leave no register behind, the next commit's PC shows
+// where a branch went
+fn log_commit(ctx: &StageContext, instr: &Instruction) {
+    let mut tracer = ctx.tracer.lock().unwrap();
+    if !tracer.logging_commits() {
+        return;
+    }
+
+    let write = match instr.instr_type {
+        InstrType::ALU(_) | InstrType::Memory(MemoryType::LDR) => Some((instr.dest, instr.meta.result)),
+        _ => None,
+    };
+    let access = match instr.instr_type {
+        InstrType::Memory(_) => Some((instr.meta.mem_addr, instr.meta.result as u32)),
+        _ => None,
+    };
+    let commit = Commit {
+        cycle: tracer.cycle(),
+        pc: instr.meta.pc,
+        raw: instr.instr_raw as u32,
+        disassembly: disassemble(instr.instr_raw as u32),
+        reg: write.map(|x| x.0),
+        value: write.map(|x| x.1),
+        addr: access.map(|x| x.0),
+        data: access.map(|x| x.1),
+    };
+    tracer.commit(&commit);
+}
@@ -0,0 +1,190 @@
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::StageType;
+use crate::memory::trace::{AccessKind, TraceFormat, TraceRecord};
+
+use super::diagram::PipelineTrace;
+use super::registers::Register;
+
+/// How commit log lines are written
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CommitFormat {
+    /// Fixed width columns, one instruction per line
+    Text,
+    /// One JSON object per line, with every field present
+    JsonLines,
+}
+
+/// One instruction leaving writeback.  Only the architectural effects are recorded,
+/// so two runs of the same program on differently configured machines produce the
+/// same log apart from the cycles.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Commit {
+    pub cycle: u128,
+    pub pc: i32,
+    pub raw: u32,
+    pub disassembly: String,
+    /// The register written and its new value
+    pub reg: Option<Register>,
+    pub value: Option<i32>,
+    /// The address loaded from or stored to and the word that moved
+    pub addr: Option<usize>,
+    pub data: Option<u32>,
+}
+
+impl Commit {
+    /// `cycle pc raw disassembly` followed by `REG=value` and `[addr]=data` for
+    /// whichever happened, numbers in hex
+    pub fn to_text(&self) -> String {
+        let mut line = format!("{:>10} {:#010x} {:#010x}  {:<20}", self.cycle, self.pc, self.raw, self.disassembly);
+        if let (Some(reg), Some(value)) = (self.reg, self.value) {
+            line.push_str(&format!(" {:?}={:#010x}", reg, value));
+        }
+        if let (Some(addr), Some(data)) = (self.addr, self.data) {
+            line.push_str(&format!(" [{:#010x}]={:#010x}", addr, data));
+        }
+        line.trim_end().to_string()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("commits always serialize")
+    }
+}
+
+// A file written a line at a time, that stops at the first error
+struct LogFile {
+    out: Box<dyn Write + Send>,
+    error: Option<io::Error>,
+}
+
+impl LogFile {
+    fn new(out: Box<dyn Write + Send>) -> Self {
+        Self { out, error: None }
+    }
+
+    fn is_open(&self) -> bool {
+        self.error.is_none()
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(error) = writeln!(self.out, "{}", line) {
+            self.error = Some(error);
+        }
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        match self.error {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// Where the pipeline reports what it's doing, shared by every stage.  The head
+/// stage stamps each cycle before any stage runs.
+#[derive(Default)]
+pub struct Tracer {
+    cycle: u128,
+    replaying: bool,
+    commits: Option<(LogFile, CommitFormat)>,
+    memory: Option<(LogFile, TraceFormat, usize)>,
+    // Short names of the stages, fetch first
+    stages: Vec<String>,
+    pipeline: Option<PipelineTrace>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cycle the pipeline is running
+    pub fn cycle(&self) -> u128 {
+        self.cycle
+    }
+
+    pub fn begin_cycle(&mut self, cycle: u128) {
+        self.cycle = cycle;
+    }
+
+    pub fn set_stages(&mut self, stages: Vec<String>) {
+        self.stages = stages;
+    }
+
+    /// Short names of the stages, fetch first, in the order `view_pipeline_instrs`
+    /// lists them
+    pub fn stages(&self) -> &[String] {
+        &self.stages
+    }
+
+    /// Cycles run again while stepping back have already been logged once, nothing
+    /// is written while this is set
+    pub fn set_replaying(&mut self, replaying: bool) {
+        self.replaying = replaying;
+    }
+
+    /// Writes every instruction that commits from now on to `out`, replacing any
+    /// log already being written
+    pub fn log_commits(&mut self, out: Box<dyn Write + Send>, format: CommitFormat) {
+        self.commits = Some((LogFile::new(out), format));
+    }
+
+    pub fn logging_commits(&self) -> bool {
+        self.commits.as_ref().is_some_and(|log| log.0.is_open()) && !self.replaying
+    }
+
+    pub fn commit(&mut self, commit: &Commit) {
+        if !self.logging_commits() {
+            return;
+        }
+        let (file, format) = self.commits.as_mut().expect("checked above");
+        let line = match format {
+            CommitFormat::Text => commit.to_text(),
+            CommitFormat::JsonLines => commit.to_json(),
+        };
+        file.write_line(&line);
+    }
+
+    /// Stops the commit log, flushing it.  Returns the first error writing it hit.
+    pub fn finish_commits(&mut self) -> io::Result<()> {
+        self.commits.take().map_or(Ok(()), |log| log.0.finish())
+    }
+
+    /// Writes every memory access the pipeline completes from now on to `out`, each
+    /// one `word_size` bytes
+    pub fn log_memory(&mut self, out: Box<dyn Write + Send>, format: TraceFormat, word_size: usize) {
+        self.memory = Some((LogFile::new(out), format, word_size));
+    }
+
+    pub fn memory_access(&mut self, addr: usize, kind: AccessKind, stage: StageType) {
+        if self.replaying {
+            return;
+        }
+        let cycle = self.cycle;
+        if let Some((file, format, word_size)) = self.memory.as_mut().filter(|log| log.0.is_open()) {
+            let record = TraceRecord { cycle, addr, size: *word_size, kind, stage };
+            file.write_line(&record.to_line(*format));
+        }
+    }
+
+    /// Stops the memory trace, flushing it.  Returns the first error writing it hit.
+    pub fn finish_memory(&mut self) -> io::Result<()> {
+        self.memory.take().map_or(Ok(()), |log| log.0.finish())
+    }
+
+    /// Starts keeping a pipeline diagram of the last `capacity` instructions fetched,
+    /// or stops and throws it away if `capacity` is 0
+    pub fn record_pipeline(&mut self, capacity: usize) {
+        self.pipeline = (capacity > 0).then(|| PipelineTrace::new(self.stages.clone(), capacity));
+    }
+
+    pub fn pipeline(&self) -> Option<&PipelineTrace> {
+        self.pipeline.as_ref()
+    }
+
+    pub fn pipeline_mut(&mut self) -> Option<&mut PipelineTrace> {
+        self.pipeline.as_mut()
+    }
+}
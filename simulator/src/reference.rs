@@ -0,0 +1,336 @@
+use std::fmt;
+
+use crate::Simulator;
+use crate::config::MachineConfig;
+use crate::memory::{Memory, MemoryValue, RAM};
+use crate::processor::instruction::{ALUType, ControlType, InstrType, Instruction, InterruptType, MemoryType};
+use crate::processor::registers::{Register, Registers};
+use crate::processor::stages::decode_fields;
+use crate::run::StopReason;
+
+/// What one instruction did to the architectural state
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retirement {
+    pub pc: i32,
+    pub raw: i32,
+    /// The register written and its new value
+    pub write: Option<(Register, i32)>,
+    /// The address stored to and the value stored
+    pub store: Option<(usize, u32)>,
+}
+
+/// An untimed interpreter for the IronLEG instruction set, with no pipeline, caches
+/// or predictor.  Every step carries out exactly one instruction, so whatever it
+/// computes is what the program means.  Words are decoded by the same code as the
+/// pipeline's decode stage, everything after that is worked out independently.
+///
+/// Memory is a flat RAM of the same size as the machine's, devices aren't modelled.
+pub struct Reference {
+    registers: Registers,
+    memory: RAM,
+    retired: u64,
+    halted: bool,
+    fault: Option<String>,
+}
+
+impl Reference {
+    /// A machine fresh out of reset with nothing in memory
+    pub fn new(config: &MachineConfig) -> Self {
+        let memory = &config.memory;
+        Self {
+            registers: Registers::with_reset(config.reset.pc, config.reset.sp),
+            memory: RAM::new(memory.ram_lines, memory.block_size, memory.word_size, 1),
+            retired: 0,
+            halted: false,
+            fault: None,
+        }
+    }
+
+    /// Picks up the architectural state of a simulator: its memory, the register file
+    /// as written back so far, and the next instruction to retire.  Anything already
+    /// in the pipeline will be carried out again by the reference.
+    pub fn from_simulator(sim: &Simulator) -> Self {
+        let mut reference = Self::new(&sim.config);
+        let word_size = sim.config.memory.word_size;
+        for addr in (0..sim.config.memory.ram_lines).step_by(word_size) {
+            reference.memory.poke(addr, &MemoryValue::Value(sim.peek(addr) as usize));
+        }
+        reference.registers.registers = sim.processor.view_registers();
+
+        let oldest = sim.processor.view_pipeline_instrs().into_iter().rev().flatten()
+            .find(|instr| instr.meta.initialized && !instr.meta.squashed);
+        if let Some(instr) = oldest {
+            reference.registers.set_reg(Register::PC, instr.meta.pc);
+        }
+        reference.retired = sim.processor.view_retired();
+        reference.halted = sim.processor.is_halted();
+        reference
+    }
+
+    pub fn flash(&mut self, addr: usize, program: &[u32]) {
+        let program: Vec<usize> = program.iter().map(|x| *x as usize).collect();
+        self.memory.flash(addr, &program);
+    }
+
+    /// Carries out the next instruction.  Returns `None`, without doing anything,
+    /// once the program has halted or faulted.
+    pub fn step(&mut self) -> Option<Retirement> {
+        if self.halted || self.fault.is_some() {
+            return None;
+        }
+
+        let pc = self.registers.get_reg(Register::PC);
+        let mut instr = Instruction::new();
+        instr.instr_raw = self.memory.peek(pc as usize) as i32;
+        if !decode_fields(&mut instr) {
+            self.fault = Some(format!("illegal instruction {:#010x} at {:#x}", instr.instr_raw, pc));
+            return None;
+        }
+
+        let mut retirement = Retirement { pc, raw: instr.instr_raw, write: None, store: None };
+        let mut next_pc = pc.wrapping_add(4);
+        let arg_1 = instr.get_arg_1(&self.registers);
+        let arg_2 = instr.get_arg_2(&self.registers);
+
+        match instr.instr_type {
+            InstrType::ALU(opcode) => {
+                let operand = arg_2.wrapping_add(instr.imm);
+                if matches!(opcode, ALUType::IDIV | ALUType::MOD) && operand == 0 {
+                    self.fault = Some(format!("division by zero at {:#x}", pc));
+                    return None;
+                }
+                let result = match opcode {
+                    ALUType::MOV  => operand,
+                    ALUType::ADD  => arg_1.wrapping_add(operand),
+                    ALUType::SUB  => arg_1.wrapping_sub(operand),
+                    ALUType::IMUL => arg_1.wrapping_mul(operand),
+                    ALUType::IDIV => arg_1.wrapping_div(operand),
+                    ALUType::AND  => arg_1 & operand,
+                    ALUType::OR   => arg_1 | operand,
+                    ALUType::XOR  => arg_1 ^ operand,
+                    ALUType::CMP  => arg_1.wrapping_sub(operand),
+                    ALUType::MOD  => arg_1.wrapping_rem(operand),
+                    ALUType::NOT  => !arg_1.wrapping_add(instr.imm),
+                    ALUType::LSL  => arg_1.wrapping_shl(operand as u32),
+                    ALUType::LSR  => arg_1.wrapping_shr(operand as u32),
+                };
+                retirement.write = Some((instr.dest, result));
+            },
+            InstrType::Memory(MemoryType::LDR) => {
+                let value = self.memory.peek(arg_2 as usize) as u32 as i32;
+                retirement.write = Some((instr.dest, value));
+            },
+            InstrType::Memory(MemoryType::STR) => {
+                let addr = arg_2 as usize;
+                self.memory.poke(addr, &MemoryValue::Value(arg_1 as u32 as usize));
+                retirement.store = Some((addr, arg_1 as u32));
+            },
+            InstrType::Control(opcode) => {
+                let flags = self.registers.get_reg(Register::BF);
+                let taken = match opcode {
+                    ControlType::BEQ => flags == 0,
+                    ControlType::BLT => flags < 0,
+                    ControlType::BGT => flags > 0,
+                    ControlType::BNE => flags != 0,
+                    ControlType::B   => true,
+                    ControlType::BGE => flags >= 0,
+                    ControlType::BLE => flags <= 0,
+                };
+                if taken {
+                    next_pc = arg_1.wrapping_add(instr.imm);
+                }
+            },
+            InstrType::Interrupt(InterruptType::HLT) => self.halted = true,
+            InstrType::Interrupt(InterruptType::NOP) => {},
+        }
+
+        if let Some((reg, value)) = retirement.write {
+            self.registers.set_reg(reg, value);
+        }
+        self.registers.set_reg(Register::PC, next_pc);
+        self.retired += 1;
+        Some(retirement)
+    }
+
+    /// Steps until the program stops or `max_instructions` have been carried out,
+    /// returning how many were
+    pub fn run(&mut self, max_instructions: u64) -> u64 {
+        let mut count = 0;
+        while count < max_instructions && self.step().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    pub fn registers(&self) -> [i32; 16] {
+        self.registers.registers
+    }
+
+    pub fn peek(&self, addr: usize) -> u32 {
+        self.memory.peek(addr) as u32
+    }
+
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+}
+
+/// How the pipeline and the reference disagreed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch {
+    /// The pipeline retired an instruction from the wrong address
+    Pc { expected: i32, actual: i32 },
+    /// Same address, different word, something changed the program
+    Instruction { expected: i32, actual: i32 },
+    Register { reg: Register, expected: i32, actual: i32 },
+    Store { expected: Option<(usize, u32)>, actual: Option<(usize, u32)> },
+    Fault { expected: Option<String>, actual: Option<String> },
+    Halt { expected: bool, actual: bool },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let store = |store: &Option<(usize, u32)>| match store {
+            Some((addr, value)) => format!("{} to {:#x}", value, addr),
+            None => String::from("nothing"),
+        };
+        match self {
+            Mismatch::Pc { expected, actual } => write!(f, "retired the instruction at {:#x} instead of {:#x}", actual, expected),
+            Mismatch::Instruction { expected, actual } => write!(f, "read {:#010x} instead of {:#010x}", actual, expected),
+            Mismatch::Register { reg, expected, actual } => write!(f, "{:?} is {} instead of {}", reg, actual, expected),
+            Mismatch::Store { expected, actual } => write!(f, "stored {} instead of {}", store(actual), store(expected)),
+            Mismatch::Fault { expected, actual } => write!(f, "faulted with {:?} instead of {:?}", actual, expected),
+            Mismatch::Halt { expected, actual } => write!(f, "{} instead of {}",
+                if *actual { "halted" } else { "carried on" },
+                if *expected { "halting" } else { "carrying on" }),
+        }
+    }
+}
+
+/// The first point the pipeline went wrong
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub cycle: u128,
+    /// Instructions retired, including the one that went wrong
+    pub retired: u64,
+    pub pc: i32,
+    pub raw: i32,
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cycle {}, instruction {} ({:#010x} at {:#x}): {}", self.cycle, self.retired, self.raw, self.pc, self.mismatch)
+    }
+}
+
+/// Runs a simulator alongside the reference, checking the architectural state after
+/// every instruction the pipeline retires
+pub struct Lockstep {
+    reference: Reference,
+}
+
+impl Lockstep {
+    /// Starts the reference from wherever the simulator is
+    pub fn new(sim: &Simulator) -> Self {
+        Self { reference: Reference::from_simulator(sim) }
+    }
+
+    pub fn reference(&self) -> &Reference {
+        &self.reference
+    }
+
+    /// Runs one cycle, checking whatever retired in it.  Returns whether the
+    /// simulator can still be stepped.
+    pub fn step(&mut self, sim: &mut Simulator) -> Result<bool, Divergence> {
+        let retiring = sim.processor.view_retiring().cloned();
+        let retired = sim.processor.view_retired();
+        let running = sim.step();
+
+        if sim.processor.view_retired() > retired {
+            let instr = retiring.expect("something retired without being in writeback");
+            self.check(sim, &instr)?;
+        } else if let (true, Some(StopReason::Fault(actual))) = (running, sim.stop_reason()) {
+            let pc = sim.processor.view_retiring().map_or(0, |instr| instr.meta.pc);
+            let expected = match self.reference.step() {
+                Some(_) => None,
+                None => self.reference.fault.clone(),
+            };
+            if expected.as_ref() != Some(&actual) {
+                return Err(self.diverged(sim, pc, 0, Mismatch::Fault { expected, actual: Some(actual) }));
+            }
+        }
+        Ok(sim.stop_reason().is_none())
+    }
+
+    /// Steps until the program halts, faults or diverges, or `max_cycles` have run.
+    /// Returns the cycles that ran.
+    pub fn run(&mut self, sim: &mut Simulator, max_cycles: u64) -> Result<u64, Divergence> {
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            cycles += 1;
+            if !self.step(sim)? {
+                break;
+            }
+        }
+        Ok(cycles)
+    }
+
+    fn check(&mut self, sim: &Simulator, instr: &Instruction) -> Result<(), Divergence> {
+        let (pc, raw) = (instr.meta.pc, instr.instr_raw);
+        let Some(expected) = self.reference.step() else {
+            let mismatch = match self.reference.fault.clone() {
+                Some(fault) => Mismatch::Fault { expected: Some(fault), actual: None },
+                None => Mismatch::Halt { expected: true, actual: false },
+            };
+            return Err(self.diverged(sim, pc, raw, mismatch));
+        };
+
+        if expected.pc != pc {
+            return Err(self.diverged(sim, pc, raw, Mismatch::Pc { expected: expected.pc, actual: pc }));
+        }
+        if expected.raw != raw {
+            return Err(self.diverged(sim, pc, raw, Mismatch::Instruction { expected: expected.raw, actual: raw }));
+        }
+
+        let store = match instr.instr_type {
+            InstrType::Memory(MemoryType::STR) => Some((instr.meta.mem_addr, instr.meta.result as u32)),
+            _ => None,
+        };
+        if store != expected.store {
+            return Err(self.diverged(sim, pc, raw, Mismatch::Store { expected: expected.store, actual: store }));
+        }
+
+        // PC is left out, the pipeline's is wherever fetch has got to
+        let actual = sim.processor.view_registers();
+        let expected_registers = self.reference.registers();
+        if let Some(i) = (0..16).filter(|i| *i != Register::PC as usize).find(|i| actual[*i] != expected_registers[*i]) {
+            let reg = Register::from_i32(i as i32);
+            return Err(self.diverged(sim, pc, raw, Mismatch::Register { reg, expected: expected_registers[i], actual: actual[i] }));
+        }
+
+        if sim.processor.is_halted() != self.reference.is_halted() {
+            let mismatch = Mismatch::Halt { expected: self.reference.is_halted(), actual: sim.processor.is_halted() };
+            return Err(self.diverged(sim, pc, raw, mismatch));
+        }
+        Ok(())
+    }
+
+    fn diverged(&self, sim: &Simulator, pc: i32, raw: i32, mismatch: Mismatch) -> Divergence {
+        Divergence {
+            cycle: sim.processor.view_cycles(),
+            retired: self.reference.retired(),
+            pc,
+            raw,
+            mismatch,
+        }
+    }
+}
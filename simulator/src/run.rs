@@ -0,0 +1,96 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::processor::debug::DebugEvent;
+
+/// Stops a run from another thread.  Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// When `Simulator::run` should give up on a program that hasn't halted.  Every limit
+/// is optional, with none set a run only stops when the program does.
+#[derive(Clone, Debug, Default)]
+pub struct RunLimits {
+    pub max_cycles: Option<u64>,
+    /// Retired instructions, squashed ones don't count
+    pub max_instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub cancel: Option<CancelHandle>,
+}
+
+impl RunLimits {
+    pub fn with_max_cycles(mut self, cycles: u64) -> Self {
+        self.max_cycles = Some(cycles);
+        self
+    }
+
+    pub fn with_max_instructions(mut self, instructions: u64) -> Self {
+        self.max_instructions = Some(instructions);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_cancel(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StopReason {
+    Halted,
+    Fault(String),
+    CycleLimit,
+    InstructionLimit,
+    Timeout,
+    Cancelled,
+    /// A breakpoint or watchpoint was hit
+    Breakpoint(DebugEvent),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Halted => write!(f, "halted"),
+            StopReason::Fault(message) => write!(f, "fault: {}", message),
+            StopReason::CycleLimit => write!(f, "cycle limit"),
+            StopReason::InstructionLimit => write!(f, "instruction limit"),
+            StopReason::Timeout => write!(f, "timed out"),
+            StopReason::Cancelled => write!(f, "cancelled"),
+            StopReason::Breakpoint(DebugEvent::Breakpoint { id, pc }) => write!(f, "breakpoint {} at {:#x}", id, pc),
+            StopReason::Breakpoint(DebugEvent::Watchpoint { id, addr, .. }) => write!(f, "watchpoint {} on {:#x}", id, addr),
+        }
+    }
+}
+
+/// How a run ended, with the cycles and instructions it took to get there
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunResult {
+    pub reason: StopReason,
+    pub cycles: u64,
+    pub instructions: u64,
+}
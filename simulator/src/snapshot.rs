@@ -0,0 +1,84 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, MachineConfig};
+use crate::history::Checkpoint;
+
+/// Bumped whenever the saved state changes shape.  Snapshots from any other version
+/// are refused rather than restored wrongly.
+pub const SNAPSHOT_VERSION: u64 = 2;
+
+/// Everything needed to pick a machine up exactly where it was left: registers,
+/// pipeline latches, every cache line and its metadata, RAM, devices and every
+/// counter, along with the configuration of the machine it came from.  Breakpoints
+/// and watchpoints aren't included.
+///
+/// Snapshots are written as JSON, RAM lines that are all zeros are left out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u64,
+    pub config: MachineConfig,
+    pub state: Checkpoint,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io { path: String, error: std::io::Error },
+    Parse(String),
+    /// Written by a different version of the simulator
+    Version(u64),
+    /// The configuration in the snapshot doesn't describe a machine that can be built
+    Config(ConfigError),
+    /// The snapshot came from a machine built differently to the one it's loaded into
+    Mismatch(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io { path, error } => write!(f, "couldn't access {}: {}", path, error),
+            SnapshotError::Parse(message) => write!(f, "couldn't parse snapshot: {}", message),
+            SnapshotError::Version(version) => write!(f, "snapshot is version {}, only version {} can be loaded", version, SNAPSHOT_VERSION),
+            SnapshotError::Config(error) => write!(f, "snapshot has a bad configuration: {}", error),
+            SnapshotError::Mismatch(reason) => write!(f, "snapshot doesn't fit this machine: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Snapshot {
+    pub fn new(config: MachineConfig, state: Checkpoint) -> Self {
+        Self { version: SNAPSHOT_VERSION, config, state }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("snapshots always serialize")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, SnapshotError> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|e| SnapshotError::Parse(e.to_string()))?;
+
+        // Checked before anything else, a newer snapshot may not even parse
+        match value.get("version").and_then(|x| x.as_u64()) {
+            Some(SNAPSHOT_VERSION) => {},
+            Some(version) => return Err(SnapshotError::Version(version)),
+            None => return Err(SnapshotError::Parse(String::from("missing a version number"))),
+        }
+        serde_json::from_value(value).map_err(|e| SnapshotError::Parse(e.to_string()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json()).map_err(|error| SnapshotError::Io { path: path.display().to_string(), error })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| SnapshotError::Io { path: path.display().to_string(), error })?;
+        Self::from_json(&text)
+    }
+}
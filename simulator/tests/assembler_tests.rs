@@ -0,0 +1,68 @@
+use simulator::Simulator;
+use simulator::assembler::{assemble, disassemble, try_assemble};
+use simulator::processor::registers::Register;
+use simulator::run::{RunLimits, StopReason};
+
+#[test]
+fn every_register_is_encoded() {
+    let program = assemble("MOV R10, R11
+MOV R9, LR
+MOV SP, PC");
+
+    assert_eq!((program[0] >> 18) & 0xF, Register::R10 as u32);
+    assert_eq!((program[0] >> 14) & 0xF, Register::R11 as u32);
+    assert_eq!((program[1] >> 14) & 0xF, Register::LR as u32);
+    assert_eq!((program[2] >> 14) & 0xF, Register::PC as u32);
+    assert_eq!(program.iter().map(|x| disassemble(*x)).collect::<Vec<_>>(), ["MOV R10, R11", "MOV R9, LR", "MOV SP, PC"]);
+}
+
+#[test]
+fn old_register_encodings_decode_as_other_registers() {
+    // Programs assembled before LR and PC were renumbered don't mean what they said.
+    // The old LR was the decoder's PC, and the old PC didn't fit in four bits so it
+    // spilled into the register before it.
+    let with_second_register = |word: u32, reg: u32| (word & !(0xF << 14)) | reg << 14;
+
+    let old_lr = with_second_register(assemble("MOV R9, LR")[0], 0b01111);
+    assert_eq!(disassemble(old_lr), "MOV R9, PC");
+
+    let old_pc = with_second_register(assemble("MOV SP, PC")[0], 0b10000);
+    assert_eq!(disassemble(old_pc), "MOV BF, R0");
+}
+
+#[test]
+fn blank_lines_and_comments_are_skipped() {
+    // Lines are counted as written, blank ones included
+    let error = try_assemble("; counts to 3
+MOV R1, 0
+
+loop:
+").unwrap_err();
+    assert_eq!(error.line, 4);
+
+    let program = try_assemble("; counts to 3
+MOV R1, 0   ; start at zero
+
+ADD R1, 1
+CMP R1, 3
+BNE 4       ; back to the ADD
+HLT
+").unwrap();
+    assert_eq!(program.len(), 5);
+
+    let mut sim = Simulator::new();
+    sim.flash(0, &program);
+    assert_eq!(sim.run(&RunLimits::default()).reason, StopReason::Halted);
+    assert_eq!(sim.processor.view_registers()[1], 3);
+}
+
+#[test]
+fn bad_lines_are_reported_with_their_number() {
+    let error = |text: &str| try_assemble(text).unwrap_err().to_string();
+
+    assert_eq!(error("MOV R1, 1\nFOO R1, 2"), "line 2: unknown instruction FOO");
+    assert_eq!(error("MOV R1, 4096"), "line 1: 4096 doesn't fit in 12 bits");
+    assert_eq!(error("ADD 1, R2"), "line 1: operands 1,R2 don't match any addressing mode");
+    assert_eq!(error("MOV R1, R2 R3"), "line 1: can't read operands R1,R2R3");
+    assert_eq!(error("LDR R1, R2, 16"), "line 1: operands R1,R2,16 don't match any addressing mode");
+}
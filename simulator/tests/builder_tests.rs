@@ -0,0 +1,108 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::config::ConfigError;
+use simulator::memory::{CacheConfig, Console};
+use simulator::processor::default_stages;
+use simulator::processor::pipeline::StageType;
+use simulator::processor::registers::Register;
+use simulator::processor::stages;
+
+fn run(sim: &mut Simulator) {
+    sim.step_n(10_000);
+    assert!(sim.processor.is_halted(), "program never halted");
+}
+
+fn invalid_field(result: Result<Simulator, ConfigError>) -> String {
+    match result {
+        Err(ConfigError::Invalid { field, .. }) => field,
+        Err(other) => panic!("unexpected error {}", other),
+        Ok(_) => panic!("expected the builder to fail"),
+    }
+}
+
+#[test]
+fn builder_sets_up_hierarchy_and_state() {
+    let sim = Simulator::builder()
+        .with_instruction_cache(None)
+        .with_data_cache(CacheConfig::new("D", 1024, 4, 1))
+        .with_shared_cache(CacheConfig::new("", 4096, 4, 4))
+        .with_reset(16, 2048)
+        .with_register(Register::R3, 7)
+        .with_program(16, &assemble("MOV R1, 5"))
+        .build()
+        .unwrap();
+
+    assert_eq!(sim.memory.lock().unwrap().view_names(), vec!["RAM", "L2", "D"]);
+    assert_eq!(sim.instruction_memory.lock().unwrap().view_names(), vec!["RAM", "L2"]);
+
+    let regs = sim.processor.view_registers();
+    assert_eq!(regs[Register::R3 as usize], 7);
+    assert_eq!(regs[Register::SP as usize], 2048);
+    assert_eq!(regs[Register::PC as usize], 16);
+    assert_eq!(sim.memory.lock().unwrap().view_line(0)[0][4], assemble("MOV R1, 5")[0] as usize);
+}
+
+#[test]
+fn builder_rejects_impossible_geometry() {
+    let result = Simulator::builder()
+        .with_data_cache(CacheConfig::new("L1D", 3000, 4, 1))
+        .build();
+
+    assert_eq!(invalid_field(result), "memory.data_cache.size");
+}
+
+#[test]
+fn builder_rejects_stages_out_of_order() {
+    let mut stages = default_stages();
+    stages.swap(1, 2);
+
+    assert_eq!(invalid_field(Simulator::builder().with_stages(stages).build()), "stages");
+    assert_eq!(invalid_field(Simulator::builder().with_stages(vec![]).build()), "stages");
+}
+
+#[test]
+fn extra_stages_deepen_the_pipeline() {
+    let program = assemble("MOV R1, 5
+ADD R1, 3
+HLT");
+    let mut stages = default_stages();
+    stages.insert(3, (StageType::Execute, stages::pass));
+
+    let mut shallow = Simulator::builder().with_program(0, &program).build().unwrap();
+    let mut deep = Simulator::builder().with_program(0, &program).with_stages(stages).build().unwrap();
+    run(&mut shallow);
+    run(&mut deep);
+
+    assert_eq!(deep.processor.view_pipeline_status().len(), 6);
+    assert_eq!(deep.processor.view_registers()[1], 8);
+    assert!(deep.processor.view_cycles() > shallow.processor.view_cycles());
+}
+
+#[test]
+fn console_device_collects_output() {
+    let console = Console::new();
+    let output = console.output();
+    let mut sim = Simulator::builder()
+        .with_device(0xF00, Box::new(console))
+        .with_program(0, &assemble("MOV R1, 72
+MOV R2, 0xF00
+STR R1, R2
+MOV R1, 105
+STR R1, R2
+HLT"))
+        .build()
+        .unwrap();
+    run(&mut sim);
+
+    assert_eq!(String::from_utf8_lossy(&output.lock().unwrap()), "Hi");
+}
+
+#[test]
+fn builder_rejects_overlapping_devices() {
+    let result = Simulator::builder()
+        .with_device(0xF00, Box::new(Console::new()))
+        .with_device(0xF02, Box::new(Console::new()))
+        .build();
+
+    assert_eq!(invalid_field(result), "devices[1]");
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use simulator::Simulator;
+use simulator::assembler::{assemble, disassemble};
+use simulator::config::MachineConfig;
+use simulator::processor::predictor::PredictorKind;
+use simulator::processor::registers::Register;
+use simulator::processor::trace::{Commit, CommitFormat};
+use simulator::run::RunLimits;
+
+// Sums 1 to 10 into memory and reads it back
+const SUM: &str = "MOV R1, 0
+MOV R2, 10
+MOV R3, 128
+ADD R1, R2
+SUB R2, 1
+CMP R2, 0
+BNE 12
+STR R1, R3
+LDR R4, R3
+HLT";
+
+// A log the test can read back while the simulator still owns the writer
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(String::from).collect()
+    }
+}
+
+fn logged(config: &MachineConfig, format: CommitFormat) -> Vec<String> {
+    let mut sim = Simulator::from_config(config).unwrap();
+    sim.flash(0, &assemble(SUM));
+    let log = Buffer::default();
+    sim.log_commits(log.clone(), format);
+    sim.run(&RunLimits::default());
+    sim.finish_commit_log().unwrap();
+    log.lines()
+}
+
+#[test]
+fn disassembly_reads_back_as_the_same_program() {
+    let program = assemble(SUM);
+    let text: Vec<String> = program.iter().map(|x| disassemble(*x)).collect();
+
+    assert_eq!(text.join("\n"), SUM);
+    assert_eq!(disassemble(0xFFFF_FFFF), ".word 0xffffffff");
+}
+
+#[test]
+fn every_retired_instruction_is_logged() {
+    let lines = logged(&MachineConfig::default(), CommitFormat::JsonLines);
+    let commits: Vec<Commit> = lines.iter().map(|x| serde_json::from_str(x).unwrap()).collect();
+
+    assert_eq!(commits.len(), 46);
+    assert!(commits.windows(2).all(|x| x[0].cycle < x[1].cycle));
+    assert_eq!(commits[0].disassembly, "MOV R1, 0");
+
+    let store = &commits[43];
+    assert_eq!(store.disassembly, "STR R1, R3");
+    assert_eq!((store.reg, store.addr, store.data), (None, Some(128), Some(55)));
+    let load = &commits[44];
+    assert_eq!((load.reg, load.value, load.addr, load.data), (Some(Register::R4), Some(55), Some(128), Some(55)));
+    let halt = &commits[45];
+    assert_eq!((halt.pc, halt.disassembly.as_str(), halt.reg), (36, "HLT", None));
+}
+
+#[test]
+fn text_lines_have_fixed_columns() {
+    let lines = logged(&MachineConfig::default(), CommitFormat::Text);
+
+    assert_eq!(lines.len(), 46);
+    let load = lines.iter().find(|x| x.contains("LDR")).unwrap();
+    assert_eq!(load, "       120 0x00000020 0x2010c000  LDR R4, R3           R4=0x00000037 [0x00000080]=0x00000037");
+    assert!(lines.last().unwrap().ends_with("0x00000024 0x62000000  HLT"));
+}
+
+#[test]
+fn logs_only_differ_in_cycles_between_machines() {
+    let mut fast = MachineConfig::default();
+    fast.pipeline.forwarding = true;
+    fast.pipeline.predictor = PredictorKind::Bimodal;
+
+    let strip = |lines: Vec<String>| -> Vec<Commit> {
+        lines.iter().map(|x| Commit { cycle: 0, ..serde_json::from_str(x).unwrap() }).collect()
+    };
+    let slow = logged(&MachineConfig::default(), CommitFormat::JsonLines);
+    let fast = logged(&fast, CommitFormat::JsonLines);
+
+    assert_ne!(slow, fast);
+    assert_eq!(strip(slow), strip(fast));
+}
+
+#[test]
+fn stepping_back_does_not_log_twice() {
+    let mut sim = Simulator::builder().with_history(20, 8).with_program(0, &assemble(SUM)).build().unwrap();
+    let log = Buffer::default();
+    sim.log_commits(log.clone(), CommitFormat::JsonLines);
+
+    sim.step_n(100);
+    let before = log.lines().len();
+    sim.step_back(30);
+    assert_eq!(log.lines().len(), before);
+
+    // Running the same cycles again logs them again
+    sim.step_n(30);
+    assert!(log.lines().len() > before);
+}
@@ -0,0 +1,132 @@
+use simulator::{Simulator, SimulatorBuilder};
+use simulator::config::{ConfigError, MachineConfig};
+use simulator::memory::{AllocatePolicy, CacheConfig, Replacement, WritePolicy};
+use simulator::processor::predictor::PredictorKind;
+
+const FULL_CONFIG: &str = r#"
+[memory]
+block_size = 8
+ram_latency = 20
+
+[memory.data_cache]
+size = 1024
+associativity = 4
+replacement = "lru"
+write_policy = "write-through"
+allocate_policy = "no-write-allocate"
+write_buffer = 4
+
+[[memory.shared_caches]]
+size = 8192
+associativity = 8
+latency = 6
+replacement = { random = { seed = 7 } }
+
+[pipeline]
+predictor = "bimodal"
+forwarding = true
+
+[reset]
+pc = 64
+sp = 4096
+"#;
+
+#[test]
+fn toml_config_sets_every_section() {
+    let config = MachineConfig::from_toml(FULL_CONFIG).unwrap();
+
+    assert_eq!(config.memory.block_size, 8);
+    assert_eq!(config.memory.ram_latency, 20);
+    assert_eq!(config.memory.word_size, 4);
+
+    let l1d = config.memory.data_cache.as_ref().unwrap();
+    assert_eq!(l1d.name, "L1D");
+    assert_eq!(l1d.replacement, Replacement::Lru);
+    assert_eq!(l1d.write_policy, WritePolicy::WriteThrough);
+    assert_eq!(l1d.allocate_policy, AllocatePolicy::NoWriteAllocate);
+    assert_eq!(l1d.write_buffer, Some(4));
+    assert_eq!(config.memory.shared_caches[0].name, "L2");
+    assert_eq!(config.memory.shared_caches[0].replacement, Replacement::Random { seed: 7 });
+
+    assert_eq!(config.pipeline.predictor, PredictorKind::Bimodal);
+    assert!(config.pipeline.forwarding);
+
+    let sim = Simulator::from_config(&config).unwrap();
+    assert_eq!(sim.memory.lock().unwrap().view_names(), vec!["RAM", "L2", "L1D"]);
+    assert_eq!(sim.processor.view_registers()[12], 4096);
+    assert_eq!(sim.processor.view_registers()[15], 64);
+}
+
+#[test]
+fn json_and_toml_round_trip() {
+    let config = MachineConfig::from_toml(FULL_CONFIG).unwrap();
+
+    assert_eq!(MachineConfig::from_json(&config.to_json()).unwrap(), config);
+    assert_eq!(MachineConfig::from_toml(&config.to_toml()).unwrap(), config);
+}
+
+#[test]
+fn empty_config_is_the_default_machine() {
+    assert_eq!(MachineConfig::from_toml("").unwrap(), MachineConfig::default());
+    assert_eq!(MachineConfig::from_json("{}").unwrap(), MachineConfig::default());
+}
+
+#[test]
+fn unnamed_caches_are_named_however_the_machine_is_built() {
+    let mut config = MachineConfig::default();
+    config.memory.data_cache = Some(CacheConfig::new("", 4096, 4, 1));
+    config.memory.shared_caches.push(CacheConfig::new("", 16384, 4, 4));
+
+    let names = |sim: Simulator| sim.view_levels().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    let from_config = names(Simulator::from_config(&config).unwrap());
+    let built = names(SimulatorBuilder::from_config(config).build().unwrap());
+
+    assert_eq!(from_config, built);
+    assert!(from_config.contains(&String::from("L1D")) && from_config.contains(&String::from("L2")));
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let err = MachineConfig::from_toml("[memory.data_cache]\nassociativty = 4").unwrap_err();
+
+    assert!(matches!(err, ConfigError::Parse(_)));
+    assert!(err.to_string().contains("associativty"));
+}
+
+#[test]
+fn impossible_geometry_names_the_field() {
+    let err = MachineConfig::from_toml("[memory.data_cache]\nsize = 1000\nassociativity = 4").unwrap_err();
+
+    match err {
+        ConfigError::Invalid { field, .. } => assert_eq!(field, "memory.data_cache.size"),
+        other => panic!("unexpected error {}", other),
+    }
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    let invalid_field = |text: &str| match MachineConfig::from_toml(text) {
+        Err(ConfigError::Invalid { field, .. }) => field,
+        other => panic!("expected a validation error, got {:?}", other),
+    };
+
+    assert_eq!(invalid_field("[[memory.shared_caches]]\nlatency = 0"), "memory.shared_caches[0].latency");
+    assert_eq!(invalid_field("[[memory.shared_caches]]\nname = \"L1D\""), "memory.shared_caches[0].name");
+    assert_eq!(invalid_field("[memory.instruction_cache]\nwrite_buffer = 0"), "memory.instruction_cache.write_buffer");
+    assert_eq!(invalid_field("[reset]\npc = 6"), "reset.pc");
+    assert_eq!(invalid_field("[pipeline]\nbtb_entries = 0"), "pipeline.btb_entries");
+    assert_eq!(invalid_field("[memory]\nword_size = 8"), "memory.word_size");
+}
+
+#[test]
+fn load_picks_format_from_extension() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("ironleg-config-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{ "pipeline": { "predictor": "always-taken" } }"#).unwrap();
+
+    let config = MachineConfig::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config.pipeline.predictor, PredictorKind::AlwaysTaken);
+
+    assert!(matches!(MachineConfig::load(dir.join("machine.yaml")), Err(ConfigError::Io { .. })));
+}
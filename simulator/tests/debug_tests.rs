@@ -0,0 +1,108 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::processor::debug::{Access, BreakStage, Breakpoint, Comparison, Condition, DebugEvent, Watchpoint};
+use simulator::processor::registers::Register;
+use simulator::run::{RunLimits, StopReason};
+
+fn loaded(program: &str) -> Simulator {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble(program));
+    sim
+}
+
+fn run(sim: &mut Simulator) -> StopReason {
+    sim.run(&RunLimits::default().with_max_cycles(10_000)).reason
+}
+
+#[test]
+fn retire_breakpoint_stops_before_commit() {
+    let mut sim = loaded("MOV R1, 5
+ADD R1, R1
+ADD R1, 3
+HLT");
+    let id = sim.add_breakpoint(Breakpoint::new(8));
+
+    assert_eq!(run(&mut sim), StopReason::Breakpoint(DebugEvent::Breakpoint { id, pc: 8 }));
+    assert_eq!(sim.processor.view_registers()[1], 10);
+    assert_eq!(sim.processor.view_retired(), 2);
+
+    assert_eq!(run(&mut sim), StopReason::Halted);
+    assert_eq!(sim.processor.view_registers()[1], 13);
+}
+
+#[test]
+fn conditional_breakpoint_checks_registers() {
+    let mut sim = loaded("MOV R1, 5
+SUB R1, 1
+CMP R1, 0
+BNE 4
+HLT");
+    sim.add_breakpoint(Breakpoint::new(4).when(Condition { reg: Register::R1, op: Comparison::Eq, value: 2 }));
+
+    assert!(matches!(run(&mut sim), StopReason::Breakpoint(_)));
+    assert_eq!(sim.processor.view_registers()[1], 2);
+    assert_eq!(run(&mut sim), StopReason::Halted);
+}
+
+#[test]
+fn fetch_breakpoints_see_the_wrong_path() {
+    let program = "B 8
+MOV R5, 1
+HLT";
+    let mut fetched = loaded(program);
+    fetched.add_breakpoint(Breakpoint::new(4).at(BreakStage::Fetch));
+    let mut retired = loaded(program);
+    retired.add_breakpoint(Breakpoint::new(4));
+
+    assert!(matches!(run(&mut fetched), StopReason::Breakpoint(DebugEvent::Breakpoint { pc: 4, .. })));
+    assert_eq!(run(&mut retired), StopReason::Halted);
+    assert_eq!(retired.processor.view_registers()[5], 0);
+}
+
+#[test]
+fn watchpoints_match_access_kind() {
+    let program = "MOV R1, 5
+MOV R2, 64
+STR R1, R2
+LDR R3, R2
+HLT";
+    let mut writes = loaded(program);
+    let id = writes.add_watchpoint(Watchpoint::new(60, 68, Access::Write));
+    let mut reads = loaded(program);
+    reads.add_watchpoint(Watchpoint::new(64, 65, Access::Read));
+
+    assert_eq!(run(&mut writes), StopReason::Breakpoint(DebugEvent::Watchpoint { id, addr: 64, access: Access::Write, value: 5 }));
+    assert!(matches!(run(&mut reads), StopReason::Breakpoint(DebugEvent::Watchpoint { access: Access::Read, value: 5, .. })));
+    assert_eq!(run(&mut writes), StopReason::Halted);
+}
+
+#[test]
+fn watchpoints_catch_accesses_that_overlap_them() {
+    let program = "MOV R1, 7
+MOV R2, 254
+STR R1, R2
+HLT";
+    // The store's word runs from 0xFE to 0x101, so it covers the watched byte
+    let mut sim = loaded(program);
+    let id = sim.add_watchpoint(Watchpoint::new(0x100, 0x101, Access::Write));
+    assert_eq!(run(&mut sim), StopReason::Breakpoint(DebugEvent::Watchpoint { id, addr: 0xFE, access: Access::Write, value: 7 }));
+
+    let mut sim = loaded(program);
+    sim.add_watchpoint(Watchpoint::new(0x102, 0x104, Access::Write));
+    assert_eq!(run(&mut sim), StopReason::Halted);
+}
+
+#[test]
+fn removed_breakpoints_stop_firing() {
+    let mut sim = loaded("MOV R1, 5
+HLT");
+    let breakpoint = sim.add_breakpoint(Breakpoint::new(0));
+    let watchpoint = sim.add_watchpoint(Watchpoint::new(0, 4096, Access::Any));
+    assert_eq!(sim.breakpoints().len(), 1);
+
+    assert!(sim.remove_breakpoint(breakpoint));
+    assert!(sim.remove_breakpoint(watchpoint));
+    assert!(!sim.remove_breakpoint(breakpoint));
+    assert!(sim.watchpoints().is_empty());
+    assert_eq!(run(&mut sim), StopReason::Halted);
+}
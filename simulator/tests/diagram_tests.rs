@@ -0,0 +1,129 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::processor::diagram::{Fate, Occupancy};
+use simulator::processor::pipeline::StageType;
+use simulator::processor::stages;
+use simulator::run::RunLimits;
+
+// Counts down from 2, the branch is predicted not taken so the first pass squashes
+// what was fetched after it
+const COUNTDOWN: &str = "MOV R1, 2
+SUB R1, 1
+CMP R1, 0
+BNE 4
+HLT";
+
+fn recorded(program: &str) -> Simulator {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble(program));
+    sim.record_pipeline(1000);
+    sim.run(&RunLimits::default());
+    sim
+}
+
+#[test]
+fn instructions_move_through_every_stage() {
+    let sim = recorded(COUNTDOWN);
+    let diagram = sim.pipeline_trace().unwrap();
+    assert_eq!(diagram.stages, ["F", "D", "E", "M", "W"]);
+
+    let first = diagram.instructions().next().unwrap();
+    let stages: Vec<usize> = first.stages.iter().map(|x| x.stage).collect();
+    assert_eq!(stages, [0, 1, 2, 3, 4]);
+    // Each stage starts the cycle after the last one ended
+    assert!(first.stages.windows(2).all(|x| x[1].start == x[0].end + 1));
+    assert_eq!(first.fate, Some(Fate::Retired(first.stages[4].end)));
+
+    let retired = diagram.instructions().filter(|x| matches!(x.fate, Some(Fate::Retired(_)))).count();
+    assert_eq!(retired as u64, sim.processor.view_retired());
+}
+
+#[test]
+fn mispredicted_branches_squash_what_follows() {
+    let sim = recorded(COUNTDOWN);
+    let diagram = sim.pipeline_trace().unwrap();
+
+    let branch = diagram.instructions().find(|x| x.pc == 12).unwrap();
+    let Some(Fate::Retired(resolved)) = branch.fate else { panic!("the branch should retire") };
+    let squashed: Vec<i32> = diagram.instructions()
+        .filter(|x| x.fate == Some(Fate::Squashed(resolved)))
+        .map(|x| x.pc)
+        .collect();
+    assert_eq!(squashed[0], 16);
+
+    let chart = diagram.to_chart(0, sim.processor.view_cycles());
+    let halt = chart.lines().find(|x| x.contains("HLT") && x.ends_with('x')).unwrap();
+    assert!(halt.trim_end_matches('x').ends_with('D') || halt.trim_end_matches('x').ends_with('E'), "{}", halt);
+}
+
+#[test]
+fn kanata_log_introduces_stages_and_retires() {
+    let diagram = recorded(COUNTDOWN).pipeline_trace().unwrap();
+    let log = diagram.to_kanata();
+    let mut lines = log.lines();
+
+    assert_eq!(lines.next(), Some("Kanata\t0004"));
+    assert_eq!(lines.next(), Some("C=\t0"));
+    assert_eq!(lines.next(), Some("I\t0\t1\t0"));
+    assert_eq!(lines.next(), Some("L\t0\t0\t0x0000 MOV R1, 2"));
+    assert_eq!(lines.next(), Some("S\t0\t0\tF"));
+
+    let intros = log.lines().filter(|x| x.starts_with("I\t")).count();
+    let retires = log.lines().filter(|x| x.starts_with("R\t") && x.ends_with("\t0")).count();
+    let flushes = log.lines().filter(|x| x.starts_with("R\t") && x.ends_with("\t1")).count();
+    assert_eq!(intros, diagram.instructions().count());
+    assert_eq!(retires, diagram.instructions().filter(|x| matches!(x.fate, Some(Fate::Retired(_)))).count());
+    assert_eq!(flushes, diagram.instructions().filter(|x| matches!(x.fate, Some(Fate::Squashed(_)))).count());
+}
+
+#[test]
+fn repeated_stages_get_their_own_lanes() {
+    let mut sim = Simulator::builder()
+        .with_stages(vec![
+            (StageType::Fetch, stages::fetch),
+            (StageType::Decode, stages::decode),
+            (StageType::Execute, stages::execute),
+            (StageType::Execute, stages::pass),
+            (StageType::Memory, stages::memory),
+            (StageType::Writeback, stages::writeback),
+        ])
+        .with_program(0, &assemble("MOV R1, 1\nHLT"))
+        .build()
+        .unwrap();
+    sim.record_pipeline(100);
+    sim.run(&RunLimits::default());
+
+    let diagram = sim.pipeline_trace().unwrap();
+    assert_eq!(diagram.stages, ["F", "D", "E", "E2", "M", "W"]);
+    assert_eq!(diagram.instructions().next().unwrap().stages.len(), 6);
+}
+
+#[test]
+fn stepping_back_rewrites_the_diagram() {
+    let mut sim = Simulator::builder().with_history(10, 8).with_program(0, &assemble(COUNTDOWN)).build().unwrap();
+    sim.record_pipeline(1000);
+    sim.step_n(15);
+    let at_15 = sim.pipeline_trace().unwrap();
+
+    assert_eq!(sim.step_n(10), 10);
+    assert_eq!(sim.step_back(10), 10);
+    let again = sim.pipeline_trace().unwrap();
+    assert_eq!(again.instructions().collect::<Vec<_>>(), at_15.instructions().collect::<Vec<_>>());
+
+    // Nothing recorded reaches past the cycle the machine is on
+    let last = again.instructions().flat_map(|x| x.stages.iter()).map(|x: &Occupancy| x.end).max().unwrap();
+    assert_eq!(last, 14);
+}
+
+#[test]
+fn only_the_most_recent_instructions_are_kept() {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble(COUNTDOWN));
+    sim.record_pipeline(3);
+    sim.run(&RunLimits::default());
+
+    let diagram = sim.pipeline_trace().unwrap();
+    assert_eq!(diagram.instructions().count(), 3);
+    sim.record_pipeline(0);
+    assert!(sim.pipeline_trace().is_none());
+}
@@ -0,0 +1,88 @@
+use simulator::assembler::assemble;
+use simulator::config::MachineConfig;
+use simulator::explore::{to_csv, to_json, Exploration, Grid, Outcome};
+use simulator::processor::predictor::PredictorKind;
+use simulator::run::StopReason;
+
+// Walks an array a block at a time, twice
+const WALK: &str = "MOV R3, 0
+MOV R1, 0
+MOV R2, 256
+LDR R4, R2
+ADD R4, 1
+STR R4, R2
+ADD R2, 16
+ADD R1, 1
+CMP R1, 64
+BNE 12
+ADD R3, 1
+CMP R3, 2
+BNE 4
+HLT";
+
+fn grid() -> Grid {
+    Grid::from_toml("cache_size = [256, 2048]
+associativity = [1, 2]
+predictor = [\"not-taken\", \"bimodal\"]
+forwarding = [false, true]").unwrap()
+}
+
+#[test]
+fn every_combination_is_run_in_order() {
+    let outcomes = Exploration::new(MachineConfig::default(), grid()).with_threads(4).run(&assemble(WALK));
+
+    assert_eq!(outcomes.len(), 16);
+    assert_eq!(grid().point_count(), 16);
+    assert_eq!(outcomes[0].point.cache_size, 256);
+    assert!(!outcomes[0].point.forwarding);
+    assert!(outcomes[1].point.forwarding);
+    assert_eq!(outcomes[15].point.predictor, PredictorKind::Bimodal);
+    // Axes the grid leaves out keep the base machine's value
+    assert!(outcomes.iter().all(|x| x.point.block_size == 16 && x.point.latency == 1));
+
+    for outcome in &outcomes {
+        assert_eq!(outcome.stop, Some(StopReason::Halted));
+        assert_eq!(outcome.instructions, outcomes[0].instructions);
+        assert_eq!(outcome.cpi, outcome.cycles as f64 / outcome.instructions as f64);
+    }
+    let l1d = |x: &Outcome| x.miss_rates.iter().find(|(name, _)| name == "L1D").unwrap().1;
+    assert!(l1d(&outcomes[0]) > l1d(&outcomes[8]));
+    assert!(outcomes[3].cycles < outcomes[0].cycles);
+}
+
+#[test]
+fn threads_do_not_change_the_results() {
+    let program = assemble(WALK);
+    let one = Exploration::new(MachineConfig::default(), grid()).with_threads(1).run(&program);
+    let many = Exploration::new(MachineConfig::default(), grid()).with_threads(8).run(&program);
+
+    assert_eq!(one, many);
+}
+
+#[test]
+fn machines_that_cannot_be_built_are_reported() {
+    let grid = Grid { cache_size: vec![1024, 1000], ..Grid::default() };
+    let outcomes = Exploration::new(MachineConfig::default(), grid).with_max_cycles(50).run(&assemble(WALK));
+
+    assert_eq!(outcomes[0].stop, Some(StopReason::CycleLimit));
+    assert_eq!(outcomes[0].cycles, 50);
+    assert!(outcomes[1].stop.is_none());
+    assert!(outcomes[1].error.as_ref().unwrap().contains("memory.instruction_cache.size"));
+}
+
+#[test]
+fn results_are_written_as_csv_and_json() {
+    let grid = Grid { associativity: vec![1, 2], latency: vec![1, 0], ..Grid::default() };
+    let outcomes = Exploration::new(MachineConfig::default(), grid).run(&assemble(WALK));
+    let csv = to_csv(&outcomes);
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!(lines[0], "cache_size,associativity,block_size,latency,predictor,forwarding,stop,cycles,instructions,cpi,RAM_miss_rate,L1D_miss_rate,L1I_miss_rate");
+    assert_eq!(lines.len(), 5);
+    assert!(lines[1].starts_with("16384,1,16,1,not-taken,false,halted,"));
+    assert!(lines[2].starts_with("16384,1,16,0,not-taken,false,\"invalid: "));
+    assert_eq!(lines[1].split(',').count(), 13);
+
+    let parsed: Vec<Outcome> = serde_json::from_str(&to_json(&outcomes)).unwrap();
+    assert_eq!(parsed, outcomes);
+}
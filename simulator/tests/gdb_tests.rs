@@ -0,0 +1,156 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::gdb::{GdbStub, target_xml};
+
+// A debugger's end of the connection, speaking in packets
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |sum, x| sum.wrapping_add(x));
+        write!(self.stream, "${}#{:02x}", packet, sum).unwrap();
+        assert_eq!(self.byte(), b'+');
+        self.reply()
+    }
+
+    // `k` gets no reply, the stub just hangs up
+    fn kill(&mut self) {
+        self.stream.write_all(b"$k#6b").unwrap();
+        assert_eq!(self.byte(), b'+');
+    }
+
+    fn reply(&mut self) -> String {
+        while self.byte() != b'$' {}
+        let mut data = vec![];
+        loop {
+            match self.byte() {
+                b'#' => break,
+                b'}' => data.push(self.byte() ^ 0x20),
+                byte => data.push(byte),
+            }
+        }
+        let mut sum = [0; 2];
+        self.stream.read_exact(&mut sum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+// Serves `program` on a port of its own and connects to it
+fn connect(program: &str) -> (Client, JoinHandle<Simulator>) {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble(program));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stub = GdbStub::new(sim);
+        stub.serve(stream).unwrap();
+        stub.into_simulator()
+    });
+    (Client { stream: TcpStream::connect(addr).unwrap() }, server)
+}
+
+fn register(registers: &str, reg: usize) -> u32 {
+    u32::from_str_radix(&registers[reg * 8..reg * 8 + 8], 16).unwrap().swap_bytes()
+}
+
+#[test]
+fn target_description_lists_sixteen_registers() {
+    let (mut client, server) = connect("HLT");
+    assert!(client.send("qSupported:multiprocess+;swbreak+").contains("qXfer:features:read+"));
+
+    let xml = target_xml();
+    let first = client.send("qXfer:features:read:target.xml:0,40");
+    assert_eq!(first, format!("m{}", &xml[..0x40]));
+    let rest = client.send(&format!("qXfer:features:read:target.xml:40,{:x}", xml.len()));
+    assert_eq!(rest, format!("l{}", &xml[0x40..]));
+
+    assert_eq!(xml.matches("<reg ").count(), 16);
+    assert!(xml.contains("name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"15\""));
+    assert_eq!(client.send("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn breakpoints_stop_continue_and_step_shows_registers() {
+    let (mut client, server) = connect("MOV R1, 5
+ADD R1, R1
+ADD R1, 3
+HLT");
+    assert_eq!(client.send("Z0,8,4"), "OK");
+    assert_eq!(client.send("c"), "T05swbreak:;");
+
+    let registers = client.send("g");
+    assert_eq!(registers.len(), 16 * 8);
+    assert_eq!(register(&registers, 1), 10);
+    assert_eq!(register(&registers, 15), 8);
+    assert_eq!(client.send("p1"), "0a000000");
+
+    assert_eq!(client.send("z0,8,4"), "OK");
+    assert_eq!(client.send("s"), "S05");
+    assert_eq!(register(&client.send("g"), 1), 13);
+
+    assert_eq!(client.send("c"), "W00");
+    client.kill();
+    assert_eq!(server.join().unwrap().processor.view_registers()[1], 13);
+}
+
+#[test]
+fn memory_and_registers_can_be_written() {
+    let (mut client, server) = connect("MOV R2, 256
+LDR R1, R2
+ADD R1, R3
+HLT");
+    // Words are little endian, a byte write leaves the rest of the word alone
+    assert_eq!(client.send("M100,4:78563412"), "OK");
+    assert_eq!(client.send("M101,1:ff"), "OK");
+    assert_eq!(client.send("m100,4"), "78ff3412");
+    assert_eq!(client.send("P3=01000000"), "OK");
+    assert_eq!(client.send("Pf=00000000"), "E01");
+
+    assert_eq!(client.send("c"), "W00");
+    client.kill();
+    assert_eq!(server.join().unwrap().processor.view_registers()[1], 0x1234ff79);
+}
+
+#[test]
+fn memory_reads_are_bounded() {
+    let (mut client, server) = connect("HLT");
+    assert_eq!(client.send("mffffffffffffffff,2"), "E01");
+    assert_eq!(client.send("mfffffffffffffffe,ffffffffffffffff"), "E01");
+    assert_eq!(client.send("Mffffffffffffffff,2:0102"), "E01");
+    assert_eq!(client.send("m0,zz"), "E01");
+    // A read asking for more than a packet holds gets as much as fits
+    assert_eq!(client.send("m0,ffffffff").len(), 0x4000);
+    assert!(client.send("qXfer:features:read:target.xml:10,ffffffffffffffff").starts_with('l'));
+    assert_eq!(client.send("Z2,ffffffffffffffff,4"), "E01");
+    assert_eq!(client.send("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn watchpoints_report_the_address() {
+    let (mut client, server) = connect("MOV R1, 7
+MOV R2, 256
+STR R1, R2
+HLT");
+    assert_eq!(client.send("Z2,100,4"), "OK");
+    assert_eq!(client.send("c"), "T05watch:100;");
+    assert_eq!(client.send("m100,4"), "07000000");
+    assert_eq!(client.send("c"), "W00");
+    client.kill();
+    server.join().unwrap();
+}
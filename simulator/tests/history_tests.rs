@@ -0,0 +1,130 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::memory::{Console, HierarchyConfig, MemoryStats};
+use simulator::processor::debug::Breakpoint;
+use simulator::processor::pipeline::StageResult;
+use simulator::run::{RunLimits, StopReason};
+
+const LOOP: &str = "MOV R1, 0
+MOV R2, 64
+ADD R1, 1
+STR R1, R2
+CMP R1, 20
+BNE 8
+HLT";
+
+fn loaded(program: &str, interval: u64, checkpoints: usize) -> Simulator {
+    Simulator::builder()
+        .with_history(interval, checkpoints)
+        .with_program(0, &assemble(program))
+        .build()
+        .unwrap()
+}
+
+type State = ([i32; 16], u128, u64, Vec<MemoryStats>, Vec<Vec<usize>>, Vec<StageResult>);
+
+fn state(sim: &Simulator) -> State {
+    let memory = sim.memory.lock().unwrap();
+    (
+        sim.processor.view_registers(),
+        sim.processor.view_cycles(),
+        sim.processor.view_retired(),
+        memory.view_stats(),
+        memory.view_line(4),
+        sim.processor.view_pipeline_status(),
+    )
+}
+
+#[test]
+fn step_back_returns_to_an_earlier_cycle() {
+    let mut sim = loaded(LOOP, 16, 32);
+    sim.step_n(90);
+    let earlier = state(&sim);
+    sim.step_n(57);
+
+    assert_eq!(sim.step_back(57), 57);
+    assert_eq!(state(&sim), earlier);
+
+    // Carrying on from there ends up exactly where an untouched run does
+    let mut untouched = loaded(LOOP, 16, 32);
+    assert_eq!(sim.run(&RunLimits::default()).reason, StopReason::Halted);
+    assert_eq!(untouched.run(&RunLimits::default()).reason, StopReason::Halted);
+    assert_eq!(state(&sim), state(&untouched));
+    assert_eq!(sim.processor.view_registers()[1], 20);
+}
+
+#[test]
+fn step_back_by_instructions() {
+    let mut sim = loaded(LOOP, 16, 32);
+    sim.run(&RunLimits::default().with_max_instructions(30));
+    let retired = sim.processor.view_retired();
+
+    assert_eq!(sim.step_back_instructions(5), 5);
+    assert_eq!(sim.processor.view_retired(), retired - 5);
+
+    // Lands on the cycle the instruction retired in, not any later
+    let cycles = sim.processor.view_cycles();
+    sim.step_back(1);
+    assert_eq!(sim.processor.view_retired(), retired - 6);
+    assert_eq!(sim.processor.view_cycles(), cycles - 1);
+}
+
+#[test]
+fn history_depth_is_bounded() {
+    let mut sim = loaded(LOOP, 10, 2);
+    sim.step_n(100);
+
+    assert_eq!(sim.history_start(), Some(80));
+    assert_eq!(sim.step_back(100), 20);
+    assert_eq!(sim.processor.view_cycles(), 80);
+
+    let mut disabled = loaded(LOOP, 10, 0);
+    disabled.step_n(100);
+    assert_eq!(disabled.step_back(10), 0);
+    assert_eq!(disabled.processor.view_cycles(), 100);
+}
+
+#[test]
+fn step_back_from_halt_and_breakpoints() {
+    let mut sim = loaded(LOOP, 16, 32);
+    let mut plain = loaded(LOOP, 16, 32);
+    sim.add_breakpoint(Breakpoint::new(16));
+
+    assert!(matches!(sim.run(&RunLimits::default()).reason, StopReason::Breakpoint(_)));
+    while sim.run(&RunLimits::default()).reason != StopReason::Halted {}
+    plain.run(&RunLimits::default());
+
+    // Stopping at a breakpoint doesn't cost the pipeline any cycles
+    assert_eq!(sim.processor.view_cycles(), plain.processor.view_cycles());
+
+    sim.step_back_instructions(1);
+    assert_eq!(sim.stop_reason(), None);
+    assert_eq!(sim.run(&RunLimits::default()).reason, StopReason::Halted);
+}
+
+#[test]
+fn step_back_restores_devices() {
+    let console = Console::new();
+    let output = console.output();
+    let mut sim = Simulator::builder()
+        .with_hierarchy(HierarchyConfig { instruction_cache: None, data_cache: None, ..HierarchyConfig::default() })
+        .with_history(4, 64)
+        .with_device(0x1000, Box::new(console))
+        .with_program(0, &assemble("MOV R1, 4095
+ADD R1, 1
+MOV R2, 72
+STR R2, R1
+MOV R2, 105
+STR R2, R1
+HLT"))
+        .build()
+        .unwrap();
+
+    sim.run(&RunLimits::default());
+    assert_eq!(output.lock().unwrap().as_slice(), b"Hi");
+
+    sim.step_back_instructions(2);
+    assert_eq!(output.lock().unwrap().as_slice(), b"H");
+    sim.run(&RunLimits::default());
+    assert_eq!(output.lock().unwrap().as_slice(), b"Hi");
+}
@@ -1,10 +1,28 @@
-use simulator::memory::{Memory, Cache, RAM, MemoryValue, Transparency};
+use simulator::memory::{Memory, Cache, RAM, MemoryValue, Transparency, Replacement};
+use simulator::memory::{WritePolicy, AllocatePolicy, WriteBuffer, SharedMemory};
+use simulator::memory::{Inclusion, CacheConfig, HierarchyConfig};
 use simulator::processor::pipeline::StageType;
 
 fn new_mem() -> Box<Cache> {
     let ram = Box::new(RAM::new(4194304, 16, 4, 5));
-    let cache = Box::new(Cache::new(2048, 16, 4, 1, 2, ram));
-    cache
+    Box::new(Cache::new(2048, 16, 4, 1, 2, ram))
+}
+
+fn new_mem_with(replacement: Replacement) -> Box<Cache> {
+    let ram = Box::new(RAM::new(4096, 16, 4, 5));
+    Box::new(Cache::new(2048, 16, 4, 1, 2, ram).with_replacement(replacement))
+}
+
+fn read_until_done(mem: &mut Box<Cache>, addr: usize) -> usize {
+    loop {
+        if let Some(MemoryValue::Value(x)) = mem.read(addr, StageType::Memory, false) {
+            return x;
+        }
+    }
+}
+
+fn write_until_done(mem: &mut Box<Cache>, addr: usize, value: usize) {
+    while !mem.write(addr, &MemoryValue::Value(value), StageType::Memory) {}
 }
 
 #[test]
@@ -39,3 +57,338 @@ fn read_from_cache() {
 }
 
 
+
+#[test]
+fn stats_count_hits_and_misses() {
+    let mut mem = new_mem();
+
+    read_until_done(&mut mem, 0);
+    read_until_done(&mut mem, 4);
+    write_until_done(&mut mem, 8, 1);
+
+    let stats = mem.view_stats();
+    assert_eq!(1, stats[1].read_misses);
+    assert_eq!(1, stats[1].read_hits);
+    assert_eq!(1, stats[1].write_hits);
+    assert_eq!(1, stats[1].compulsory_misses);
+    assert_eq!(1, stats[0].read_hits);
+    assert!(stats[1].wait_cycles > 0);
+}
+
+#[test]
+fn stats_classify_conflict_misses() {
+    let mut mem = new_mem();
+
+    // Three blocks that all map to the same two-way set
+    for _ in 0..2 {
+        for addr in [0, 2048, 4096] {
+            read_until_done(&mut mem, addr);
+        }
+    }
+
+    let stats = mem.view_stats();
+    assert_eq!(3, stats[1].compulsory_misses);
+    assert_eq!(0, stats[1].capacity_misses);
+    assert!(stats[1].conflict_misses > 0);
+    assert!(stats[1].evictions > 0);
+}
+
+#[test]
+fn stats_count_dirty_writebacks() {
+    let mut mem = new_mem();
+
+    write_until_done(&mut mem, 0, 30);
+    write_until_done(&mut mem, 2048, 31);
+    for addr in [4096, 6144] {
+        read_until_done(&mut mem, addr);
+    }
+
+    let stats = mem.view_stats();
+    assert_eq!(1, stats[1].writebacks);
+    assert_eq!(1, stats[0].write_hits);
+    assert_eq!(30, read_until_done(&mut mem, 0));
+}
+
+#[test]
+fn lru_keeps_recently_used_line() {
+    let mut mem = new_mem_with(Replacement::Lru);
+
+    for addr in [0, 2048, 0, 4096, 0] {
+        read_until_done(&mut mem, addr);
+    }
+    assert_eq!(2, mem.view_stats()[1].read_hits);
+}
+
+#[test]
+fn fifo_evicts_oldest_fill() {
+    let mut mem = new_mem_with(Replacement::Fifo);
+
+    for addr in [0, 2048, 0, 4096, 0] {
+        read_until_done(&mut mem, addr);
+    }
+    assert_eq!(1, mem.view_stats()[1].read_hits);
+}
+
+#[test]
+fn tree_plru_tracks_recent_use() {
+    let mut policy = Replacement::TreePlru.build(1, 4);
+    for way in 0..4 {
+        policy.fill(0, way);
+    }
+    assert_eq!(0, policy.victim(0));
+
+    policy.touch(0, 0);
+    assert_eq!(2, policy.victim(0));
+}
+
+#[test]
+fn lfu_evicts_least_used() {
+    let mut policy = Replacement::Lfu.build(1, 2);
+    policy.fill(0, 0);
+    policy.fill(0, 1);
+    policy.touch(0, 0);
+    assert_eq!(1, policy.victim(0));
+}
+
+#[test]
+fn random_replacement_is_repeatable() {
+    let trace = [0, 2048, 4096, 0, 6144, 2048, 8192, 4096, 0];
+
+    let mut first = new_mem_with(Replacement::Random { seed: 42 });
+    let mut second = new_mem_with(Replacement::Random { seed: 42 });
+    for addr in trace {
+        read_until_done(&mut first, addr);
+        read_until_done(&mut second, addr);
+    }
+    assert_eq!(first.view_stats(), second.view_stats());
+}
+
+fn new_mem_with_writes(write_policy: WritePolicy, allocate_policy: AllocatePolicy) -> Box<Cache> {
+    let ram = Box::new(RAM::new(4096, 16, 4, 5));
+    Box::new(Cache::new(2048, 16, 4, 1, 2, ram).with_write_policy(write_policy, allocate_policy))
+}
+
+#[test]
+fn write_back_defers_lower_level() {
+    let mut mem = new_mem_with_writes(WritePolicy::WriteBack, AllocatePolicy::WriteAllocate);
+
+    write_until_done(&mut mem, 68, 7);
+    assert_eq!(0, mem.view_line(1)[0][1]);
+    assert_eq!(7, mem.view_line(2)[1][1]);
+}
+
+#[test]
+fn write_through_updates_lower_level() {
+    let mut mem = new_mem_with_writes(WritePolicy::WriteThrough, AllocatePolicy::WriteAllocate);
+
+    write_until_done(&mut mem, 68, 7);
+    assert_eq!(7, mem.view_line(1)[0][1]);
+    assert_eq!(7, read_until_done(&mut mem, 68));
+    assert_eq!(1, mem.view_stats()[1].read_hits);
+    assert_eq!(1, mem.view_stats()[1].write_misses);
+}
+
+#[test]
+fn write_around_skips_the_cache() {
+    let mut mem = new_mem_with_writes(WritePolicy::WriteThrough, AllocatePolicy::NoWriteAllocate);
+
+    write_until_done(&mut mem, 68, 7);
+    assert_eq!(7, mem.view_line(1)[0][1]);
+    assert_eq!(0, mem.view_stats()[0].read_hits);
+
+    assert_eq!(7, read_until_done(&mut mem, 68));
+    assert_eq!(1, mem.view_stats()[1].read_misses);
+}
+
+#[test]
+fn write_buffer_hides_lower_level_latency() {
+    let ram = Box::new(RAM::new(4096, 16, 4, 5));
+    let buffer = Box::new(WriteBuffer::new(2, 16, 4, ram));
+    let mut mem = Box::new(Cache::new(2048, 16, 4, 1, 2, buffer)
+        .with_write_policy(WritePolicy::WriteThrough, AllocatePolicy::NoWriteAllocate));
+
+    assert!(mem.write(68, &MemoryValue::Value(7), StageType::Memory));
+    assert_eq!(0, mem.view_line(1)[0][1]);
+
+    for _ in 0..5 {
+        mem.tick();
+    }
+    assert_eq!(7, mem.view_line(1)[0][1]);
+}
+
+#[test]
+fn write_buffer_orders_reads_after_writes() {
+    let ram = Box::new(RAM::new(4096, 16, 4, 5));
+    let buffer = Box::new(WriteBuffer::new(2, 16, 4, ram));
+    let mut mem = Box::new(Cache::new(2048, 16, 4, 1, 2, buffer)
+        .with_write_policy(WritePolicy::WriteThrough, AllocatePolicy::NoWriteAllocate));
+
+    write_until_done(&mut mem, 68, 7);
+    assert_eq!(7, read_until_done(&mut mem, 68));
+}
+
+#[test]
+fn split_caches_access_in_same_cycle() {
+    let ram = SharedMemory::new(Box::new(RAM::new(4096, 16, 4, 5)));
+    let mut l1i = Cache::new(2048, 16, 4, 1, 2, Box::new(ram.handle()));
+    let mut l1d = Cache::new(2048, 16, 4, 1, 2, Box::new(ram));
+
+    while l1i.read(0, StageType::Fetch, false).is_none() {}
+    while l1d.read(64, StageType::Memory, false).is_none() {}
+
+    assert!(l1i.read(0, StageType::Fetch, false).is_some());
+    assert!(l1d.read(64, StageType::Memory, false).is_some());
+}
+
+#[test]
+fn split_caches_share_lower_level() {
+    let ram = SharedMemory::new(Box::new(RAM::new(4096, 16, 4, 5)));
+    let mut l1i = Cache::new(2048, 16, 4, 1, 2, Box::new(ram.handle()));
+    let mut l1d = Cache::new(2048, 16, 4, 1, 2, Box::new(ram));
+
+    while l1d.read(64, StageType::Memory, false).is_none() {}
+    assert_eq!(1, l1i.view_stats()[0].read_hits);
+
+    // Both miss at once, the data side has to wait for the shared RAM
+    let mut cycles = 0;
+    let (mut fetched, mut loaded) = (false, false);
+    while !(fetched && loaded) {
+        fetched = fetched || l1i.read(128, StageType::Fetch, false).is_some();
+        loaded = loaded || l1d.read(192, StageType::Memory, false).is_some();
+        cycles += 1;
+    }
+    assert!(cycles > 6);
+}
+
+#[test]
+fn writes_from_outside_drop_stale_instruction_lines() {
+    let fetched = |sim: &simulator::Simulator| sim.view_cache_set("L1I", 0).unwrap().iter().any(|(tag, _)| tag.valid);
+    let mut sim = simulator::Simulator::new();
+    sim.flash(0, &[0x6200_0000]);
+    sim.step_n(10);
+    assert!(fetched(&sim));
+    sim.poke(4, 7);
+    assert!(!fetched(&sim));
+    assert_eq!(7, sim.peek(4));
+
+    sim.reset();
+    sim.flash(0, &[0x6200_0000]);
+    sim.step_n(10);
+    sim.flash(0, &[0x6200_0000, 8]);
+    assert!(!fetched(&sim));
+}
+
+fn new_two_level(l1_size: usize, l1_ways: usize, inclusion: Inclusion) -> Box<Cache> {
+    let ram = Box::new(RAM::new(4096, 16, 4, 5));
+    let l2 = Box::new(Cache::new(256, 16, 4, 2, 1, ram).with_inclusion(inclusion));
+    Box::new(Cache::new(l1_size, 16, 4, 1, l1_ways, l2))
+}
+
+#[test]
+fn inclusive_cache_back_invalidates() {
+    let mut mem = new_two_level(128, 2, Inclusion::Inclusive);
+
+    // Both blocks fit in the L1 set, but fight over the same line in L2
+    read_until_done(&mut mem, 0);
+    read_until_done(&mut mem, 256);
+    assert_eq!(1, mem.view_stats()[2].back_invalidations);
+
+    read_until_done(&mut mem, 0);
+    assert_eq!(3, mem.view_stats()[2].read_misses);
+}
+
+#[test]
+fn inclusive_cache_keeps_dirty_data() {
+    let mut mem = new_two_level(128, 2, Inclusion::Inclusive);
+
+    read_until_done(&mut mem, 0);
+    write_until_done(&mut mem, 4, 5);
+    read_until_done(&mut mem, 256);
+    assert_eq!(5, mem.view_line(0)[0][1]);
+    assert_eq!(5, read_until_done(&mut mem, 4));
+}
+
+#[test]
+fn exclusive_cache_holds_victims() {
+    let mut mem = new_two_level(64, 1, Inclusion::Exclusive);
+
+    read_until_done(&mut mem, 0);
+    read_until_done(&mut mem, 64);
+    read_until_done(&mut mem, 0);
+
+    let l2 = mem.view_stats()[1];
+    assert_eq!(2, l2.read_misses);
+    assert_eq!(1, l2.read_hits);
+    assert_eq!(2, l2.write_misses);
+}
+
+#[test]
+fn hierarchy_builds_every_level() {
+    let config = HierarchyConfig {
+        instruction_cache: None,
+        data_cache: Some(CacheConfig::new("L1D", 1024, 2, 1)),
+        shared_caches: vec![CacheConfig::new("L2", 4096, 4, 4), CacheConfig::new("L3", 16384, 8, 8)],
+        ..HierarchyConfig::default()
+    };
+    let hierarchy = config.build();
+
+    let data = hierarchy.data.lock().unwrap();
+    assert_eq!(vec!["RAM", "L3", "L2", "L1D"], data.view_names());
+    assert_eq!(4, data.view_stats().len());
+    assert_eq!(vec!["RAM", "L3", "L2"], hierarchy.instruction.lock().unwrap().view_names());
+}
+
+#[test]
+fn levels_are_listed_once_each_even_with_the_same_name() {
+    let config = HierarchyConfig {
+        instruction_cache: Some(CacheConfig::new("L1", 1024, 2, 1)),
+        data_cache: Some(CacheConfig::new("L1", 1024, 2, 1)),
+        shared_caches: vec![CacheConfig::new("L2", 4096, 4, 4)],
+        ..HierarchyConfig::default()
+    };
+    let names: Vec<String> = config.build().view_levels().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(vec!["RAM", "L2", "L1", "L1"], names);
+
+    let config = HierarchyConfig { instruction_cache: None, ..config };
+    let names: Vec<String> = config.build().view_levels().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(vec!["RAM", "L2", "L1"], names);
+}
+
+#[test]
+fn abandoned_miss_does_not_pick_the_next_victim() {
+    let mut mem = new_mem();
+    // A miss that's given up on part way, like a fetch down a squashed path
+    assert!(mem.read(0, StageType::Memory, false).is_none());
+    read_until_done(&mut mem, 0x40);
+
+    // Block 0x40 belongs in set 1, not in the line picked for block 0
+    let valid = |line: usize| mem.view_tags(line)[1].is_some_and(|x| x.valid);
+    assert!(!valid(0) && !valid(1));
+    assert!([2, 3].iter().any(|&line| mem.view_tags(line)[1].is_some_and(|x| x.valid && x.block == 0x40)));
+}
+
+#[test]
+fn cache_sets_can_be_viewed_with_their_tags() {
+    let mut mem = new_mem();
+    write_until_done(&mut mem, 0x404, 7);
+    read_until_done(&mut mem, 0x4);
+
+    // 2KB, 2 way, 16 word blocks, so set 0 holds both blocks
+    let tags: Vec<_> = [0, 1].iter().map(|line| mem.view_tags(*line)[1].unwrap()).collect();
+    assert_eq!(None, mem.view_tags(0)[0]);
+    assert!(tags.iter().all(|x| x.valid));
+    assert_eq!(1, tags.iter().filter(|x| x.dirty).count());
+    let dirty = tags.iter().position(|x| x.dirty).unwrap();
+    assert_eq!(0x400, tags[dirty].block);
+    assert_eq!(7, mem.view_line(dirty)[1][1]);
+
+    let mut sim = simulator::Simulator::new();
+    sim.flash(0, &[0x6200_0000]);
+    sim.step_n(10);
+    let set = sim.view_cache_set("L1I", 0).unwrap();
+    assert_eq!(2, set.len());
+    assert!(set[0].0.valid && !set[0].0.dirty);
+    assert_eq!(0x6200_0000, set[0].1[0]);
+    assert!(sim.view_cache_set("L1D", 512).is_none());
+    assert!(sim.view_cache_set("L2", 0).is_none());
+}
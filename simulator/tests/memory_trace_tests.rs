@@ -0,0 +1,124 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::memory::{CacheConfig, HierarchyConfig, StageType};
+use simulator::memory::trace::{read_trace, replay, AccessKind, TraceFormat, TraceRecord};
+use simulator::run::RunLimits;
+
+// Walks an array a block at a time, twice
+const WALK: &str = "MOV R3, 0
+MOV R1, 0
+MOV R2, 256
+LDR R4, R2
+ADD R4, 1
+STR R4, R2
+ADD R2, 16
+ADD R1, 1
+CMP R1, 64
+BNE 12
+ADD R3, 1
+CMP R3, 2
+BNE 4
+HLT";
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+fn traced(config: &HierarchyConfig, format: TraceFormat) -> (Simulator, String) {
+    let mut sim = Simulator::from_hierarchy(config);
+    sim.flash(0, &assemble(WALK));
+    let trace = Buffer::default();
+    sim.trace_memory(trace.clone(), format);
+    sim.run(&RunLimits::default());
+    sim.finish_memory_trace().unwrap();
+    (sim, trace.text())
+}
+
+#[test]
+fn every_completed_access_is_traced() {
+    let (_, text) = traced(&HierarchyConfig::default(), TraceFormat::Native);
+    let records = read_trace(text.as_bytes(), TraceFormat::Native, 4).unwrap();
+
+    let loads = records.iter().filter(|x| x.stage == StageType::Memory && x.kind == AccessKind::Read).count();
+    let stores = records.iter().filter(|x| x.kind == AccessKind::Write).count();
+    assert_eq!((loads, stores), (128, 128));
+    assert!(records.iter().filter(|x| x.stage == StageType::Fetch).all(|x| x.kind == AccessKind::Read));
+    assert!(records.windows(2).all(|x| x[0].cycle <= x[1].cycle));
+
+    let first_store = records.iter().find(|x| x.kind == AccessKind::Write).unwrap();
+    assert_eq!((first_store.addr, first_store.size), (256, 4));
+    assert_eq!(first_store.to_line(TraceFormat::Native), format!("{} M W 100 4", first_store.cycle));
+}
+
+#[test]
+fn replaying_a_trace_gives_the_same_cache_statistics() {
+    let mut config = HierarchyConfig::default();
+    config.shared_caches.push(CacheConfig::new("L2", 4096, 4, 4));
+    let (sim, text) = traced(&config, TraceFormat::Native);
+    let records = read_trace(text.as_bytes(), TraceFormat::Native, 4).unwrap();
+
+    let result = replay(&records, &config);
+    let memory = sim.memory.lock().unwrap();
+    assert_eq!(result.accesses as usize, records.len());
+    assert_eq!(result.level("L1D"), memory.view_stats().last());
+    assert_eq!(result.level("L2"), memory.view_stats().get(1));
+    assert_eq!(result.levels.last().unwrap().1, *sim.instruction_memory.lock().unwrap().view_stats().last().unwrap());
+}
+
+#[test]
+fn any_cache_can_be_swept_over_one_trace() {
+    let (_, text) = traced(&HierarchyConfig::default(), TraceFormat::Din);
+    let records = read_trace(text.as_bytes(), TraceFormat::Din, 4).unwrap();
+
+    let mut misses = vec![];
+    for size in [256, 512, 1024, 2048] {
+        let config = HierarchyConfig {
+            data_cache: Some(CacheConfig::new("L1D", size, 1, 1)),
+            ..HierarchyConfig::default()
+        };
+        misses.push(replay(&records, &config).level("L1D").unwrap().misses());
+    }
+    // The array is 1KB, so it only fits from 1KB up and the second pass hits
+    assert!(misses[0] > misses[2], "{:?}", misses);
+    assert_eq!(misses[2], misses[3]);
+}
+
+#[test]
+fn din_traces_are_read_like_dinero_does() {
+    let din = "# from another simulator
+2 0
+0 1f0 extra fields
+1 0x200
+
+2 4";
+    let records = read_trace(din.as_bytes(), TraceFormat::Din, 8).unwrap();
+
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[0], TraceRecord { cycle: 0, addr: 0, size: 8, kind: AccessKind::Read, stage: StageType::Fetch });
+    assert_eq!((records[1].addr, records[1].kind, records[1].stage), (0x1f0, AccessKind::Read, StageType::Memory));
+    assert_eq!((records[2].addr, records[2].kind), (0x200, AccessKind::Write));
+    assert_eq!(records[3].cycle, 3);
+
+    let error = read_trace("2 0\n4 0".as_bytes(), TraceFormat::Din, 4).unwrap_err();
+    assert_eq!(error.to_string(), "line 2: unsupported label 4");
+    let error = read_trace("12 X R 0 4".as_bytes(), TraceFormat::Native, 4).unwrap_err();
+    assert_eq!(error.to_string(), "line 1: bad stage X");
+}
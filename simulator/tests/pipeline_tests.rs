@@ -0,0 +1,126 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::config::MachineConfig;
+use simulator::processor::PipelineConfig;
+use simulator::processor::predictor::PredictorKind;
+
+fn run_program(pipeline: PipelineConfig, program: &str) -> Simulator {
+    let config = MachineConfig { pipeline, ..MachineConfig::default() };
+    let mut sim = Simulator::from_config(&config).unwrap();
+    sim.flash(0, &assemble(program));
+    sim.step_n(10_000);
+    assert!(sim.processor.is_halted(), "program never halted");
+    sim
+}
+
+const DEPENDENT_CHAIN: &str = "MOV R1, 5
+ADD R1, R1
+ADD R1, 3
+MOV R2, 64
+STR R1, R2
+LDR R3, R2
+ADD R3, R1
+HLT";
+
+const COUNTDOWN_LOOP: &str = "MOV R1, 5
+SUB R1, 1
+CMP R1, 0
+BNE 4
+HLT";
+
+#[test]
+fn taken_branches_squash_what_was_fetched_after_them() {
+    let sim = run_program(PipelineConfig::default(), COUNTDOWN_LOOP);
+
+    assert_eq!(sim.processor.view_registers()[1], 0);
+    assert_eq!(sim.processor.view_cycles(), 63);
+}
+
+#[test]
+fn forwarding_keeps_results_and_saves_cycles() {
+    let stalled = run_program(PipelineConfig::default(), DEPENDENT_CHAIN);
+    let forwarded = run_program(PipelineConfig { forwarding: true, ..PipelineConfig::default() }, DEPENDENT_CHAIN);
+
+    assert_eq!(stalled.processor.view_registers()[1], 13);
+    assert_eq!(stalled.processor.view_registers()[3], 26);
+    assert_eq!(forwarded.processor.view_registers(), stalled.processor.view_registers());
+    assert!(forwarded.processor.view_cycles() < stalled.processor.view_cycles());
+}
+
+#[test]
+fn not_taken_predictor_misses_every_taken_branch() {
+    let sim = run_program(PipelineConfig::default(), COUNTDOWN_LOOP);
+
+    assert_eq!(sim.processor.view_registers()[1], 0);
+    let stats = sim.processor.view_predictor_stats();
+    assert_eq!(stats.predictions, 5);
+    assert_eq!(stats.mispredictions, 4);
+}
+
+#[test]
+fn bimodal_predictor_learns_loop_branch() {
+    let not_taken = run_program(PipelineConfig::default(), COUNTDOWN_LOOP);
+    let bimodal = run_program(PipelineConfig { predictor: PredictorKind::Bimodal, ..PipelineConfig::default() }, COUNTDOWN_LOOP);
+
+    assert_eq!(bimodal.processor.view_registers()[1], 0);
+    let stats = bimodal.processor.view_predictor_stats();
+    assert_eq!(stats.predictions, 5);
+    assert_eq!(stats.mispredictions, 2);
+    assert!(bimodal.processor.view_cycles() < not_taken.processor.view_cycles());
+}
+
+#[test]
+fn always_taken_predictor_follows_seen_branches() {
+    let sim = run_program(PipelineConfig { predictor: PredictorKind::AlwaysTaken, ..PipelineConfig::default() }, COUNTDOWN_LOOP);
+
+    assert_eq!(sim.processor.view_registers()[1], 0);
+    assert_eq!(sim.processor.view_predictor_stats().mispredictions, 2);
+}
+
+#[test]
+fn forwarding_and_prediction_together() {
+    let pipeline = PipelineConfig { predictor: PredictorKind::AlwaysTaken, forwarding: true, ..PipelineConfig::default() };
+    let sim = run_program(pipeline, COUNTDOWN_LOOP);
+
+    assert_eq!(sim.processor.view_registers()[1], 0);
+    assert_eq!(sim.processor.view_predictor_stats().mispredictions, 2);
+}
+
+#[test]
+fn default_pipeline_keeps_original_timing() {
+    let sim = run_program(PipelineConfig::default(), "MOV R1, 5
+MOV R2, 64
+STR R1, R2
+LDR R3, R2
+ADD R3, R3
+HLT");
+
+    assert_eq!(sim.processor.view_registers()[3], 10);
+    assert_eq!(sim.processor.view_cycles(), 18);
+}
+
+#[test]
+fn step_n_stops_when_halted() {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble(DEPENDENT_CHAIN));
+
+    assert_eq!(sim.step_n(3), 3);
+    let ran = sim.step_n(1000);
+    assert!(ran < 1000);
+    assert_eq!(sim.processor.view_cycles(), ran as u128 + 3);
+    assert!(!sim.step());
+    assert_eq!(sim.processor.view_cycles(), ran as u128 + 3);
+}
+
+#[test]
+fn run_until_stops_on_predicate() {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble(DEPENDENT_CHAIN));
+
+    assert!(sim.run_until(|sim| sim.processor.view_registers()[1] == 13));
+    assert!(!sim.processor.is_halted());
+    assert_eq!(sim.processor.view_registers()[3], 0);
+
+    assert!(!sim.run_until(|_| false));
+    assert_eq!(sim.processor.view_registers()[3], 26);
+}
@@ -0,0 +1,102 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::config::MachineConfig;
+use simulator::processor::instruction::{ALUType, InstrType, Instruction};
+use simulator::processor::pipeline::{StageContext, StageResult, StageType};
+use simulator::processor::predictor::PredictorKind;
+use simulator::processor::registers::Register;
+use simulator::processor::stages;
+use simulator::reference::{Lockstep, Mismatch, Reference};
+use simulator::run::StopReason;
+
+// Sums 1 to 10 into memory and reads it back
+const SUM: &str = "MOV R1, 0
+MOV R2, 10
+MOV R3, 128
+ADD R1, R2
+SUB R2, 1
+CMP R2, 0
+BNE 12
+STR R1, R3
+LDR R4, R3
+HLT";
+
+#[test]
+fn reference_runs_programs_on_its_own() {
+    let mut reference = Reference::new(&MachineConfig::default());
+    reference.flash(0, &assemble(SUM));
+
+    assert_eq!(reference.run(1000), 46);
+    assert!(reference.is_halted());
+    assert_eq!(reference.registers()[4], 55);
+    assert_eq!(reference.peek(128), 55);
+    assert_eq!(reference.step(), None);
+}
+
+#[test]
+fn pipeline_matches_the_reference() {
+    let mut forwarding = MachineConfig::default();
+    forwarding.pipeline.forwarding = true;
+    forwarding.pipeline.predictor = PredictorKind::Bimodal;
+    let mut unified = MachineConfig::default();
+    unified.memory.instruction_cache = None;
+    unified.memory.data_cache = None;
+    unified.pipeline.predictor = PredictorKind::AlwaysTaken;
+
+    for config in [MachineConfig::default(), forwarding, unified] {
+        let mut sim = Simulator::from_config(&config).unwrap();
+        sim.flash(0, &assemble(SUM));
+        let mut lockstep = Lockstep::new(&sim);
+
+        lockstep.run(&mut sim, 10_000).unwrap();
+        assert_eq!(sim.stop_reason(), Some(StopReason::Halted));
+        assert_eq!(lockstep.reference().retired(), sim.processor.view_retired());
+    }
+}
+
+// Gets every ADD with a register operand wrong by one
+fn broken_execute(ctx: &StageContext, instr: &mut Instruction) -> StageResult {
+    let result = stages::execute(ctx, instr);
+    if let InstrType::ALU(ALUType::ADD) = instr.instr_type {
+        if instr.reg_2 == Register::R2 {
+            instr.meta.result += 1;
+        }
+    }
+    result
+}
+
+#[test]
+fn lockstep_reports_the_first_divergence() {
+    let mut sim = Simulator::builder()
+        .with_stages(vec![
+            (StageType::Fetch, stages::fetch),
+            (StageType::Decode, stages::decode),
+            (StageType::Execute, broken_execute),
+            (StageType::Memory, stages::memory),
+            (StageType::Writeback, stages::writeback),
+        ])
+        .with_program(0, &assemble(SUM))
+        .build()
+        .unwrap();
+    let mut lockstep = Lockstep::new(&sim);
+
+    let divergence = lockstep.run(&mut sim, 10_000).unwrap_err();
+    assert_eq!(divergence.pc, 12);
+    assert_eq!(divergence.retired, 4);
+    assert_eq!(divergence.mismatch, Mismatch::Register { reg: Register::R1, expected: 10, actual: 11 });
+    assert!(divergence.to_string().contains("R1 is 11 instead of 10"));
+}
+
+#[test]
+fn faults_have_to_match() {
+    let mut sim = Simulator::new();
+    sim.flash(0, &assemble("MOV R1, 7
+MOV R2, 0
+IDIV R1, R2
+HLT"));
+    let mut lockstep = Lockstep::new(&sim);
+
+    lockstep.run(&mut sim, 1000).unwrap();
+    assert!(matches!(sim.stop_reason(), Some(StopReason::Fault(_))));
+    assert_eq!(lockstep.reference().fault(), Some("division by zero at 0x8"));
+}
@@ -0,0 +1,111 @@
+use std::thread;
+use std::time::Duration;
+
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::run::{CancelHandle, RunLimits, StopReason};
+
+const INFINITE_LOOP: &str = "MOV R1, 1
+ADD R2, R1
+B 4";
+
+fn loaded(program: &[u32]) -> Simulator {
+    let mut sim = Simulator::new();
+    sim.flash(0, program);
+    sim
+}
+
+#[test]
+fn run_reports_halt() {
+    let mut sim = loaded(&assemble("MOV R1, 5
+ADD R1, R1
+HLT"));
+    let result = sim.run(&RunLimits::default());
+
+    assert_eq!(result.reason, StopReason::Halted);
+    assert_eq!(result.instructions, 3);
+    assert_eq!(result.cycles as u128, sim.processor.view_cycles());
+
+    let again = sim.run(&RunLimits::default());
+    assert_eq!(again.reason, StopReason::Halted);
+    assert_eq!(again.cycles, 0);
+}
+
+#[test]
+fn run_stops_at_cycle_limit() {
+    let mut sim = loaded(&assemble(INFINITE_LOOP));
+    let result = sim.run(&RunLimits::default().with_max_cycles(500));
+
+    assert_eq!(result.reason, StopReason::CycleLimit);
+    assert_eq!(result.cycles, 500);
+    assert_eq!(sim.processor.view_cycles(), 500);
+
+    // Limits apply to each run, not the whole life of the simulator
+    assert_eq!(sim.run(&RunLimits::default().with_max_cycles(500)).cycles, 500);
+}
+
+#[test]
+fn run_stops_at_instruction_limit() {
+    let mut sim = loaded(&assemble(INFINITE_LOOP));
+    let result = sim.run(&RunLimits::default().with_max_instructions(40));
+
+    assert_eq!(result.reason, StopReason::InstructionLimit);
+    assert_eq!(result.instructions, 40);
+    assert_eq!(sim.processor.view_retired(), 40);
+}
+
+#[test]
+fn run_stops_at_timeout() {
+    let mut sim = loaded(&assemble(INFINITE_LOOP));
+    let result = sim.run(&RunLimits::default().with_timeout(Duration::from_millis(20)));
+
+    assert_eq!(result.reason, StopReason::Timeout);
+    assert!(result.cycles > 0);
+}
+
+#[test]
+fn run_can_be_cancelled_from_another_thread() {
+    let mut sim = loaded(&assemble(INFINITE_LOOP));
+    let cancel = CancelHandle::new();
+    let remote = cancel.clone();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        remote.cancel();
+    });
+
+    let result = sim.run(&RunLimits::default().with_cancel(cancel));
+    canceller.join().unwrap();
+    assert_eq!(result.reason, StopReason::Cancelled);
+}
+
+#[test]
+fn illegal_instruction_faults() {
+    let mut program = assemble("MOV R1, 5");
+    program.push(0xFFFF_FFFF);
+    let mut sim = loaded(&program);
+    let result = sim.run(&RunLimits::default());
+
+    assert_eq!(result.reason, StopReason::Fault(String::from("illegal instruction 0xffffffff at 0x4")));
+    assert_eq!(result.instructions, 1);
+    assert!(!sim.step());
+}
+
+#[test]
+fn division_by_zero_faults() {
+    let mut sim = loaded(&assemble("MOV R1, 0
+MOV R2, 7
+IDIV R2, R1
+HLT"));
+
+    assert_eq!(sim.run(&RunLimits::default()).reason, StopReason::Fault(String::from("division by zero at 0x8")));
+}
+
+#[test]
+fn squashed_instructions_never_fault() {
+    let mut program = assemble("B 8");
+    program.push(0xFFFF_FFFF);
+    program.extend(assemble("HLT"));
+    let mut sim = loaded(&program);
+
+    assert_eq!(sim.run(&RunLimits::default()).reason, StopReason::Halted);
+}
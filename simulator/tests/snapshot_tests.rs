@@ -0,0 +1,96 @@
+use simulator::Simulator;
+use simulator::assembler::assemble;
+use simulator::config::MachineConfig;
+use simulator::memory::{CacheConfig, Replacement, WritePolicy, AllocatePolicy};
+use simulator::processor::predictor::PredictorKind;
+use simulator::run::{RunLimits, StopReason};
+use simulator::snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION};
+
+const LOOP: &str = "MOV R1, 0
+MOV R2, 64
+ADD R1, 1
+STR R1, R2
+ADD R2, 512
+CMP R1, 40
+BNE 8
+HLT";
+
+fn machine() -> MachineConfig {
+    let mut config = MachineConfig::default();
+    let data = config.memory.data_cache.as_mut().unwrap();
+    data.size = 1024;
+    data.replacement = Replacement::Lru;
+    data.write_policy = WritePolicy::WriteThrough;
+    data.allocate_policy = AllocatePolicy::NoWriteAllocate;
+    data.write_buffer = Some(2);
+    let mut l2 = CacheConfig::new("L2", 4096, 4, 4);
+    l2.replacement = Replacement::Random { seed: 3 };
+    config.memory.shared_caches.push(l2);
+    config.pipeline.predictor = PredictorKind::Bimodal;
+    config.pipeline.forwarding = true;
+    config
+}
+
+fn loaded() -> Simulator {
+    let mut sim = Simulator::from_config(&machine()).unwrap();
+    sim.flash(0, &assemble(LOOP));
+    sim
+}
+
+#[test]
+fn snapshot_round_trips_mid_run() {
+    let mut sim = loaded();
+    sim.step_n(173);
+
+    let json = sim.snapshot().to_json();
+    let mut restored = Simulator::from_snapshot(&Snapshot::from_json(&json).unwrap()).unwrap();
+    assert_eq!(restored.snapshot().to_json(), json);
+
+    // Both carry on identically, right down to the replacement state and counters
+    assert_eq!(sim.run(&RunLimits::default()).reason, StopReason::Halted);
+    assert_eq!(restored.run(&RunLimits::default()).reason, StopReason::Halted);
+    assert_eq!(restored.snapshot().to_json(), sim.snapshot().to_json());
+    assert_eq!(restored.processor.view_registers()[1], 40);
+    assert_eq!(restored.processor.view_predictor_stats(), sim.processor.view_predictor_stats());
+}
+
+#[test]
+fn snapshot_saves_to_a_file() {
+    let mut sim = loaded();
+    sim.step_n(50);
+    let path = std::env::temp_dir().join(format!("ironleg-snapshot-{}.json", std::process::id()));
+
+    sim.snapshot().save(&path).unwrap();
+    let mut other = loaded();
+    other.load_snapshot(&Snapshot::load(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(other.processor.view_cycles(), 50);
+    assert_eq!(other.snapshot().to_json(), sim.snapshot().to_json());
+}
+
+#[test]
+fn snapshots_from_other_versions_are_refused() {
+    let mut value: serde_json::Value = serde_json::from_str(&loaded().snapshot().to_json()).unwrap();
+    value["version"] = serde_json::json!(SNAPSHOT_VERSION + 1);
+
+    assert!(matches!(Snapshot::from_json(&value.to_string()), Err(SnapshotError::Version(v)) if v == SNAPSHOT_VERSION + 1));
+    assert!(matches!(Snapshot::from_json("{\"state\": 1}"), Err(SnapshotError::Parse(_))));
+}
+
+#[test]
+fn snapshots_only_load_into_the_same_machine() {
+    let snapshot = loaded().snapshot();
+
+    let mut default = Simulator::new();
+    assert!(matches!(default.load_snapshot(&snapshot), Err(SnapshotError::Mismatch(_))));
+
+    let mut unified = MachineConfig::default();
+    unified.memory.instruction_cache = None;
+    unified.memory.data_cache = None;
+    let mut snapshot = Simulator::from_config(&unified).unwrap().snapshot();
+
+    // Even with the configuration doctored to match, the levels themselves don't
+    snapshot.config = MachineConfig::default();
+    assert!(matches!(default.load_snapshot(&snapshot), Err(SnapshotError::Mismatch(_))));
+}